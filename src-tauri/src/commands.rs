@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
 use std::io::Write;
 use std::sync::Arc;
@@ -20,20 +20,12 @@ struct MediaCacheEntry {
 }
 
 /// Cache state stored in Tauri Store
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct MediaCacheState {
     entries: HashMap<String, MediaCacheEntry>,
     total_size: u64,
 }
 
-impl Default for MediaCacheState {
-    fn default() -> Self {
-        Self {
-            entries: HashMap::new(),
-            total_size: 0,
-        }
-    }
-}
 
 /// Get the cache directory path
 fn get_cache_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
@@ -50,8 +42,25 @@ fn get_cache_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
     Ok(cache_dir)
 }
 
+/// Path a version of a media file is cached under
+fn cached_file_path(cache_dir: &Path, media_id: &str, updated_at: &str, ext: &str) -> PathBuf {
+    // Generate safe filename - only alphanumeric, dash, underscore, dot
+    let safe_id = media_id.replace(|c: char| !c.is_alphanumeric() && c != '-' && c != '.', "_");
+    // Sanitize timestamp: replace special chars with safe alternatives
+    let safe_timestamp = updated_at
+        .replace(':', "-")
+        .replace('+', "_")
+        .replace('.', "-");
+    cache_dir.join(format!("{}_{}.{}", safe_id, safe_timestamp, ext))
+}
+
 /// Load cache state from Tauri Store
 async fn load_cache_state(app_handle: &AppHandle) -> Result<MediaCacheState, String> {
+    read_cache_state(app_handle)
+}
+
+/// Read the cache state without awaiting (the store is kept in memory)
+fn read_cache_state(app_handle: &AppHandle) -> Result<MediaCacheState, String> {
     use tauri_plugin_store::StoreExt;
 
     let store = app_handle.store("media_cache.json")
@@ -147,14 +156,7 @@ pub async fn cache_media(
         }
     };
 
-    // Generate safe filename - only alphanumeric, dash, underscore, dot
-    let safe_id = media_id.replace(|c: char| !c.is_alphanumeric() && c != '-' && c != '.', "_");
-    // Sanitize timestamp: replace special chars with safe alternatives
-    let safe_timestamp = updated_at
-        .replace(':', "-")
-        .replace('+', "_")
-        .replace('.', "-");
-    let file_path = cache_dir.join(format!("{}_{}.{}", safe_id, safe_timestamp, ext));
+    let file_path = cached_file_path(&cache_dir, &media_id, &updated_at, ext);
 
     tracing::info!("Caching media: {} with extension: {} -> {}", media_id, ext, file_path.display());
 
@@ -224,14 +226,7 @@ pub async fn cache_media_from_buffer(
         }.to_string()
     };
 
-    // Generate safe filename - only alphanumeric, dash, underscore, dot
-    let safe_id = media_id.replace(|c: char| !c.is_alphanumeric() && c != '-' && c != '.', "_");
-    // Sanitize timestamp: replace special chars with safe alternatives
-    let safe_timestamp = updated_at
-        .replace(':', "-")
-        .replace('+', "_")
-        .replace('.', "-");
-    let file_path = cache_dir.join(format!("{}_{}.{}", safe_id, safe_timestamp, ext));
+    let file_path = cached_file_path(&cache_dir, &media_id, &updated_at, &ext);
 
     tracing::info!("Caching media from buffer: {} with extension: {} -> {}", media_id, ext, file_path.display());
 
@@ -274,6 +269,51 @@ pub async fn cache_media_from_buffer(
     Ok(file_path.to_string_lossy().to_string())
 }
 
+/// Move a file received over the WebSocket into the media cache
+async fn cache_received_media(app_handle: &AppHandle, media: &ReceivedMedia) -> Result<String, String> {
+    let cache_dir = get_cache_dir(app_handle)?;
+    let file_path = cached_file_path(&cache_dir, &media.media_id, &media.updated_at, &media.extension);
+
+    tracing::info!("Caching received media: {} -> {}", media.media_id, file_path.display());
+
+    let mut state = load_cache_state(app_handle).await?;
+
+    if let Some(existing) = state.entries.get(&media.media_id) {
+        if existing.updated_at >= media.updated_at && existing.file_path == file_path.to_string_lossy() {
+            tracing::info!("Media already cached with current or newer version: {}", media.media_id);
+            let _ = fs::remove_file(&media.path);
+            return Ok(existing.file_path.clone());
+        }
+        // Remove old version
+        let _ = fs::remove_file(&existing.file_path);
+        state.total_size = state.total_size.saturating_sub(existing.size);
+        state.entries.remove(&media.media_id);
+    }
+
+    // The staging dir is normally on the same filesystem; copy if it is not
+    if fs::rename(&media.path, &file_path).is_err() {
+        fs::copy(&media.path, &file_path)
+            .map_err(|e| format!("Failed to copy received file: {}", e))?;
+        let _ = fs::remove_file(&media.path);
+    }
+
+    let now = chrono::Utc::now().to_rfc3339();
+    let entry = MediaCacheEntry {
+        file_path: file_path.to_string_lossy().to_string(),
+        updated_at: media.updated_at.clone(),
+        last_accessed: now,
+        size: media.size,
+    };
+
+    state.entries.insert(media.media_id.clone(), entry);
+    state.total_size += media.size;
+
+    // Evict if needed
+    let _ = evict_lru(app_handle, state, &cache_dir).await?;
+
+    Ok(file_path.to_string_lossy().to_string())
+}
+
 /// Get a cached media file path
 #[tauri::command]
 pub async fn get_cached_media(
@@ -375,19 +415,30 @@ pub async fn clear_media_cache(app_handle: AppHandle) -> Result<(), String> {
 #[tauri::command]
 pub async fn get_cache_stats(app_handle: AppHandle) -> Result<CacheStats, String> {
     let state = load_cache_state(&app_handle).await?;
+    Ok(cache_stats(&state))
+}
 
-    Ok(CacheStats {
+fn cache_stats(state: &MediaCacheState) -> CacheStats {
+    CacheStats {
         entry_count: state.entries.len(),
         total_size: state.total_size,
         max_size: MAX_CACHE_SIZE_MB * 1024 * 1024,
-    })
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CacheStats {
-    pub entry_count: usize,
-    pub total_size: u64,
-    pub max_size: u64,
+/// The media cache as served by the WebSocket server's HTTP endpoints
+struct AppMediaLibrary(AppHandle);
+
+impl MediaLibrary for AppMediaLibrary {
+    fn path(&self, media_id: &str) -> Option<PathBuf> {
+        let state = read_cache_state(&self.0).ok()?;
+        let path = PathBuf::from(&state.entries.get(media_id)?.file_path);
+        path.exists().then_some(path)
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        read_cache_state(&self.0).ok().as_ref().map(cache_stats)
+    }
 }
 
 /// Test command to emit an event to the frontend (for debugging event system)
@@ -812,26 +863,359 @@ pub fn start_auto_test(_app_handle: AppHandle, mode: crate::AutoStartMode) {
 // WebSocket Commands
 // ============================================================================
 
-use crate::websocket::{WebSocketServer, WsMessage, LyricsData, SlideData};
+use crate::websocket::{DeliveryReport, ServerConfig, WebSocketServer, WsMessage, LyricsData, SlideData, ServerEvent};
+use crate::websocket::client::{ClientConnections, ClientEvent, ClientTarget, WebSocketClient, CLIENT_CAPABILITIES};
+use crate::websocket::clients::{ClientInfo, RoomInfo};
+use crate::websocket::precache::ReadinessMatrix;
+use crate::websocket::http::{CacheStats, MediaLibrary};
+use crate::websocket::server::ServerStatus;
+use crate::websocket::journal::JournalInfo;
+use crate::websocket::transfer::ReceivedMedia;
+use crate::websocket::types::{
+    HelloData, LogoData, PrecacheData, PrecacheMediaItem, PrecacheSongItem, ScreenData, PROTOCOL_VERSION,
+};
+use crate::websocket::pairing::{Pairing, PairingCode, PairingSummary, DEFAULT_PIN_TTL};
+
+const PAIRING_STORE_NAME: &str = "websocket_pairings.json";
+const SERVER_CONFIG_STORE_NAME: &str = "websocket_server.json";
+
+/// Load paired controllers from Tauri Store
+fn load_pairings(app_handle: &AppHandle) -> Result<(Vec<Pairing>, bool), String> {
+    use tauri_plugin_store::StoreExt;
+
+    let store = app_handle.store(PAIRING_STORE_NAME)
+        .map_err(|e| format!("Failed to get store: {}", e))?;
+
+    let pairings: Vec<Pairing> = store
+        .get("pairings")
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default();
+
+    // Installs that never chose start out requiring pairing; controllers that
+    // connected without credentials before have to pair once
+    let require_pairing = store
+        .get("require_pairing")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true);
+
+    Ok((pairings, require_pairing))
+}
+
+/// Save paired controllers to Tauri Store
+fn save_pairings(app_handle: &AppHandle, pairings: &[Pairing]) -> Result<(), String> {
+    use tauri_plugin_store::StoreExt;
+
+    let store = app_handle.store(PAIRING_STORE_NAME)
+        .map_err(|e| format!("Failed to get store: {}", e))?;
+
+    let value = serde_json::to_value(pairings)
+        .map_err(|e| format!("Failed to serialize pairings: {}", e))?;
+    store.set("pairings", value);
+    store.save().map_err(|e| format!("Failed to save store: {}", e))?;
+
+    Ok(())
+}
+
+/// Load the WebSocket server's listening configuration from Tauri Store
+fn load_server_config(app_handle: &AppHandle) -> Result<ServerConfig, String> {
+    use tauri_plugin_store::StoreExt;
+
+    let store = app_handle.store(SERVER_CONFIG_STORE_NAME)
+        .map_err(|e| format!("Failed to get store: {}", e))?;
+
+    Ok(store
+        .get("config")
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default())
+}
+
+/// Save the WebSocket server's listening configuration to Tauri Store
+fn save_server_config(app_handle: &AppHandle, config: &ServerConfig) -> Result<(), String> {
+    use tauri_plugin_store::StoreExt;
+
+    let store = app_handle.store(SERVER_CONFIG_STORE_NAME)
+        .map_err(|e| format!("Failed to get store: {}", e))?;
+
+    let value = serde_json::to_value(config)
+        .map_err(|e| format!("Failed to serialize server config: {}", e))?;
+    store.set("config", value);
+    store.save().map_err(|e| format!("Failed to save store: {}", e))?;
+
+    Ok(())
+}
+
+/// Refresh advertisements when the host's addresses change
+///
+/// Called once from `setup`. On every change the displays' records are
+/// updated in place with the new addresses, the UDP listener is bound again
+/// if interfaces came or went, and "network-changed" is emitted with the change.
+pub fn watch_network(app_handle: AppHandle) {
+    use tokio::sync::broadcast::error::RecvError;
+
+    let watcher = app_handle.state::<Arc<crate::mdns::NetworkWatcher>>().inner().clone();
+    let mut changes = watcher.subscribe();
+    tauri::async_runtime::spawn(async move {
+        // Started here, inside the runtime its poller is spawned on
+        watcher.start(crate::mdns::POLL_INTERVAL);
+        loop {
+            let change = match changes.recv().await {
+                Ok(change) => change,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Network watcher forwarder skipped {} changes", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let advertiser = app_handle.state::<Arc<crate::mdns::AdvertiserState>>();
+            match advertiser.readvertise().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Re-advertised {} displays after network change", count),
+                Err(e) => tracing::error!("Failed to re-advertise after network change: {}", e),
+            }
+            if change.interfaces_changed {
+                let listener = app_handle.state::<Arc<crate::mdns::UdpListener>>();
+                listener.restart().await;
+            }
+
+            let _ = app_handle.emit("network-changed", change);
+        }
+    });
+}
+
+/// Forward changes seen by the background mDNS browser to the frontend
+///
+/// Called once from `setup`; the browser itself is started on demand.
+pub fn forward_browser_events(app_handle: AppHandle) {
+    use tokio::sync::broadcast::error::RecvError;
+
+    let mut events = app_handle.state::<Arc<crate::mdns::DiscoveryBrowser>>().subscribe();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(crate::mdns::BrowserEvent::Discovered(device)) => {
+                    let _ = app_handle.emit("display-discovered", device);
+                }
+                Ok(crate::mdns::BrowserEvent::Updated(device)) => {
+                    let _ = app_handle.emit("display-updated", device);
+                }
+                Ok(crate::mdns::BrowserEvent::Lost(device)) => {
+                    let _ = app_handle.emit("display-lost", device);
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Display browser forwarder skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Forward WebSocket server events to the frontend, persisting state as needed
+///
+/// Called once from `setup`; runs for the lifetime of the app.
+pub fn forward_websocket_events(app_handle: AppHandle) {
+    use tokio::sync::broadcast::error::RecvError;
+
+    tauri::async_runtime::spawn(async move {
+        let mut events = {
+            let ws_state = app_handle.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+            let server = ws_state.lock().await;
+            server.subscribe()
+        };
+
+        loop {
+            match events.recv().await {
+                Ok(ServerEvent::PairingsChanged(pairings)) => {
+                    if let Err(e) = save_pairings(&app_handle, &pairings) {
+                        tracing::error!("Failed to persist pairings: {}", e);
+                    }
+                    let summaries: Vec<PairingSummary> =
+                        pairings.iter().map(PairingSummary::from).collect();
+                    let _ = app_handle.emit("pairings-changed", summaries);
+                }
+                Ok(ServerEvent::DisplayLagging(lag)) => {
+                    let _ = app_handle.emit("display-lagging", lag);
+                }
+                Ok(ServerEvent::DisplayMissedUpdate(missed)) => {
+                    let _ = app_handle.emit("display-missed-update", missed);
+                }
+                Ok(ServerEvent::DisplayConnected(info)) => {
+                    let _ = app_handle.emit("display-connected", info);
+                }
+                Ok(ServerEvent::DisplayDisconnected(gone)) => {
+                    let _ = app_handle.emit("display-disconnected", gone);
+                }
+                Ok(ServerEvent::DisplayPrecacheReady(ready)) => {
+                    let _ = app_handle.emit("display-precache-ready", ready);
+                }
+                Ok(ServerEvent::MediaReceived(media)) => match cache_received_media(&app_handle, &media).await {
+                    Ok(file_path) => {
+                        let _ = app_handle.emit(
+                            "media-received",
+                            serde_json::json!({ "media_id": media.media_id, "file_path": file_path }),
+                        );
+                    }
+                    Err(e) => tracing::error!("Failed to cache received media {}: {}", media.media_id, e),
+                },
+                Ok(ServerEvent::MediaTransferStatus(status)) => {
+                    let _ = app_handle.emit("media-transfer-status", status);
+                }
+                Ok(ServerEvent::JournalReplayFinished(finished)) => {
+                    let _ = app_handle.emit("journal-replay-finished", finished);
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("WebSocket event forwarder skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Directory holding the WebSocket server's TLS certificate
+fn get_tls_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?
+        .join("tls"))
+}
+
+/// Staging directory for media files being received over the WebSocket
+/// (outside the media cache dir, which `clear_media_cache` empties file by file)
+fn get_transfer_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_handle
+        .path()
+        .app_cache_dir()
+        .map_err(|e| format!("Failed to get cache dir: {}", e))?
+        .join("media_transfers"))
+}
+
+/// Directory holding the per-event journals of published messages
+fn get_journal_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?
+        .join("journals"))
+}
 
 /// Start the WebSocket server
+/// If tls is Some(true), the server also accepts wss:// using this device's
+/// self-signed certificate (loopback clients may still use ws://)
 #[tauri::command]
-pub async fn start_websocket_server(app: tauri::AppHandle) -> Result<u16, String> {
-    tracing::info!("start_websocket_server called");
+pub async fn start_websocket_server(app: tauri::AppHandle, tls: Option<bool>) -> Result<u16, String> {
+    tracing::info!("start_websocket_server called (tls: {:?})", tls);
 
     let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
     let mut server = ws_state.lock().await;
 
-    let port = server.start(0).await?;  // 0 = auto-assign port
+    if server.port() == 0 {
+        let (pairings, require_pairing) = load_pairings(&app)?;
+        tracing::info!("Loaded {} paired controller(s), pairing required: {}", pairings.len(), require_pairing);
+        server.load_pairings(pairings, require_pairing);
+        server.set_transfer_dir(get_transfer_dir(&app)?).await;
+        server.set_journal_dir(get_journal_dir(&app)?);
+        server.set_media_library(Arc::new(AppMediaLibrary(app.clone())));
+    }
+
+    match tls {
+        Some(true) => {
+            let identity = crate::websocket::tls::TlsIdentity::load_or_generate(&get_tls_dir(&app)?)?;
+            server.set_tls(Some(identity))?;
+        }
+        Some(false) => server.set_tls(None)?,
+        None => {}
+    }
+
+    // Bind address and dual-stack come from the config; port 0 = auto-assign
+    let config = load_server_config(&app)?;
+    let configured_port = config.port;
+    server.set_config(config);
+    let port = server.start(configured_port).await?;
     tracing::info!("WebSocket server started on port {}", port);
 
     Ok(port)
 }
 
+/// Stop the WebSocket server, sending a close frame to every client
+#[tauri::command]
+pub async fn stop_websocket_server(app: tauri::AppHandle) -> Result<(), String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let mut server = ws_state.lock().await;
+    server.stop().await;
+    Ok(())
+}
+
+/// Restart the WebSocket server with the saved configuration (e.g. after
+/// switching networks). Clients are closed and can reconnect straight away.
+/// If the port changed, advertisements and the UDP listener move with it.
+/// Returns the port the server now listens on
+#[tauri::command]
+pub async fn restart_websocket_server(app: tauri::AppHandle) -> Result<u16, String> {
+    let (previous_port, port) = {
+        let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+        let mut server = ws_state.lock().await;
+
+        let previous_port = server.port();
+        server.set_config(load_server_config(&app)?);
+        (previous_port, server.restart().await?)
+    };
+    tracing::info!("WebSocket server restarted on port {}", port);
+
+    if port != previous_port {
+        let advertiser = app.state::<Arc<crate::mdns::AdvertiserState>>();
+        match advertiser.set_port(port).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("Re-advertised {} displays on port {}", count, port),
+            Err(e) => tracing::error!("Failed to re-advertise on port {}: {}", port, e),
+        }
+        app.state::<Arc<crate::mdns::UdpListener>>().set_ws_port(port).await;
+    }
+
+    Ok(port)
+}
+
+/// Get the saved WebSocket server configuration (bind address, port, dual-stack)
+#[tauri::command]
+pub async fn get_websocket_server_config(app: tauri::AppHandle) -> Result<ServerConfig, String> {
+    load_server_config(&app)
+}
+
+/// Save the WebSocket server configuration
+/// Takes effect the next time the server is started or restarted
+#[tauri::command]
+pub async fn set_websocket_server_config(app: tauri::AppHandle, config: ServerConfig) -> Result<(), String> {
+    save_server_config(&app, &config)?;
+    tracing::info!("WebSocket server config saved: {:?}", config);
+    Ok(())
+}
+
+/// Get the JSON Schema of the WebSocket protocol, for display builders
+/// (the same document as `schema/ws-protocol.schema.json`)
+#[tauri::command]
+pub async fn get_websocket_protocol_schema() -> Result<serde_json::Value, String> {
+    serde_json::to_value(crate::websocket::types::protocol_schema())
+        .map_err(|e| format!("Failed to serialize schema: {}", e))
+}
+
+/// Get the SHA-256 fingerprint of the WebSocket server's TLS certificate
+/// Returns None when TLS is not enabled
+#[tauri::command]
+pub async fn get_tls_fingerprint(app: tauri::AppHandle) -> Result<Option<String>, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    Ok(server.tls_fingerprint())
+}
+
 /// Publish lyrics to connected displays
 /// If target_display_id is Some, only that display will process the message
 /// If target_display_id is None, all displays will process the message (broadcast)
+/// execute_at (Unix milliseconds) schedules the change, so every display switches together
+/// Returns the sequence number assigned to the update and which displays missed it
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn publish_lyrics(
     app: tauri::AppHandle,
     church_id: String,
@@ -841,7 +1225,8 @@ pub async fn publish_lyrics(
     lyrics: String,
     background_url: Option<String>,
     target_display_id: Option<String>,
-) -> Result<(), String> {
+    execute_at: Option<i64>,
+) -> Result<DeliveryReport, String> {
     let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
     let server = ws_state.lock().await;
 
@@ -857,6 +1242,7 @@ pub async fn publish_lyrics(
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs() as i64,
+        execute_at,
     });
 
     server.broadcast(message).await
@@ -865,6 +1251,8 @@ pub async fn publish_lyrics(
 /// Publish slide change to connected displays
 /// If target_display_id is Some, only that display will process the message
 /// If target_display_id is None, all displays will process the message (broadcast)
+/// execute_at (Unix milliseconds) schedules the change, so every display switches together
+/// Returns the sequence number assigned to the update and which displays missed it
 #[tauri::command]
 pub async fn publish_slide(
     app: tauri::AppHandle,
@@ -873,7 +1261,8 @@ pub async fn publish_slide(
     song_id: String,
     slide_index: usize,
     target_display_id: Option<String>,
-) -> Result<(), String> {
+    execute_at: Option<i64>,
+) -> Result<DeliveryReport, String> {
     let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
     let server = ws_state.lock().await;
 
@@ -887,13 +1276,296 @@ pub async fn publish_slide(
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs() as i64,
+        execute_at,
+    });
+
+    server.broadcast(message).await
+}
+
+/// Current Unix time in seconds, for message timestamps
+fn unix_timestamp() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Build the payload shared by black/clear/freeze
+fn screen_data(
+    church_id: String,
+    event_id: String,
+    enabled: bool,
+    target_display_id: Option<String>,
+) -> ScreenData {
+    ScreenData {
+        target_display_id,
+        church_id,
+        event_id,
+        enabled,
+        timestamp: unix_timestamp(),
+    }
+}
+
+/// Black out displays (enabled = false brings the content back)
+/// If target_display_id is None, all displays go black
+#[tauri::command]
+pub async fn publish_black(
+    app: tauri::AppHandle,
+    church_id: String,
+    event_id: String,
+    enabled: bool,
+    target_display_id: Option<String>,
+) -> Result<DeliveryReport, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+
+    let data = screen_data(church_id, event_id, enabled, target_display_id);
+    server.broadcast(WsMessage::Black(data)).await
+}
+
+/// Clear the text on displays while keeping the background
+/// If target_display_id is None, all displays are cleared
+#[tauri::command]
+pub async fn publish_clear(
+    app: tauri::AppHandle,
+    church_id: String,
+    event_id: String,
+    enabled: bool,
+    target_display_id: Option<String>,
+) -> Result<DeliveryReport, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+
+    let data = screen_data(church_id, event_id, enabled, target_display_id);
+    server.broadcast(WsMessage::Clear(data)).await
+}
+
+/// Show (or hide) the logo on displays
+/// If logo_url is None, displays use the church's default logo
+#[tauri::command]
+pub async fn publish_logo(
+    app: tauri::AppHandle,
+    church_id: String,
+    event_id: String,
+    enabled: bool,
+    logo_url: Option<String>,
+    target_display_id: Option<String>,
+) -> Result<DeliveryReport, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+
+    let message = WsMessage::Logo(LogoData {
+        target_display_id,
+        church_id,
+        event_id,
+        enabled,
+        logo_url,
+        timestamp: unix_timestamp(),
     });
 
     server.broadcast(message).await
 }
 
-/// Discover display devices via mDNS with UDP broadcast fallback
-/// Tries mDNS first, then falls back to UDP broadcast if no devices found
+/// Freeze displays on their current frame; they ignore updates until unfrozen
+/// Unfreezing re-sends the current state so displays catch up immediately
+#[tauri::command]
+pub async fn publish_freeze(
+    app: tauri::AppHandle,
+    church_id: String,
+    event_id: String,
+    enabled: bool,
+    target_display_id: Option<String>,
+) -> Result<DeliveryReport, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+
+    let data = screen_data(church_id, event_id, enabled, target_display_id);
+    server.broadcast(WsMessage::Freeze(data)).await
+}
+
+/// Ask displays to cache an event's media and songs before the service
+/// Readiness is reported through `display-precache-ready` events and `get_precache_readiness`
+#[tauri::command]
+pub async fn publish_precache(
+    app: tauri::AppHandle,
+    church_id: String,
+    event_id: String,
+    media: Vec<PrecacheMediaItem>,
+    songs: Vec<PrecacheSongItem>,
+    target_display_id: Option<String>,
+) -> Result<DeliveryReport, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+
+    let message = WsMessage::Precache(PrecacheData {
+        target_display_id,
+        church_id,
+        event_id,
+        media,
+        songs,
+    });
+
+    server.broadcast(message).await
+}
+
+/// List open WebSocket connections with their display ID, RTT, last-seen time
+/// and outgoing queue stats (depth, coalesced and dropped messages)
+#[tauri::command]
+pub async fn get_connected_displays(app: tauri::AppHandle) -> Result<Vec<ClientInfo>, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    Ok(server.connected_clients().await)
+}
+
+/// What the server's `/status` HTTP endpoint reports: app and protocol
+/// version, connected clients, current display state and cache stats
+#[tauri::command]
+pub async fn get_websocket_status(app: tauri::AppHandle) -> Result<ServerStatus, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    Ok(server.status().await)
+}
+
+/// List the church/event rooms clients have joined, with their members
+#[tauri::command]
+pub async fn get_websocket_rooms(app: tauri::AppHandle) -> Result<Vec<RoomInfo>, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    Ok(server.rooms().await)
+}
+
+/// Get the precache readiness matrix (display × media item) for an event
+#[tauri::command]
+pub async fn get_precache_readiness(
+    app: tauri::AppHandle,
+    event_id: String,
+) -> Result<ReadinessMatrix, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    Ok(server.precache_readiness(&event_id).await)
+}
+
+/// Push a file from the media cache to a connected display over its WebSocket
+/// The file lands in the display's own media cache; progress is reported
+/// through `media-transfer-status` events. Returns the transfer ID.
+#[tauri::command]
+pub async fn push_media_to_display(
+    app: tauri::AppHandle,
+    display_id: String,
+    media_id: String,
+) -> Result<String, String> {
+    let state = load_cache_state(&app).await?;
+    let entry = state
+        .entries
+        .get(&media_id)
+        .ok_or_else(|| format!("Media {} is not cached", media_id))?;
+
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    server
+        .push_media(&display_id, &media_id, &entry.updated_at, Path::new(&entry.file_path))
+        .await
+}
+
+/// List the events with a journal of published messages, most recent first
+#[tauri::command]
+pub async fn list_journals(app: tauri::AppHandle) -> Result<Vec<JournalInfo>, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    server.journals()
+}
+
+/// Replay an event's journal onto the connected displays
+/// speed defaults to 1.0 (real time); start_at (Unix ms) skips ahead, publishing
+/// everything before it at once. Returns the number of messages scheduled;
+/// `journal-replay-finished` is emitted when the replay ends.
+#[tauri::command]
+pub async fn replay_journal(
+    app: tauri::AppHandle,
+    event_id: String,
+    speed: Option<f64>,
+    start_at: Option<i64>,
+) -> Result<usize, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let mut server = ws_state.lock().await;
+    server.replay_journal(&event_id, speed.unwrap_or(1.0), start_at)
+}
+
+/// Stop the running journal replay; returns false if none was running
+#[tauri::command]
+pub async fn stop_journal_replay(app: tauri::AppHandle) -> Result<bool, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let mut server = ws_state.lock().await;
+    Ok(server.stop_replay())
+}
+
+/// Begin pairing a controller: returns a PIN for the display to show (as text or QR)
+#[tauri::command]
+pub async fn begin_pairing(
+    app: tauri::AppHandle,
+    ttl_secs: Option<u64>,
+) -> Result<PairingCode, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+
+    let ttl = ttl_secs.map(std::time::Duration::from_secs).unwrap_or(DEFAULT_PIN_TTL);
+    Ok(server.begin_pairing(ttl))
+}
+
+/// Cancel an in-progress pairing (invalidates the PIN)
+#[tauri::command]
+pub async fn cancel_pairing(app: tauri::AppHandle) -> Result<(), String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    server.cancel_pairing();
+    Ok(())
+}
+
+/// List paired controllers
+#[tauri::command]
+pub async fn list_pairings(app: tauri::AppHandle) -> Result<Vec<PairingSummary>, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    Ok(server.pairings())
+}
+
+/// Revoke a paired controller and disconnect it
+#[tauri::command]
+pub async fn revoke_pairing(app: tauri::AppHandle, pair_id: String) -> Result<(), String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    server.revoke_pairing(&pair_id).await
+}
+
+/// Whether remote connections must be paired
+#[tauri::command]
+pub async fn get_pairing_required(app: tauri::AppHandle) -> Result<bool, String> {
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    Ok(server.require_pairing())
+}
+
+/// Enable or disable the pairing requirement for remote connections
+/// With it off, any remote controller may connect and publish without a token.
+#[tauri::command]
+pub async fn set_pairing_required(app: tauri::AppHandle, required: bool) -> Result<(), String> {
+    use tauri_plugin_store::StoreExt;
+
+    let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+    let server = ws_state.lock().await;
+    server.set_require_pairing(required);
+
+    let store = app.store(PAIRING_STORE_NAME)
+        .map_err(|e| format!("Failed to get store: {}", e))?;
+    store.set("require_pairing", serde_json::Value::Bool(required));
+    store.save().map_err(|e| format!("Failed to save store: {}", e))?;
+
+    tracing::info!("Pairing required for remote connections: {}", required);
+    Ok(())
+}
+
+/// Discover display devices via mDNS and UDP broadcast at the same time
+/// Results are merged by display_id; returns early once they stop changing
 /// Skips discovery when running in display mode (displays advertise, they don't discover)
 #[tauri::command]
 pub async fn discover_display_devices(
@@ -908,30 +1580,152 @@ pub async fn discover_display_devices(
     }
 
     let timeout = timeout_secs.unwrap_or(5);
+    let devices = crate::mdns::discover_displays(timeout).await;
 
-    // Try mDNS first
-    let devices = crate::mdns::discover_disdevices(timeout).await;
+    if devices.is_empty() {
+        tracing::warn!("No devices found via mDNS or UDP broadcast");
+    }
 
-    if !devices.is_empty() {
-        tracing::info!("Found {} devices via mDNS", devices.len());
-        return Ok(devices);
+    Ok(devices)
+}
+
+/// Start the background mDNS browser (no-op if it is already running)
+/// Changes are emitted as "display-discovered", "display-updated" and
+/// "display-lost" (see `forward_browser_events`); returns the displays known so far
+#[tauri::command]
+pub async fn start_display_browser(
+    app: tauri::AppHandle,
+) -> Result<Vec<crate::mdns::DiscoveredDevice>, String> {
+    let auto_start_mode = app.state::<Arc<crate::AutoStartMode>>();
+    if **auto_start_mode == crate::AutoStartMode::Display {
+        tracing::info!("Display mode detected, not starting the mDNS browser");
+        return Ok(Vec::new());
     }
 
-    tracing::info!("No devices found via mDNS, trying UDP broadcast fallback");
-    // Fall back to UDP broadcast
-    let udp_devices = crate::mdns::udp_broadcast_discover(timeout).await;
+    let browser = app.state::<Arc<crate::mdns::DiscoveryBrowser>>();
+    browser.start()?;
+    Ok(browser.devices())
+}
 
-    if !udp_devices.is_empty() {
-        tracing::info!("Found {} devices via UDP broadcast", udp_devices.len());
-    } else {
-        tracing::warn!("No devices found via mDNS or UDP broadcast");
+/// Displays currently seen by the background mDNS browser
+#[tauri::command]
+pub async fn get_discovered_displays(
+    app: tauri::AppHandle,
+) -> Result<Vec<crate::mdns::DiscoveredDevice>, String> {
+    let browser = app.state::<Arc<crate::mdns::DiscoveryBrowser>>();
+    Ok(browser.devices())
+}
+
+/// Stop the background mDNS browser and clear its table
+#[tauri::command]
+pub async fn stop_display_browser(app: tauri::AppHandle) -> Result<(), String> {
+    let browser = app.state::<Arc<crate::mdns::DiscoveryBrowser>>();
+    browser.stop();
+    Ok(())
+}
+
+/// Connect to a discovered display from Rust, reconnecting until disconnected
+/// token: pairing token for the display, if it requires pairing
+/// as_display: register as this display_id (display-side use); None connects as a controller
+/// Emits "ws-client-connected", "ws-client-message" and "ws-client-disconnected",
+/// each tagged with the display_id
+#[tauri::command]
+pub async fn connect_to_display(
+    app: tauri::AppHandle,
+    device: crate::mdns::DiscoveredDevice,
+    token: Option<String>,
+    as_display: Option<String>,
+) -> Result<(), String> {
+    let hello = HelloData {
+        display_id: as_display,
+        device_id: get_device_id(app.clone()).await.ok(),
+        role: None,
+        protocol_version: Some(PROTOCOL_VERSION),
+        capabilities: CLIENT_CAPABILITIES.to_vec(),
+    };
+    let client = WebSocketClient::connect(ClientTarget::from_device(&device, token), hello);
+    let events = client.subscribe();
+
+    let state = app.state::<Arc<tokio::sync::Mutex<ClientConnections>>>();
+    let previous = state.lock().await.insert(device.display_id.clone(), client);
+    if let Some(previous) = previous {
+        previous.close().await;
     }
+    tracing::info!("Connecting to display {} at {}:{}", device.display_id, device.host, device.port);
+
+    forward_client_events(app.clone(), device.display_id, events);
+    Ok(())
+}
+
+/// Turn a client's events into Tauri events until the client is closed
+fn forward_client_events(
+    app_handle: AppHandle,
+    display_id: String,
+    mut events: tokio::sync::broadcast::Receiver<ClientEvent>,
+) {
+    use tokio::sync::broadcast::error::RecvError;
+
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(ClientEvent::Connected { url }) => {
+                    let _ = app_handle.emit(
+                        "ws-client-connected",
+                        serde_json::json!({ "display_id": display_id, "url": url }),
+                    );
+                }
+                Ok(ClientEvent::Message(envelope)) => {
+                    let _ = app_handle.emit(
+                        "ws-client-message",
+                        serde_json::json!({ "display_id": display_id, "message": envelope }),
+                    );
+                }
+                Ok(ClientEvent::Disconnected { reason, retry_in }) => {
+                    let _ = app_handle.emit(
+                        "ws-client-disconnected",
+                        serde_json::json!({
+                            "display_id": display_id,
+                            "reason": reason,
+                            "retry_in_ms": retry_in.as_millis() as u64,
+                        }),
+                    );
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Dropped {} client events for display {}", skipped, display_id);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
 
-    Ok(udp_devices)
+/// Close a connection opened with connect_to_display
+#[tauri::command]
+pub async fn disconnect_from_display(app: tauri::AppHandle, display_id: String) -> Result<(), String> {
+    let state = app.state::<Arc<tokio::sync::Mutex<ClientConnections>>>();
+    let client = state
+        .lock()
+        .await
+        .remove(&display_id)
+        .ok_or_else(|| format!("Not connected to display {}", display_id))?;
+    client.close().await;
+    Ok(())
+}
+
+/// Send a message over a connection opened with connect_to_display
+#[tauri::command]
+pub async fn send_to_display(app: tauri::AppHandle, display_id: String, message: WsMessage) -> Result<(), String> {
+    let state = app.state::<Arc<tokio::sync::Mutex<ClientConnections>>>();
+    let clients = state.lock().await;
+    clients
+        .get(&display_id)
+        .ok_or_else(|| format!("Not connected to display {}", display_id))?
+        .send(message)
 }
 
 /// Start the UDP broadcast listener (for Android TV displays)
-/// This allows the display to respond to UDP broadcast discovery requests
+/// This allows the display to respond to UDP broadcast discovery requests,
+/// describing every display `start_advertising` announced
 #[tauri::command]
 pub async fn start_udp_listener(
     app: tauri::AppHandle,
@@ -939,20 +1733,19 @@ pub async fn start_udp_listener(
     ws_port: u16,
 ) -> Result<(), String> {
     // Start UDP listener to respond to discovery requests
-    let handle = crate::mdns::start_udp_listener(port, ws_port);
+    let announcements = app.state::<Arc<crate::mdns::AdvertiserState>>().announcements();
+    let listener = app.state::<Arc<crate::mdns::UdpListener>>();
+    listener.start(port, ws_port, announcements).await;
     tracing::info!("UDP broadcast listener started on port {} for WS port {}", port, ws_port);
 
-    // Store the handle in app state to keep it alive
-    app.manage(UdpListenerHandle(Some(handle)));
-
     Ok(())
 }
 
-// Wrapper to keep the UDP listener task alive
-struct UdpListenerHandle(Option<tokio::task::JoinHandle<()>>);
-
 /// Start advertising this device as a display
+/// The fingerprint of the certificate the running server serves (if it uses
+/// TLS) is included in the TXT records so controllers can pin it
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn start_advertising(
     app: tauri::AppHandle,
     name: String,
@@ -964,17 +1757,50 @@ pub async fn start_advertising(
     height: Option<u32>,
     platform: Option<String>,
 ) -> Result<(), String> {
+    let cert_fingerprint = {
+        let ws_state = app.state::<Arc<tokio::sync::Mutex<WebSocketServer>>>();
+        let server = ws_state.lock().await;
+        server.listening_fingerprint()
+    };
+
     let advertiser = app.state::<Arc<crate::mdns::AdvertiserState>>();
-    advertiser.advertise(
-        &name,
+    advertiser.advertise(crate::mdns::Advertisement {
+        name,
         port,
-        &display_id,
-        &device_id,
-        display_name.as_deref(),
+        display_id,
+        device_id,
+        display_name,
         width,
         height,
-        platform.as_deref(),
-    ).await
+        platform,
+        cert_fingerprint,
+    }).await
+}
+
+/// Stop advertising a display, or every display if no display_id is given
+/// Returns false if the display was not being advertised
+#[tauri::command]
+pub async fn stop_advertising(
+    app: tauri::AppHandle,
+    display_id: Option<String>,
+) -> Result<bool, String> {
+    let advertiser = app.state::<Arc<crate::mdns::AdvertiserState>>();
+    match display_id {
+        Some(display_id) => Ok(advertiser.stop_display(&display_id).await),
+        None => {
+            advertiser.stop().await;
+            Ok(true)
+        }
+    }
+}
+
+/// Displays currently advertised over mDNS, with the names in use on the wire
+#[tauri::command]
+pub async fn get_advertisements(
+    app: tauri::AppHandle,
+) -> Result<Vec<crate::mdns::Registration>, String> {
+    let advertiser = app.state::<Arc<crate::mdns::AdvertiserState>>();
+    Ok(advertiser.registrations().await)
 }
 
 /// Get or generate a persistent device ID for this display instance
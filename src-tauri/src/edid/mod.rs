@@ -58,15 +58,6 @@ impl DisplayFingerprint {
         // Using the DNS namespace as a base
         Uuid::new_v5(&Uuid::NAMESPACE_DNS, fingerprint_str.as_bytes())
     }
-
-    /// Get a human-readable display name
-    pub fn display_name(&self) -> String {
-        if !self.model_name.is_empty() {
-            self.model_name.clone()
-        } else {
-            format!("{} Display", self.manufacturer_id)
-        }
-    }
 }
 
 /// Display info combining OS data with EDID fingerprint
@@ -67,7 +67,11 @@ pub fn run() {
         .plugin(tauri_plugin_machine_uid::init())
         .manage(Arc::new(auto_start_mode))
         .manage(Arc::new(Mutex::new(websocket::WebSocketServer::new())))
+        .manage(Arc::new(Mutex::new(websocket::client::ClientConnections::new())))
         .manage(Arc::new(mdns::AdvertiserState::new()))
+        .manage(Arc::new(mdns::DiscoveryBrowser::new()))
+        .manage(Arc::new(mdns::UdpListener::new()))
+        .manage(Arc::new(mdns::NetworkWatcher::new()))
         .invoke_handler({
             // Desktop: includes all commands including multi-monitor display management
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -87,10 +91,43 @@ pub fn run() {
                     commands::auto_start_display_windows,
                     commands::get_platform,
                     commands::start_websocket_server,
+                    commands::stop_websocket_server,
+                    commands::restart_websocket_server,
+                    commands::get_websocket_server_config,
+                    commands::get_websocket_protocol_schema,
+                    commands::set_websocket_server_config,
                     commands::publish_lyrics,
                     commands::publish_slide,
+                    commands::publish_black,
+                    commands::publish_clear,
+                    commands::publish_logo,
+                    commands::publish_freeze,
+                    commands::publish_precache,
+                    commands::get_precache_readiness,
+                    commands::push_media_to_display,
+                    commands::list_journals,
+                    commands::replay_journal,
+                    commands::stop_journal_replay,
+                    commands::get_connected_displays,
+                    commands::get_websocket_rooms,
+                    commands::get_websocket_status,
+                    commands::begin_pairing,
+                    commands::cancel_pairing,
+                    commands::list_pairings,
+                    commands::revoke_pairing,
+                    commands::get_pairing_required,
+                    commands::get_tls_fingerprint,
+                    commands::set_pairing_required,
                     commands::discover_display_devices,
+                    commands::start_display_browser,
+                    commands::get_discovered_displays,
+                    commands::stop_display_browser,
+                    commands::connect_to_display,
+                    commands::disconnect_from_display,
+                    commands::send_to_display,
                     commands::start_advertising,
+                    commands::stop_advertising,
+                    commands::get_advertisements,
                     commands::start_udp_listener,
                     commands::get_device_id,
                     commands::get_local_ip_addresses,
@@ -110,10 +147,43 @@ pub fn run() {
                     commands::test_emit_event,
                     commands::get_platform,
                     commands::start_websocket_server,
+                    commands::stop_websocket_server,
+                    commands::restart_websocket_server,
+                    commands::get_websocket_server_config,
+                    commands::get_websocket_protocol_schema,
+                    commands::set_websocket_server_config,
                     commands::publish_lyrics,
                     commands::publish_slide,
+                    commands::publish_black,
+                    commands::publish_clear,
+                    commands::publish_logo,
+                    commands::publish_freeze,
+                    commands::publish_precache,
+                    commands::get_precache_readiness,
+                    commands::push_media_to_display,
+                    commands::list_journals,
+                    commands::replay_journal,
+                    commands::stop_journal_replay,
+                    commands::get_connected_displays,
+                    commands::get_websocket_rooms,
+                    commands::get_websocket_status,
+                    commands::begin_pairing,
+                    commands::cancel_pairing,
+                    commands::list_pairings,
+                    commands::revoke_pairing,
+                    commands::get_pairing_required,
+                    commands::get_tls_fingerprint,
+                    commands::set_pairing_required,
                     commands::discover_display_devices,
+                    commands::start_display_browser,
+                    commands::get_discovered_displays,
+                    commands::stop_display_browser,
+                    commands::connect_to_display,
+                    commands::disconnect_from_display,
+                    commands::send_to_display,
                     commands::start_advertising,
+                    commands::stop_advertising,
+                    commands::get_advertisements,
                     commands::start_udp_listener,
                     commands::get_device_id,
                     commands::get_local_ip_addresses,
@@ -121,6 +191,10 @@ pub fn run() {
             }
         })
         .setup(|app| {
+            commands::forward_websocket_events(app.handle().clone());
+            commands::forward_browser_events(app.handle().clone());
+            commands::watch_network(app.handle().clone());
+
             // Trigger auto-start if mode is set
             let auto_start_mode = app.state::<Arc<AutoStartMode>>();
             let mode = **auto_start_mode.inner();
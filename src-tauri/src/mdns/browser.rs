@@ -0,0 +1,242 @@
+//! Long-running mDNS browser keeping a live table of displays
+//!
+//! `discover_disdevices` takes a snapshot; the browser instead keeps one
+//! daemon browsing for as long as the app runs and reports every change to
+//! the table as it happens, including displays going away (goodbye packets
+//! or expired records).
+
+use crate::mdns::discovery::{device_from_info, DiscoveredDevice, SERVICE_TYPE};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// A change to the device table
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "device", rename_all = "snake_case")]
+pub enum BrowserEvent {
+    /// A display was seen for the first time
+    Discovered(DiscoveredDevice),
+    /// A known display re-announced with a different address or TXT records
+    Updated(DiscoveredDevice),
+    /// The last service instance of a display went away
+    Lost(DiscoveredDevice),
+}
+
+/// Live displays keyed by display_id
+///
+/// One display may be announced under several service instances (e.g. after
+/// a rename), so it is only lost once none of them is left.
+#[derive(Debug, Default)]
+pub struct DeviceTable {
+    devices: HashMap<String, DiscoveredDevice>,
+    /// Service fullname -> display_id
+    instances: HashMap<String, String>,
+}
+
+impl DeviceTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a resolved service instance
+    pub fn resolved(&mut self, device: DiscoveredDevice) -> Vec<BrowserEvent> {
+        let mut events = Vec::new();
+        // The instance now belongs to a different display: the old one may be gone
+        if let Some(previous) = self.instances.get(&device.name) {
+            if *previous != device.display_id {
+                events.extend(self.removed(&device.name));
+            }
+        }
+        self.instances.insert(device.name.clone(), device.display_id.clone());
+
+        match self.devices.get(&device.display_id) {
+            None => events.push(BrowserEvent::Discovered(device.clone())),
+            Some(known) if *known != device => events.push(BrowserEvent::Updated(device.clone())),
+            Some(_) => {}
+        }
+        self.devices.insert(device.display_id.clone(), device);
+        events
+    }
+
+    /// Record a service instance going away
+    pub fn removed(&mut self, fullname: &str) -> Option<BrowserEvent> {
+        let display_id = self.instances.remove(fullname)?;
+        if self.instances.values().any(|id| *id == display_id) {
+            return None;
+        }
+        self.devices.remove(&display_id).map(BrowserEvent::Lost)
+    }
+
+    /// Every live display, ordered by display_id
+    pub fn devices(&self) -> Vec<DiscoveredDevice> {
+        let mut devices: Vec<DiscoveredDevice> = self.devices.values().cloned().collect();
+        devices.sort_by(|a, b| a.display_id.cmp(&b.display_id));
+        devices
+    }
+}
+
+/// Background browser shared as app state
+pub struct DiscoveryBrowser {
+    table: Arc<Mutex<DeviceTable>>,
+    /// Bumped by `stop`, so a stopped run's queued events are dropped
+    generation: Arc<AtomicU64>,
+    events: broadcast::Sender<BrowserEvent>,
+    daemon: Mutex<Option<mdns_sd::ServiceDaemon>>,
+}
+
+impl DiscoveryBrowser {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            table: Arc::new(Mutex::new(DeviceTable::new())),
+            generation: Arc::new(AtomicU64::new(0)),
+            events,
+            daemon: Mutex::new(None),
+        }
+    }
+
+    /// Start browsing, unless already started
+    /// Returns false if the browser was already running
+    pub fn start(&self) -> Result<bool, String> {
+        let mut daemon_guard = self.daemon.lock().unwrap();
+        if daemon_guard.is_some() {
+            return Ok(false);
+        }
+
+        let daemon = mdns_sd::ServiceDaemon::new()
+            .map_err(|e| format!("Failed to create mDNS daemon: {}", e))?;
+        let receiver = daemon
+            .browse(SERVICE_TYPE)
+            .map_err(|e| format!("Failed to browse mDNS services: {}", e))?;
+        info!("Started background mDNS browser for {}", SERVICE_TYPE);
+
+        let table = self.table.clone();
+        let generation = self.generation.clone();
+        let run = generation.load(Ordering::SeqCst);
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            while let Ok(event) = receiver.recv_async().await {
+                // Checked under the table lock, which `stop` holds while it
+                // bumps the generation and clears the table
+                let mut table = table.lock().unwrap();
+                if generation.load(Ordering::SeqCst) != run {
+                    break;
+                }
+                let changes = match event {
+                    mdns_sd::ServiceEvent::ServiceResolved(info) => match device_from_info(&info) {
+                        Some(device) => table.resolved(device),
+                        None => Vec::new(),
+                    },
+                    mdns_sd::ServiceEvent::ServiceRemoved(_, fullname) => {
+                        table.removed(&fullname).into_iter().collect()
+                    }
+                    mdns_sd::ServiceEvent::SearchStopped(_) => break,
+                    _ => Vec::new(),
+                };
+                for change in changes {
+                    info!("Display browser: {:?}", change);
+                    let _ = events.send(change);
+                }
+            }
+            info!("Background mDNS browser stopped");
+        });
+
+        *daemon_guard = Some(daemon);
+        Ok(true)
+    }
+
+    /// Stop browsing and forget every display
+    pub fn stop(&self) {
+        if let Some(daemon) = self.daemon.lock().unwrap().take() {
+            if let Err(e) = daemon.shutdown() {
+                warn!("Failed to shut down mDNS browser: {}", e);
+            }
+        }
+        let mut table = self.table.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        *table = DeviceTable::new();
+    }
+
+    /// Every live display
+    pub fn devices(&self) -> Vec<DiscoveredDevice> {
+        self.table.lock().unwrap().devices()
+    }
+
+    /// Subscribe to changes in the device table
+    pub fn subscribe(&self) -> broadcast::Receiver<BrowserEvent> {
+        self.events.subscribe()
+    }
+}
+
+impl Default for DiscoveryBrowser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(fullname: &str, display_id: &str, host: &str) -> DiscoveredDevice {
+        DiscoveredDevice {
+            name: fullname.to_string(),
+            host: host.to_string(),
+            port: 8765,
+            service_type: SERVICE_TYPE.to_string(),
+            display_id: display_id.to_string(),
+            device_id: None,
+            display_name: None,
+            width: None,
+            height: None,
+            platform: None,
+            cert_fingerprint: None,
+            protocol_version: None,
+            interface: None,
+            transports: vec![crate::mdns::discovery::Transport::Mdns],
+        }
+    }
+
+    #[test]
+    fn test_table_reports_found_updated_and_lost() {
+        let mut table = DeviceTable::new();
+        let lobby = device("Lobby._mw-display._tcp.local.", "display-1", "192.168.1.20");
+
+        assert_eq!(table.resolved(lobby.clone()), vec![BrowserEvent::Discovered(lobby.clone())]);
+        // Re-announcements with nothing new are not reported
+        assert!(table.resolved(lobby.clone()).is_empty());
+
+        let moved = device("Lobby._mw-display._tcp.local.", "display-1", "192.168.1.42");
+        assert_eq!(table.resolved(moved.clone()), vec![BrowserEvent::Updated(moved.clone())]);
+        assert_eq!(table.devices(), vec![moved.clone()]);
+
+        assert!(table.removed("Unknown._mw-display._tcp.local.").is_none());
+        assert_eq!(table.removed("Lobby._mw-display._tcp.local."), Some(BrowserEvent::Lost(moved)));
+        assert!(table.devices().is_empty());
+    }
+
+    #[test]
+    fn test_display_lost_only_with_its_last_instance() {
+        let mut table = DeviceTable::new();
+        let old = device("Old name._mw-display._tcp.local.", "display-1", "192.168.1.20");
+        let new = device("New name._mw-display._tcp.local.", "display-1", "192.168.1.20");
+        table.resolved(old);
+        assert_eq!(table.resolved(new.clone()), vec![BrowserEvent::Updated(new.clone())]);
+
+        assert!(table.removed("Old name._mw-display._tcp.local.").is_none());
+        assert_eq!(table.devices().len(), 1);
+        assert_eq!(table.removed("New name._mw-display._tcp.local."), Some(BrowserEvent::Lost(new)));
+
+        // An instance re-used by another display drops the display it used to be
+        let first = device("Stage._mw-display._tcp.local.", "display-1", "192.168.1.20");
+        let second = device("Stage._mw-display._tcp.local.", "display-2", "192.168.1.20");
+        table.resolved(first.clone());
+        assert_eq!(
+            table.resolved(second.clone()),
+            vec![BrowserEvent::Lost(first), BrowserEvent::Discovered(second)]
+        );
+    }
+}
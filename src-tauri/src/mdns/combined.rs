@@ -0,0 +1,242 @@
+//! mDNS and UDP discovery run side by side, merged into one list
+//!
+//! Both backends stream what they find into a `DeviceSet`, which keeps one
+//! record per display. Discovery ends when both backends are done, at the
+//! timeout, or once the set has stopped changing for `SETTLE_AFTER` after
+//! the first display turned up, so a quiet network does not make the
+//! operator wait out the whole timeout.
+
+use super::discovery::{discover_disdevices, DiscoveredDevice};
+use super::udp_broadcast::{udp_broadcast_discover, FALLBACK_ID_PREFIX};
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::info;
+
+/// How long the results must stay unchanged before discovery ends early
+pub const SETTLE_AFTER: Duration = Duration::from_millis(1500);
+
+/// Discovered displays, one record per display
+#[derive(Debug, Default)]
+pub struct DeviceSet {
+    devices: Vec<DiscoveredDevice>,
+}
+
+/// How much a record tells about its display; higher is better
+///
+/// A real display_id beats a made-up one, then the more TXT fields the
+/// better, then an IPv4 host (link-local IPv6 needs a scope to connect).
+fn rank(device: &DiscoveredDevice) -> (bool, usize, bool) {
+    let fields = [
+        device.device_id.is_some(),
+        device.display_name.is_some(),
+        device.width.is_some(),
+        device.height.is_some(),
+        device.platform.is_some(),
+        device.cert_fingerprint.is_some(),
+        device.protocol_version.is_some(),
+    ];
+    (
+        !is_fallback(device),
+        fields.iter().filter(|known| **known).count(),
+        is_ipv4(device),
+    )
+}
+
+fn is_fallback(device: &DiscoveredDevice) -> bool {
+    device.display_id.starts_with(FALLBACK_ID_PREFIX)
+}
+
+fn is_ipv4(device: &DiscoveredDevice) -> bool {
+    device.host.parse::<IpAddr>().is_ok_and(|ip| ip.is_ipv4())
+}
+
+/// A legacy answer and another record from the same host and port
+fn same_legacy_endpoint(a: &DiscoveredDevice, b: &DiscoveredDevice) -> bool {
+    (is_fallback(a) || is_fallback(b)) && a.host == b.host && a.port == b.port
+}
+
+/// Merge two records of the same display, keeping the richer one as the base
+fn merge(known: &mut DiscoveredDevice, device: DiscoveredDevice) {
+    if rank(&device) > rank(known) {
+        let previous = std::mem::replace(known, device);
+        fill(known, previous);
+    } else {
+        fill(known, device);
+    }
+}
+
+/// Fold `other` into `base`, the richer of the two records
+fn fill(base: &mut DiscoveredDevice, other: DiscoveredDevice) {
+    if !is_ipv4(base) && is_ipv4(&other) {
+        base.host = other.host;
+        base.port = other.port;
+    }
+    base.device_id = base.device_id.take().or(other.device_id);
+    base.display_name = base.display_name.take().or(other.display_name);
+    base.width = base.width.or(other.width);
+    base.height = base.height.or(other.height);
+    base.platform = base.platform.take().or(other.platform);
+    base.cert_fingerprint = base.cert_fingerprint.take().or(other.cert_fingerprint);
+    base.protocol_version = base.protocol_version.or(other.protocol_version);
+    base.interface = base.interface.take().or(other.interface);
+    base.transports.extend(other.transports);
+    base.transports.sort();
+    base.transports.dedup();
+}
+
+impl DeviceSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a record; returns true if it told us anything new
+    ///
+    /// Records are matched by display_id. A legacy UDP answer, whose
+    /// display_id is made up from its address, is matched by host and port.
+    pub fn add(&mut self, device: DiscoveredDevice) -> bool {
+        let before = self.devices.clone();
+        let position = self
+            .devices
+            .iter()
+            .position(|known| known.display_id == device.display_id)
+            .or_else(|| self.devices.iter().position(|known| same_legacy_endpoint(known, &device)));
+        let Some(mut position) = position else {
+            self.devices.push(device);
+            return true;
+        };
+        merge(&mut self.devices[position], device);
+
+        // The merged record may turn out to be one we already had under
+        // another display_id or address
+        while let Some(duplicate) = self.devices.iter().enumerate().position(|(index, known)| {
+            let merged = &self.devices[position];
+            index != position && (known.display_id == merged.display_id || same_legacy_endpoint(known, merged))
+        }) {
+            let other = self.devices.remove(duplicate);
+            if duplicate < position {
+                position -= 1;
+            }
+            merge(&mut self.devices[position], other);
+        }
+        self.devices != before
+    }
+
+    pub fn into_devices(self) -> Vec<DiscoveredDevice> {
+        self.devices
+    }
+}
+
+/// Discover displays over mDNS and UDP broadcast at the same time
+pub async fn discover_displays(timeout_secs: u64) -> Vec<DiscoveredDevice> {
+    let (found, mut received) = mpsc::unbounded_channel();
+    tokio::spawn(discover_disdevices(timeout_secs, found.clone()));
+    tokio::spawn(udp_broadcast_discover(timeout_secs, found));
+
+    let started = tokio::time::Instant::now();
+    let deadline = started + Duration::from_secs(timeout_secs);
+    let mut devices = DeviceSet::new();
+    let mut settled_at = deadline;
+    loop {
+        match tokio::time::timeout_at(settled_at, received.recv()).await {
+            Ok(Some(device)) => {
+                if devices.add(device) {
+                    settled_at = (tokio::time::Instant::now() + SETTLE_AFTER).min(deadline);
+                }
+            }
+            // Both backends are done
+            Ok(None) => break,
+            // Settled, or out of time
+            Err(_) => break,
+        }
+    }
+    // Dropping the receiver tells the backends to stop
+    drop(received);
+
+    let devices = devices.into_devices();
+    info!("Discovery finished after {:?} with {} displays", started.elapsed(), devices.len());
+    devices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mdns::discovery::Transport;
+
+    fn device(host: &str, display_id: &str, transport: Transport) -> DiscoveredDevice {
+        DiscoveredDevice {
+            name: format!("Display@{}", host),
+            host: host.to_string(),
+            port: 8765,
+            service_type: "test".to_string(),
+            display_id: display_id.to_string(),
+            device_id: None,
+            display_name: None,
+            width: None,
+            height: None,
+            platform: None,
+            cert_fingerprint: None,
+            protocol_version: None,
+            interface: None,
+            transports: vec![transport],
+        }
+    }
+
+    #[test]
+    fn test_merges_transports_and_keeps_richest_record() {
+        let mut set = DeviceSet::new();
+
+        let mut mdns = device("192.168.1.20", "display-1", Transport::Mdns);
+        mdns.display_name = Some("Lobby".to_string());
+        mdns.width = Some(1920);
+        mdns.height = Some(1080);
+        assert!(set.add(mdns));
+
+        let mut udp = device("192.168.1.20", "display-1", Transport::Udp);
+        udp.interface = Some("eth0".to_string());
+        udp.protocol_version = Some(5);
+        assert!(set.add(udp.clone()));
+        // The same answer from another interface adds nothing
+        assert!(!set.add(udp));
+
+        let devices = set.into_devices();
+        assert_eq!(devices.len(), 1);
+        let display = &devices[0];
+        assert_eq!(display.display_name.as_deref(), Some("Lobby"));
+        assert_eq!(display.width, Some(1920));
+        assert_eq!(display.protocol_version, Some(5));
+        assert_eq!(display.interface.as_deref(), Some("eth0"));
+        assert_eq!(display.transports, vec![Transport::Mdns, Transport::Udp]);
+    }
+
+    #[test]
+    fn test_fallback_ids_and_ipv6_hosts_give_way() {
+        let mut set = DeviceSet::new();
+        // A display answering both UDP requests, over IPv6 as well as IPv4
+        set.add(device("192.168.1.20", "udp-fallback-192-168-1-20", Transport::Udp));
+        let mut v6 = device("fe80::1", "display-1", Transport::Udp);
+        v6.protocol_version = Some(5);
+        set.add(v6);
+        let mut v4 = device("192.168.1.20", "display-1", Transport::Udp);
+        v4.protocol_version = Some(5);
+        set.add(v4);
+        // Only reachable over IPv6
+        set.add(device("fe80::2", "display-2", Transport::Udp));
+        // A legacy display
+        set.add(device("192.168.1.30", "udp-fallback-192-168-1-30", Transport::Udp));
+
+        let hosts: Vec<(String, String)> = set
+            .into_devices()
+            .into_iter()
+            .map(|device| (device.display_id, device.host))
+            .collect();
+        assert_eq!(
+            hosts,
+            vec![
+                ("display-1".to_string(), "192.168.1.20".to_string()),
+                ("display-2".to_string(), "fe80::2".to_string()),
+                ("udp-fallback-192-168-1-30".to_string(), "192.168.1.30".to_string()),
+            ]
+        );
+    }
+}
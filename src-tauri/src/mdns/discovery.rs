@@ -1,9 +1,13 @@
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
 use tracing::{info, error, warn};
 use std::net::Ipv4Addr;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// mDNS service type displays advertise
+pub const SERVICE_TYPE: &str = "_mw-display._tcp.local.";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DiscoveredDevice {
     pub name: String,
     pub host: String,
@@ -19,6 +23,22 @@ pub struct DiscoveredDevice {
     pub width: Option<u32>, // Resolution width from TXT records
     pub height: Option<u32>, // Resolution height from TXT records
     pub platform: Option<String>, // Platform/OS info (e.g., "Android 11", "Fire OS 7")
+    #[serde(rename = "certFingerprint", default)]
+    pub cert_fingerprint: Option<String>, // TLS certificate SHA-256 to pin (display serves wss://)
+    #[serde(rename = "protocolVersion", default)]
+    pub protocol_version: Option<u32>, // WebSocket protocol version, if the display announces it
+    #[serde(default)]
+    pub interface: Option<String>, // Network interface the display was found on (UDP discovery)
+    #[serde(default)]
+    pub transports: Vec<Transport>, // Discovery mechanisms that saw this display
+}
+
+/// How a display was discovered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Mdns,
+    Udp,
 }
 
 /// Extract IPv4 address from mDNS service info
@@ -39,17 +59,76 @@ fn extract_ipv4_address(info: &mdns_sd::ServiceInfo) -> Option<String> {
     // Fallback to any address (including IPv6)
     info.get_addresses()
         .iter()
-        .filter(|a| {
+        .find(|a| {
             // Filter out link-local and loopback addresses that won't work
             let ip_str = a.to_string();
             !ip_str.starts_with("fe80::") && !ip_str.starts_with("127.") && !ip_str.starts_with("::1")
         })
-        .next()
         .map(|a| a.to_string())
 }
 
+/// Build a device from a resolved service's TXT records
+/// Returns None for services advertising neither display_id nor device_id
+pub fn device_from_info(info: &mdns_sd::ServiceInfo) -> Option<DiscoveredDevice> {
+    let txt_properties = info.get_properties();
+    info!("  TXT records: {:?}", txt_properties);
+    let txt = |key: &str| {
+        txt_properties
+            .iter()
+            .find(|prop| prop.key() == key)
+            .map(|prop| prop.val_str().to_string())
+    };
+
+    let display_id = txt("display_id");
+    let device_id = txt("device_id");
+    let display_name = txt("display_name");
+    let width = txt("width").and_then(|w| w.parse::<u32>().ok());
+    let height = txt("height").and_then(|h| h.parse::<u32>().ok());
+    let platform = txt("platform");
+    let cert_fingerprint = txt("cert_sha256").filter(|fp| !fp.is_empty());
+    let protocol_version = txt("protocol_version").and_then(|v| v.parse::<u32>().ok());
+
+    // display_id is required for per-display tracking
+    // If not present, fall back to device_id for backward compat with legacy displays
+    let Some(display_id) = display_id.or_else(|| device_id.clone()) else {
+        warn!("  ⚠ No display_id or device_id in TXT records (legacy display?)");
+        return None;
+    };
+    info!("  ✓ Found display_id: {}", display_id);
+
+    let host = match extract_ipv4_address(info) {
+        Some(a) => {
+            info!("  ✓ Using IPv4 address: {}", a);
+            a
+        }
+        None => {
+            warn!("  No valid IP address found, using hostname (may fail)");
+            info.get_hostname().to_string()
+        }
+    };
+
+    Some(DiscoveredDevice {
+        name: info.get_fullname().to_string(),
+        host,
+        port: info.get_port(),
+        service_type: SERVICE_TYPE.to_string(),
+        display_id,
+        device_id,
+        display_name,
+        width,
+        height,
+        platform,
+        cert_fingerprint,
+        protocol_version,
+        interface: None,
+        transports: vec![Transport::Mdns],
+    })
+}
+
 /// Discover Mobile Worship display devices via mDNS
-pub async fn discover_disdevices(timeout_secs: u64) -> Vec<DiscoveredDevice> {
+/// Each display is sent to `found` as soon as it resolves; stops early once
+/// the receiving side is dropped
+pub async fn discover_disdevices(timeout_secs: u64, found: UnboundedSender<DiscoveredDevice>) {
     info!("=== Starting mDNS Discovery ===");
     info!("Timeout: {} seconds", timeout_secs);
 
@@ -62,7 +141,7 @@ pub async fn discover_disdevices(timeout_secs: u64) -> Vec<DiscoveredDevice> {
         }
     }
 
-    let service_type = "_mw-display._tcp.local.";
+    let service_type = SERVICE_TYPE;
     info!("Browsing for service type: {}", service_type);
 
     // Create a service daemon for browsing
@@ -73,7 +152,7 @@ pub async fn discover_disdevices(timeout_secs: u64) -> Vec<DiscoveredDevice> {
         }
         Err(e) => {
             error!("Failed to create mDNS daemon for discovery: {}", e);
-            return Vec::new();
+            return;
         }
     };
 
@@ -85,11 +164,10 @@ pub async fn discover_disdevices(timeout_secs: u64) -> Vec<DiscoveredDevice> {
         }
         Err(e) => {
             error!("Failed to browse mDNS services: {}", e);
-            return Vec::new();
+            return;
         }
     };
 
-    let mut devices = Vec::new();
     let mut seen_fullnames = std::collections::HashSet::new();
     let timeout = Duration::from_secs(timeout_secs);
     let start = std::time::Instant::now();
@@ -99,10 +177,21 @@ pub async fn discover_disdevices(timeout_secs: u64) -> Vec<DiscoveredDevice> {
 
     info!("Listening for mDNS events...");
 
-    // Collect devices for the specified timeout
-    while start.elapsed() < timeout {
-        match receiver.recv_timeout(Duration::from_millis(100)) {
-            Ok(mdns_sd::ServiceEvent::ServiceResolved(info)) => {
+    // Collect devices for the specified timeout, or until nobody is listening
+    while start.elapsed() < timeout && !found.is_closed() {
+        let event = match tokio::time::timeout(Duration::from_millis(100), receiver.recv_async()).await {
+            Ok(Ok(event)) => event,
+            Ok(Err(e)) => {
+                warn!("mDNS browse channel closed: {}", e);
+                break;
+            }
+            Err(_) => {
+                // Timeout is expected, continue checking total timeout
+                continue;
+            }
+        };
+        match event {
+            mdns_sd::ServiceEvent::ServiceResolved(info) => {
                 event_count += 1;
                 resolved_count += 1;
                 info!("=== ServiceResolved event #{} ===", event_count);
@@ -111,104 +200,37 @@ pub async fn discover_disdevices(timeout_secs: u64) -> Vec<DiscoveredDevice> {
                 info!("  Port: {}", info.get_port());
                 info!("  All addresses: {:?}", info.get_addresses());
 
-                // Extract TXT records for per-display identification
-                let txt_properties = info.get_properties();
-                info!("  TXT records: {:?}", txt_properties);
-
-                // Extract all TXT record fields
-                let display_id = txt_properties
-                    .iter()
-                    .find(|prop| prop.key() == "display_id")
-                    .map(|prop| prop.val_str().to_string());
-
-                let device_id = txt_properties
-                    .iter()
-                    .find(|prop| prop.key() == "device_id")
-                    .map(|prop| prop.val_str().to_string());
-
-                let display_name = txt_properties
-                    .iter()
-                    .find(|prop| prop.key() == "display_name")
-                    .map(|prop| prop.val_str().to_string());
-
-                let width = txt_properties
-                    .iter()
-                    .find(|prop| prop.key() == "width")
-                    .and_then(|prop| prop.val_str().parse::<u32>().ok());
-
-                let height = txt_properties
-                    .iter()
-                    .find(|prop| prop.key() == "height")
-                    .and_then(|prop| prop.val_str().parse::<u32>().ok());
-
-                let platform = txt_properties
-                    .iter()
-                    .find(|prop| prop.key() == "platform")
-                    .map(|prop| prop.val_str().to_string());
-
-                // display_id is required for per-display tracking
-                // If not present, fall back to device_id for backward compat with legacy displays
-                let final_display_id = display_id.clone().or_else(|| device_id.clone());
-
-                if let Some(ref id) = final_display_id {
-                    info!("  ✓ Found display_id: {}", id);
-                } else {
-                    warn!("  ⚠ No display_id or device_id in TXT records (legacy display?)");
-                    continue; // Skip displays without any ID
-                }
-
                 // Skip if we've already seen this service (deduplication)
                 let fullname = info.get_fullname().to_string();
                 if seen_fullnames.contains(&fullname) {
                     info!("  Skipping duplicate service: {}", fullname);
                     continue;
                 }
-                seen_fullnames.insert(fullname.clone());
-
-                let host = match extract_ipv4_address(&info) {
-                    Some(a) => {
-                        info!("  ✓ Using IPv4 address: {}", a);
-                        a
-                    }
-                    None => {
-                        warn!("  No valid IP address found, using hostname (may fail)");
-                        info.get_hostname().to_string()
-                    }
+
+                let Some(device) = device_from_info(&info) else {
+                    continue; // Skip displays without any ID
                 };
+                seen_fullnames.insert(fullname);
 
                 found_count += 1;
-                info!("  ★ Discovered display #{}: {} at {}:{} (display_id: {:?}, device_id: {:?})",
-                      found_count, info.get_fullname(), host, info.get_port(), final_display_id, device_id);
-
-                devices.push(DiscoveredDevice {
-                    name: fullname,
-                    host,
-                    port: info.get_port(),
-                    service_type: service_type.to_string(),
-                    display_id: final_display_id.unwrap(),
-                    device_id,
-                    display_name,
-                    width,
-                    height,
-                    platform,
-                });
+                info!("  ★ Discovered display #{}: {} at {}:{} (display_id: {}, device_id: {:?})",
+                      found_count, device.name, device.host, device.port, device.display_id, device.device_id);
+
+                let _ = found.send(device);
             }
-            Ok(mdns_sd::ServiceEvent::ServiceFound(name, typ)) => {
+            mdns_sd::ServiceEvent::ServiceFound(name, typ) => {
                 event_count += 1;
                 info!("ServiceFound event #{}: {} (type: {})", event_count, name, typ);
                 info!("  Querying for more details...");
             }
-            Ok(mdns_sd::ServiceEvent::ServiceRemoved(name, typ)) => {
+            mdns_sd::ServiceEvent::ServiceRemoved(name, typ) => {
                 event_count += 1;
                 info!("ServiceRemoved event #{}: {} (type: {})", event_count, name, typ);
             }
-            Ok(other) => {
+            other => {
                 event_count += 1;
                 info!("Other mDNS event #{}: {:?}", event_count, other);
             }
-            Err(_e) => {
-                // Timeout is expected, continue checking total timeout
-            }
         }
     }
 
@@ -217,12 +239,6 @@ pub async fn discover_disdevices(timeout_secs: u64) -> Vec<DiscoveredDevice> {
 
     info!("=== Discovery Complete ===");
     info!("Total events received: {}", event_count);
-    info!("Services found: {}", found_count);
     info!("Services resolved: {}", resolved_count);
-    info!("Devices discovered: {}", devices.len());
-    for (i, device) in devices.iter().enumerate() {
-        info!("  Device {}: {} at {}:{}", i + 1, device.name, device.host, device.port);
-    }
-
-    devices
+    info!("Devices discovered: {}", found_count);
 }
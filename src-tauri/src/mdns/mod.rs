@@ -1,7 +1,13 @@
+pub mod browser;
+pub mod combined;
 pub mod discovery;
+pub mod network;
 pub mod service;
 pub mod udp_broadcast;
 
+pub use browser::*;
+pub use combined::*;
 pub use discovery::*;
+pub use network::*;
 pub use service::*;
 pub use udp_broadcast::*;
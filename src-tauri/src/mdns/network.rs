@@ -0,0 +1,200 @@
+//! Watch the host's network interfaces for address changes
+//!
+//! Advertisements and the UDP listener are set up for the addresses the host
+//! had at the time. When a DHCP lease changes or Wi-Fi reconnects they go
+//! stale, so the watcher polls the interface list and reports changes for
+//! them to be refreshed.
+//!
+//! Only what they depend on is compared: the addresses advertisements carry
+//! and the interfaces the UDP listener joins its IPv6 group on. Rotating IPv6
+//! temporary addresses change neither, so they are not reported.
+
+use super::service::is_advertised;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// How often the interface list is checked
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// An address assigned to an interface
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct InterfaceAddress {
+    pub interface: String,
+    pub ip: IpAddr,
+}
+
+/// The part of the host's network the watcher compares
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Non-loopback addresses advertisements carry
+    pub addresses: BTreeSet<InterfaceAddress>,
+    /// Indexes of the non-loopback IPv6 interfaces
+    pub interfaces: BTreeSet<u32>,
+}
+
+impl Snapshot {
+    /// Record one interface address, keeping only what is compared
+    pub fn add(&mut self, interface: &str, ip: IpAddr, index: Option<u32>) {
+        if let (true, false, Some(index)) = (ip.is_ipv6(), ip.is_loopback(), index) {
+            self.interfaces.insert(index);
+        }
+        if is_advertised(&ip) {
+            self.addresses.insert(InterfaceAddress { interface: interface.to_string(), ip });
+        }
+    }
+}
+
+/// What changed between two looks at the interfaces
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NetworkChange {
+    pub added: Vec<InterfaceAddress>,
+    pub removed: Vec<InterfaceAddress>,
+    /// Every advertised address the host has now
+    pub addresses: Vec<InterfaceAddress>,
+    /// IPv6 interfaces came or went, so the UDP listener must bind again
+    pub interfaces_changed: bool,
+}
+
+/// The host's network as it is now
+pub fn snapshot() -> Snapshot {
+    let mut snapshot = Snapshot::default();
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => {
+            for iface in interfaces {
+                snapshot.add(&iface.name, iface.ip(), iface.index);
+            }
+        }
+        Err(e) => warn!("Failed to list network interfaces: {}", e),
+    }
+    snapshot
+}
+
+/// The change from `old` to `new`, if there is one
+pub fn diff(old: &Snapshot, new: &Snapshot) -> Option<NetworkChange> {
+    if old == new {
+        return None;
+    }
+    Some(NetworkChange {
+        added: new.addresses.difference(&old.addresses).cloned().collect(),
+        removed: old.addresses.difference(&new.addresses).cloned().collect(),
+        addresses: new.addresses.iter().cloned().collect(),
+        interfaces_changed: old.interfaces != new.interfaces,
+    })
+}
+
+/// Background poller shared as app state
+pub struct NetworkWatcher {
+    events: broadcast::Sender<NetworkChange>,
+    handle: Mutex<Option<tokio::task::JoinHandle<()>>>,
+}
+
+impl NetworkWatcher {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(16);
+        Self { events, handle: Mutex::new(None) }
+    }
+
+    /// Start polling every `interval`, unless already started
+    pub fn start(&self, interval: Duration) {
+        let mut handle = self.handle.lock().unwrap();
+        if handle.is_some() {
+            return;
+        }
+
+        let events = self.events.clone();
+        *handle = Some(tokio::spawn(async move {
+            let mut known = snapshot();
+            info!("Watching {} network addresses for changes", known.addresses.len());
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let current = snapshot();
+                if let Some(change) = diff(&known, &current) {
+                    info!("Network changed: added {:?}, removed {:?}", change.added, change.removed);
+                    let _ = events.send(change);
+                    known = current;
+                }
+            }
+        }));
+    }
+
+    /// Subscribe to network changes
+    pub fn subscribe(&self) -> broadcast::Receiver<NetworkChange> {
+        self.events.subscribe()
+    }
+}
+
+impl Default for NetworkWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(interface: &str, ip: &str) -> InterfaceAddress {
+        InterfaceAddress { interface: interface.to_string(), ip: ip.parse().unwrap() }
+    }
+
+    fn snapshot_of(addresses: &[(&str, &str, u32)]) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        for (interface, ip, index) in addresses {
+            snapshot.add(interface, ip.parse().unwrap(), Some(*index));
+        }
+        snapshot
+    }
+
+    #[test]
+    fn test_diff_reports_added_and_removed_addresses() {
+        let before = snapshot_of(&[("lo", "127.0.0.1", 1), ("eth0", "192.168.1.20", 2), ("wlan0", "10.0.0.5", 3)]);
+        assert!(diff(&before, &before.clone()).is_none());
+
+        // New DHCP lease on Wi-Fi
+        let after = snapshot_of(&[("eth0", "192.168.1.20", 2), ("wlan0", "10.0.0.17", 3)]);
+        let change = diff(&before, &after).unwrap();
+        assert_eq!(change.added, vec![address("wlan0", "10.0.0.17")]);
+        assert_eq!(change.removed, vec![address("wlan0", "10.0.0.5")]);
+        assert_eq!(change.addresses.len(), 2);
+        assert!(!change.interfaces_changed);
+
+        // Wi-Fi dropped
+        let change = diff(&after, &snapshot_of(&[("eth0", "192.168.1.20", 2)])).unwrap();
+        assert!(change.added.is_empty());
+        assert_eq!(change.removed, vec![address("wlan0", "10.0.0.17")]);
+    }
+
+    #[test]
+    fn test_only_advertised_addresses_and_interfaces_count() {
+        let before = snapshot_of(&[
+            ("eth0", "192.168.1.20", 2),
+            ("eth0", "fe80::1c2b:3aff:fe4d:5e6f", 2),
+            ("eth0", "2001:db8::8a1f:1e2d", 2),
+        ]);
+
+        // A rotated IPv6 temporary address or a new link-local IPv4 address
+        // changes nothing that is advertised
+        let rotated = snapshot_of(&[
+            ("eth0", "192.168.1.20", 2),
+            ("eth0", "fe80::1c2b:3aff:fe4d:5e6f", 2),
+            ("eth0", "2001:db8::51c4:77a0", 2),
+            ("eth0", "169.254.10.20", 2),
+        ]);
+        assert!(diff(&before, &rotated).is_none());
+
+        // A new IPv6 interface needs the UDP listener to join its group
+        let mut joined = rotated.clone();
+        joined.add("wlan0", "fe80::aa:bbff:fecc:dd".parse().unwrap(), Some(3));
+        let change = diff(&rotated, &joined).unwrap();
+        assert!(change.interfaces_changed);
+        assert!(change.added.is_empty() && change.removed.is_empty());
+    }
+}
@@ -1,3 +1,6 @@
+use super::discovery::SERVICE_TYPE;
+use super::udp_broadcast::{DisplayAnnouncement, SharedAnnouncements};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tracing::{info, debug, warn};
@@ -35,7 +38,7 @@ fn get_all_ip_addresses() -> Vec<String> {
                     continue;
                 }
                 // Skip link-local (169.254.x.x)
-                if addr.octets()[0] == 169 && addr.octets()[1] == 254 {
+                if !is_advertised(&iface.ip()) {
                     info!("Skipping link-local address {} on interface {}", addr, iface.name);
                     continue;
                 }
@@ -50,84 +53,150 @@ fn get_all_ip_addresses() -> Vec<String> {
     addresses
 }
 
-/// Get the primary local IP address
-/// For same-machine discovery, prefer loopback (127.0.0.1)
-/// Falls back to network IP for cross-machine discovery
-fn get_local_ip_address() -> Option<String> {
-    let addresses = get_all_ip_addresses();
-    addresses.into_iter().next()
+/// Whether advertisements carry a network address for other machines
+/// IPv4 only, without loopback or link-local (169.254.x.x) addresses
+/// (loopback is added separately, for same-machine discovery)
+pub fn is_advertised(ip: &IpAddr) -> bool {
+    matches!(ip, IpAddr::V4(addr) if !addr.is_loopback() && !addr.is_link_local())
+}
+
+/// mDNS hostname for a display: `mw-<display_id>.local.`, trimmed to DNS label rules
+/// Each display gets its own host record, so two displays never fight over one
+pub fn hostname_for(display_id: &str) -> String {
+    let label: String = display_id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .take(32)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let label = label.trim_matches('-');
+    if label.is_empty() {
+        "mw-display.local.".to_string()
+    } else {
+        format!("mw-{}.local.", label)
+    }
+}
+
+/// One display advertised on the network
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Registration {
+    pub display_id: String,
+    /// Service fullname as registered (used to unregister)
+    pub fullname: String,
+    /// Service fullname on the wire, after any conflict renaming
+    pub advertised_name: String,
+    /// Hostname on the wire, after any conflict renaming
+    pub hostname: String,
+    pub port: u16,
+}
+
+/// What a display is advertised with
+/// Kept per display to register it again on new addresses
+#[derive(Debug, Clone, PartialEq)]
+pub struct Advertisement {
+    /// Service instance name
+    pub name: String,
+    pub port: u16,
+    /// Unique per-display UUID (from EDID fingerprint)
+    pub display_id: String,
+    /// Device UUID (for backward compatibility and grouping)
+    pub device_id: String,
+    /// Human-readable display name
+    pub display_name: Option<String>,
+    /// Display resolution in pixels
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Platform/OS info (e.g., "Android 11", "Fire OS 7")
+    pub platform: Option<String>,
+    /// SHA-256 fingerprint of the wss:// certificate, if TLS is enabled
+    pub cert_fingerprint: Option<String>,
 }
 
 /// Service advertiser using mDNS
+/// One daemon serves every registration, one per display_id
 pub struct ServiceAdvertiser {
     service_daemon: Option<mdns_sd::ServiceDaemon>,
-    service_fullname: Option<String>,
+    registrations: HashMap<String, Registration>,
+    advertisements: HashMap<String, Advertisement>,
 }
 
 impl ServiceAdvertiser {
     /// Create a new advertiser
     pub fn new() -> Self {
-        Self { service_daemon: None, service_fullname: None }
+        Self { service_daemon: None, registrations: HashMap::new(), advertisements: HashMap::new() }
+    }
+
+    /// The daemon, started on first use
+    fn daemon(&mut self) -> Result<mdns_sd::ServiceDaemon, String> {
+        if let Some(daemon) = &self.service_daemon {
+            return Ok(daemon.clone());
+        }
+        let daemon = mdns_sd::ServiceDaemon::new()
+            .map_err(|e| format!("Failed to create mDNS daemon: {}", e))?;
+        info!("Created mDNS daemon");
+
+        // Start browsing on the same daemon to keep it actively processing queries
+        let _browse_receiver = daemon.browse(SERVICE_TYPE);
+        info!("Started browsing on advertising daemon to enable query responses");
+
+        self.service_daemon = Some(daemon.clone());
+        Ok(daemon)
     }
 
     /// Start advertising the service with per-display identification
-    /// display_id: Unique per-display UUID (from EDID fingerprint)
-    /// device_id: Device UUID (for backward compatibility and grouping)
-    /// display_name: Human-readable display name
-    /// width, height: Display resolution in pixels
-    /// platform: Platform/OS info (e.g., "Android 11", "Fire OS 7")
-    pub async fn advertise(
-        &mut self,
-        name: &str,
-        port: u16,
-        display_id: &str,
-        device_id: &str,
-        display_name: Option<&str>,
-        width: Option<u32>,
-        height: Option<u32>,
-        platform: Option<&str>,
-    ) -> Result<(), String> {
+    /// Re-advertising a display_id replaces its registration; other displays are untouched
+    pub async fn advertise(&mut self, advertisement: Advertisement) -> Result<(), String> {
         info!("=== Starting mDNS Advertising ===");
-        info!("Service name: '{}'", name);
-        info!("Port: {}", port);
-        info!("Display ID: {}", display_id);
-        info!("Device ID: {}", device_id);
-        info!("Platform: {:?}", platform);
-
-        // Stop any existing service first
-        if self.service_daemon.is_some() {
-            warn!("Stopping existing mDNS service before starting new one");
-            self.stop();
+        info!("Service name: '{}'", advertisement.name);
+        info!("Port: {}", advertisement.port);
+        info!("Display ID: {}", advertisement.display_id);
+        info!("Device ID: {}", advertisement.device_id);
+        info!("Platform: {:?}", advertisement.platform);
+
+        // Replace any existing registration for this display
+        if self.registrations.contains_key(&advertisement.display_id) {
+            warn!("Replacing existing mDNS registration for display {}", advertisement.display_id);
+            self.unregister(&advertisement.display_id);
         }
 
+        let name = self.instance_name(&advertisement.name);
+        self.register(Advertisement { name, ..advertisement })
+    }
+
+    /// Register a display under its exact instance name with the host's current addresses
+    /// Registering a name the daemon already has updates its records in
+    /// place, without a goodbye, so browsers do not see the display go away
+    fn register(&mut self, advertisement: Advertisement) -> Result<(), String> {
+        let Advertisement { port, width, height, .. } = advertisement;
+        let display_id = advertisement.display_id.as_str();
+        let device_id = advertisement.device_id.as_str();
+        let name = advertisement.name.as_str();
+
         // Get ALL local IP addresses for better discovery
         let all_ips = get_all_ip_addresses();
         if all_ips.is_empty() {
             return Err("Failed to get any local IP addresses".to_string());
         }
 
-        // Create a new mDNS daemon
-        let daemon = mdns_sd::ServiceDaemon::new()
-            .map_err(|e| format!("Failed to create mDNS daemon: {}", e))?;
-        info!("Created mDNS daemon");
-
-        let service_type = "_mw-display._tcp.local.";
-        let hostname = "mobile-worship-display.local.";
+        let daemon = self.daemon()?;
+        let hostname = hostname_for(display_id);
 
         // Create service info with ALL IP addresses for better discovery
         let all_ips_str = all_ips.join(",");
-        info!("Creating service info with IPs: {}", all_ips_str);
+        info!("Creating service info for {} with IPs: {}", hostname, all_ips_str);
 
         // TXT records with display_id (primary) and device_id (for grouping)
         // Also include display info for discovery UI
         let width_str = width.map(|w| w.to_string()).unwrap_or_default();
         let height_str = height.map(|h| h.to_string()).unwrap_or_default();
-        let display_name_str = display_name.unwrap_or("");
-        let platform_str = platform.unwrap_or("");
+        let display_name_str = advertisement.display_name.as_deref().unwrap_or("");
+        let platform_str = advertisement.platform.as_deref().unwrap_or("");
 
+        let protocol_version = crate::websocket::types::PROTOCOL_VERSION.to_string();
         let mut txt_records: Vec<(&str, &str)> = vec![
             ("display_id", display_id),
             ("device_id", device_id),
+            ("protocol_version", &protocol_version),
         ];
 
         if !display_name_str.is_empty() {
@@ -142,20 +211,24 @@ impl ServiceAdvertiser {
         if !platform_str.is_empty() {
             txt_records.push(("platform", platform_str));
         }
+        // Controllers connect with wss:// and pin this fingerprint
+        if let Some(fingerprint) = advertisement.cert_fingerprint.as_deref() {
+            txt_records.push(("tls", "1"));
+            txt_records.push(("cert_sha256", fingerprint));
+        }
 
-        let mut service_info = mdns_sd::ServiceInfo::new(
-            service_type,
+        // Probing is left on: if another host already uses the instance name
+        // or hostname, the daemon picks a new one and reports it to the monitor
+        let service_info = mdns_sd::ServiceInfo::new(
+            SERVICE_TYPE,
             name,
-            hostname,
+            &hostname,
             all_ips.as_slice(),
             port,
             txt_records.as_slice(),
         )
         .map_err(|e| format!("Failed to create service info: {}", e))?;
 
-        // Skip probing for faster announcement (safe for same-machine testing)
-        service_info.set_requires_probe(false);
-
         let fullname = service_info.get_fullname().to_string();
         info!("Service fullname: {}", fullname);
         info!("Service addresses: {:?}", service_info.get_addresses());
@@ -166,26 +239,136 @@ impl ServiceAdvertiser {
         daemon.register(service_info)
             .map_err(|e| format!("Failed to register mDNS service: {}", e))?;
 
-        // Start browsing on the same daemon to keep it actively processing queries
-        let _browse_receiver = daemon.browse(service_type);
-        info!("Started browsing on advertising daemon to enable query responses");
-
         info!("✓ Advertising mDNS service '{}' on port {} with {} IP addresses",
               name, port, all_ips.len());
         info!("  Display ID: {}", display_id);
         info!("  Device ID: {}", device_id);
+        info!("  Hostname: {}", hostname);
         info!("  Addresses: {:?}", all_ips);
 
-        self.service_daemon = Some(daemon);
-        self.service_fullname = Some(fullname);
+        // A conflict rename the daemon made earlier still stands
+        let (advertised_name, hostname) = match self.registrations.get(display_id) {
+            Some(known) if known.fullname == fullname => (known.advertised_name.clone(), known.hostname.clone()),
+            _ => (fullname.clone(), hostname),
+        };
+        self.registrations.insert(display_id.to_string(), Registration {
+            display_id: display_id.to_string(),
+            fullname,
+            advertised_name,
+            hostname,
+            port,
+        });
+        self.advertisements.insert(display_id.to_string(), advertisement);
         Ok(())
     }
 
+    /// Update every display's records with the host's current addresses
+    /// Returns how many displays were re-advertised
+    pub async fn readvertise(&mut self) -> Result<usize, String> {
+        let mut advertisements: Vec<(String, Advertisement)> = self
+            .advertisements
+            .iter()
+            .map(|(display_id, advertisement)| (display_id.clone(), advertisement.clone()))
+            .collect();
+        advertisements.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut readvertised = 0;
+        let mut last_error = None;
+        for (display_id, advertisement) in advertisements {
+            match self.register(advertisement) {
+                Ok(()) => readvertised += 1,
+                Err(e) => {
+                    // Still kept, to try again on the next network change
+                    warn!("Failed to re-advertise display {}: {}", display_id, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) if readvertised == 0 => Err(e),
+            _ => Ok(readvertised),
+        }
+    }
+
+    /// Advertise every display on a new WebSocket port
+    /// Returns how many displays were re-advertised
+    pub async fn set_port(&mut self, port: u16) -> Result<usize, String> {
+        for advertisement in self.advertisements.values_mut() {
+            advertisement.port = port;
+        }
+        self.readvertise().await
+    }
+
+    /// `name`, or `name (2)`, `name (3)`... if another display here already uses it
+    /// Probing only catches conflicts with other hosts, not between our own registrations
+    fn instance_name(&self, name: &str) -> String {
+        let taken = |candidate: &str| {
+            let fullname = format!("{}.{}", candidate, SERVICE_TYPE);
+            self.registrations.values().any(|registration| registration.fullname == fullname)
+        };
+        let mut candidate = name.to_string();
+        let mut n = 2;
+        while taken(&candidate) {
+            candidate = format!("{} ({})", name, n);
+            n += 1;
+        }
+        candidate
+    }
+
+    fn unregister(&mut self, display_id: &str) -> bool {
+        self.advertisements.remove(display_id);
+        let Some(registration) = self.registrations.remove(display_id) else {
+            return false;
+        };
+        if let Some(daemon) = &self.service_daemon {
+            if let Err(e) = daemon.unregister(&registration.fullname) {
+                warn!("Failed to unregister {}: {}", registration.fullname, e);
+            }
+        }
+        true
+    }
+
+    /// Stop advertising one display; returns false if it was not advertised
+    /// The daemon is shut down once nothing is left to advertise
+    pub fn stop_display(&mut self, display_id: &str) -> bool {
+        let stopped = self.unregister(display_id);
+        if self.registrations.is_empty() {
+            if let Some(daemon) = self.service_daemon.take() {
+                let _ = daemon.shutdown();
+            }
+        }
+        stopped
+    }
+
     /// Stop advertising
     pub fn stop(&mut self) {
-        if let (Some(daemon), Some(fullname)) = (self.service_daemon.take(), self.service_fullname.take()) {
-            let _ = daemon.unregister(&fullname);
+        let display_ids: Vec<String> = self.registrations.keys().cloned().collect();
+        for display_id in display_ids {
+            self.stop_display(&display_id);
+        }
+    }
+
+    /// Record a name the daemon changed to resolve a conflict
+    /// Returns false if the name is not one of ours
+    pub fn apply_name_change(&mut self, original: &str, new_name: &str) -> bool {
+        let mut applied = false;
+        for registration in self.registrations.values_mut() {
+            if registration.fullname == original {
+                registration.advertised_name = new_name.to_string();
+                applied = true;
+            } else if hostname_for(&registration.display_id) == original {
+                registration.hostname = new_name.to_string();
+                applied = true;
+            }
         }
+        applied
+    }
+
+    /// Every display currently advertised, ordered by display_id
+    pub fn registrations(&self) -> Vec<Registration> {
+        let mut registrations: Vec<Registration> = self.registrations.values().cloned().collect();
+        registrations.sort_by(|a, b| a.display_id.cmp(&b.display_id));
+        registrations
     }
 }
 
@@ -199,6 +382,7 @@ impl Default for ServiceAdvertiser {
 pub struct AdvertiserState {
     advertiser: Arc<Mutex<ServiceAdvertiser>>,
     monitor_handle: Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    announcements: SharedAnnouncements,
 }
 
 impl AdvertiserState {
@@ -206,30 +390,39 @@ impl AdvertiserState {
         Self {
             advertiser: Arc::new(Mutex::new(ServiceAdvertiser::new())),
             monitor_handle: Arc::new(Mutex::new(None)),
+            announcements: SharedAnnouncements::default(),
         }
     }
 
-    pub async fn advertise(
-        &self,
-        name: &str,
-        port: u16,
-        display_id: &str,
-        device_id: &str,
-        display_name: Option<&str>,
-        width: Option<u32>,
-        height: Option<u32>,
-        platform: Option<&str>,
-    ) -> Result<(), String> {
-        // First, stop any existing advertising
+    /// What the UDP broadcast listener should answer with, one entry per
+    /// advertised display
+    pub fn announcements(&self) -> SharedAnnouncements {
+        self.announcements.clone()
+    }
+
+    pub async fn advertise(&self, advertisement: Advertisement) -> Result<(), String> {
         let mut adv = self.advertiser.lock().await;
-        adv.advertise(name, port, display_id, device_id, display_name, width, height, platform).await?;
+        adv.advertise(advertisement.clone()).await?;
+        self.announcements.write().unwrap().insert(advertisement.display_id.clone(), DisplayAnnouncement {
+            port: advertisement.port,
+            protocol_version: crate::websocket::types::PROTOCOL_VERSION,
+            display_id: Some(advertisement.display_id),
+            device_id: Some(advertisement.device_id),
+            display_name: advertisement.display_name,
+            width: advertisement.width,
+            height: advertisement.height,
+            platform: advertisement.platform,
+            cert_sha256: advertisement.cert_fingerprint,
+        });
 
         // Get a clone of the daemon for monitoring
         let daemon_clone = adv.service_daemon.clone();
         drop(adv); // Release the lock before spawning the task
 
-        // Start monitoring the daemon to keep it alive and responding
-        if let Some(daemon) = daemon_clone {
+        // Monitor the daemon (once per daemon) to keep it alive and to follow conflict renames
+        let mut handle_guard = self.monitor_handle.lock().await;
+        let monitoring = handle_guard.as_ref().is_some_and(|handle| !handle.is_finished());
+        if let (Some(daemon), false) = (daemon_clone, monitoring) {
             let monitor_receiver = match daemon.monitor() {
                 Ok(r) => r,
                 Err(e) => {
@@ -238,30 +431,59 @@ impl AdvertiserState {
                 }
             };
 
-            // Spawn a task to monitor the daemon and keep it alive
+            let advertiser = self.advertiser.clone();
             let handle = tokio::spawn(async move {
                 info!("Starting mDNS daemon monitor task");
                 while let Ok(event) = monitor_receiver.recv_async().await {
-                    debug!("mDNS daemon monitor event: {:?}", event);
+                    match event {
+                        mdns_sd::DaemonEvent::NameChange(change) => {
+                            warn!("mDNS name conflict on {}: '{}' is now '{}'",
+                                  change.intf_name, change.original, change.new_name);
+                            advertiser.lock().await.apply_name_change(&change.original, &change.new_name);
+                        }
+                        other => debug!("mDNS daemon monitor event: {:?}", other),
+                    }
                 }
                 info!("mDNS daemon monitor task ended");
             });
-
-            // Store the handle and cancel any previous one
-            let mut handle_guard = self.monitor_handle.lock().await;
-            if let Some(old_handle) = handle_guard.take() {
-                old_handle.abort();
-            }
             *handle_guard = Some(handle);
         }
 
         Ok(())
     }
 
+    /// Register every display again with the host's current addresses
+    pub async fn readvertise(&self) -> Result<usize, String> {
+        let mut adv = self.advertiser.lock().await;
+        adv.readvertise().await
+    }
+
+    /// Advertise every display on a new WebSocket port
+    pub async fn set_port(&self, port: u16) -> Result<usize, String> {
+        let mut adv = self.advertiser.lock().await;
+        for announcement in self.announcements.write().unwrap().values_mut() {
+            announcement.port = port;
+        }
+        adv.set_port(port).await
+    }
+
+    /// Stop advertising one display; returns false if it was not advertised
+    pub async fn stop_display(&self, display_id: &str) -> bool {
+        let mut adv = self.advertiser.lock().await;
+        self.announcements.write().unwrap().remove(display_id);
+        adv.stop_display(display_id)
+    }
+
     pub async fn stop(&self) {
         let mut adv = self.advertiser.lock().await;
+        self.announcements.write().unwrap().clear();
         adv.stop();
     }
+
+    /// Every display currently advertised
+    pub async fn registrations(&self) -> Vec<Registration> {
+        self.advertiser.lock().await.registrations()
+    }
 }
 
 impl Default for AdvertiserState {
@@ -269,3 +491,61 @@ impl Default for AdvertiserState {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advertised_addresses() {
+        assert!(is_advertised(&"192.168.1.20".parse().unwrap()));
+        assert!(!is_advertised(&"127.0.0.1".parse().unwrap()));
+        assert!(!is_advertised(&"169.254.10.20".parse().unwrap()));
+        assert!(!is_advertised(&"fe80::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_hostnames_are_derived_per_display() {
+        assert_eq!(hostname_for("3F2A-91bc-77d0"), "mw-3f2a-91bc-77d0.local.");
+        assert_eq!(hostname_for("display one/ü"), "mw-displayone.local.");
+        assert_eq!(hostname_for("--"), "mw-display.local.");
+        assert_ne!(hostname_for("display-a"), hostname_for("display-b"));
+        assert!(hostname_for(&"a".repeat(100)).len() <= "mw-.local.".len() + 32);
+    }
+
+    #[test]
+    fn test_name_changes_follow_registrations() {
+        let mut advertiser = ServiceAdvertiser::new();
+        for display_id in ["display-a", "display-b"] {
+            let fullname = format!("{}.{}", advertiser.instance_name(&format!("Lobby {}", display_id)), SERVICE_TYPE);
+            advertiser.registrations.insert(display_id.to_string(), Registration {
+                display_id: display_id.to_string(),
+                advertised_name: fullname.clone(),
+                fullname,
+                hostname: hostname_for(display_id),
+                port: 8765,
+            });
+        }
+
+        let original = format!("Lobby display-a.{}", SERVICE_TYPE);
+        let renamed = format!("Lobby display-a (2).{}", SERVICE_TYPE);
+        assert!(advertiser.apply_name_change(&original, &renamed));
+        assert!(advertiser.apply_name_change("mw-display-b.local.", "mw-display-b-2.local."));
+        assert!(!advertiser.apply_name_change("someone-else.local.", "someone-else-2.local."));
+
+        let registrations = advertiser.registrations();
+        assert_eq!(registrations[0].advertised_name, renamed);
+        // Unregistering still uses the name it was registered under
+        assert_eq!(registrations[0].fullname, original);
+        assert_eq!(registrations[1].hostname, "mw-display-b-2.local.");
+
+        // Displays sharing a name on this machine get distinct instances
+        assert_eq!(advertiser.instance_name("Lobby display-b"), "Lobby display-b (2)");
+        assert_eq!(advertiser.instance_name("Stage"), "Stage");
+
+        // Stopping one display leaves the other advertised
+        assert!(advertiser.stop_display("display-a"));
+        assert!(!advertiser.stop_display("display-a"));
+        assert_eq!(advertiser.registrations().len(), 1);
+    }
+}
@@ -1,90 +1,338 @@
-use super::discovery::DiscoveredDevice;
-use std::net::{SocketAddr, UdpSocket};
+//! UDP broadcast discovery, for networks that block mDNS
+//!
+//! Version 1 controllers broadcast `MW-DISCOVER` and displays answer
+//! `MW-HERE<port>`, which says nothing about the display. Version 2 adds
+//! `MW-DISCOVER/2`, answered with `MW-HERE/2 ` followed by a JSON
+//! `DisplayAnnouncement` carrying the same fields as the mDNS TXT records.
+//! Controllers send both requests and prefer the richer answer; displays
+//! keep answering `MW-DISCOVER` so older controllers still find them.
+//!
+//! Requests go to the directed broadcast address of every IPv4 interface and
+//! to an IPv6 link-local multicast group on every IPv6 interface, since the
+//! limited broadcast `255.255.255.255` only leaves through one interface on
+//! many systems.
+//!
+//! The announcements are shared with the mDNS advertiser, so both discovery
+//! paths always describe the displays the same way. A host advertising
+//! several displays answers a version 2 request once per display.
+
+use super::discovery::{DiscoveredDevice, Transport};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
-use tracing::{info, error, warn};
+use tracing::{debug, info, error, warn};
 use tokio::net::UdpSocket as TokioUdpSocket;
+use tokio::sync::mpsc::UnboundedSender;
 
 const DISCOVERY_PORT: u16 = 48488; // "MW" in hex + port offset
 const BROADCAST_MESSAGE: &[u8] = b"MW-DISCOVER";
 const RESPONSE_MESSAGE: &[u8] = b"MW-HERE";
+const BROADCAST_MESSAGE_V2: &[u8] = b"MW-DISCOVER/2";
+const RESPONSE_MESSAGE_V2: &[u8] = b"MW-HERE/2 ";
 
-/// Discover displays via UDP broadcast (fallback for networks that block mDNS)
-pub async fn udp_broadcast_discover(timeout_secs: u64) -> Vec<DiscoveredDevice> {
-    info!("Starting UDP broadcast discovery for {} seconds", timeout_secs);
+/// Link-local multicast group displays join for IPv6 discovery ("mw" in hex)
+const MULTICAST_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x6d77);
 
-    let socket = match UdpSocket::bind("0.0.0.0:0") {
-        Ok(s) => s,
-        Err(e) => {
-            error!("Failed to bind UDP socket for broadcast: {}", e);
-            return Vec::new();
-        }
-    };
+/// What a display tells controllers about itself in a version 2 response
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DisplayAnnouncement {
+    /// WebSocket server port
+    pub port: u16,
+    /// WebSocket protocol version the display speaks
+    pub protocol_version: u32,
+    pub display_id: Option<String>,
+    pub device_id: Option<String>,
+    pub display_name: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub platform: Option<String>,
+    /// SHA-256 fingerprint of the wss:// certificate, if TLS is enabled
+    pub cert_sha256: Option<String>,
+}
 
-    socket.set_read_timeout(Some(Duration::from_secs(timeout_secs)))
-        .map_err(|e| {
-            error!("Failed to set socket read timeout: {}", e);
-        })
-        .ok();
+/// Announcements of every advertised display, keyed by display_id
+/// Kept up to date by the advertiser and read by the listener
+pub type SharedAnnouncements = Arc<RwLock<BTreeMap<String, DisplayAnnouncement>>>;
 
-    let broadcast_addr: SocketAddr = "255.255.255.255:48488".parse().unwrap();
+/// The replies a display host sends to a discovery request: one per display
+/// for a version 2 request, with the port the listener serves
+/// Nothing advertised yet still gets an answer, without display details.
+fn responses_for(request: &[u8], ws_port: u16, announcements: &BTreeMap<String, DisplayAnnouncement>) -> Vec<Vec<u8>> {
+    if request == BROADCAST_MESSAGE_V2 {
+        let bare = [DisplayAnnouncement::default()];
+        let announcements: Vec<&DisplayAnnouncement> = if announcements.is_empty() {
+            bare.iter().collect()
+        } else {
+            announcements.values().collect()
+        };
+        announcements
+            .into_iter()
+            .filter_map(|announcement| {
+                let announcement = DisplayAnnouncement {
+                    port: ws_port,
+                    protocol_version: crate::websocket::types::PROTOCOL_VERSION,
+                    ..announcement.clone()
+                };
+                let json = serde_json::to_vec(&announcement).ok()?;
+                Some([RESPONSE_MESSAGE_V2, json.as_slice()].concat())
+            })
+            .collect()
+    } else if request == BROADCAST_MESSAGE {
+        vec![format!("{}{}", String::from_utf8_lossy(RESPONSE_MESSAGE), ws_port).into_bytes()]
+    } else {
+        Vec::new()
+    }
+}
 
-    // Send broadcast message
-    if let Err(e) = socket.send_to(BROADCAST_MESSAGE, broadcast_addr) {
-        error!("Failed to send UDP broadcast: {}", e);
-        return Vec::new();
+/// Parse a display's reply to a discovery request
+/// Legacy replies only carry the port, so the display_id is derived from the address
+fn parse_response(response: &[u8], ip: IpAddr) -> Option<DiscoveredDevice> {
+    if let Some(json) = response.strip_prefix(RESPONSE_MESSAGE_V2) {
+        let announcement: DisplayAnnouncement = match serde_json::from_slice(json) {
+            Ok(announcement) => announcement,
+            Err(e) => {
+                warn!("Invalid UDP discovery response from {}: {}", ip, e);
+                return None;
+            }
+        };
+        let display_id = announcement
+            .display_id
+            .or_else(|| announcement.device_id.clone())
+            .unwrap_or_else(|| fallback_display_id(ip));
+        return Some(DiscoveredDevice {
+            name: format!("Display@{}", ip),
+            host: ip.to_string(),
+            port: announcement.port,
+            service_type: "udp-broadcast".to_string(),
+            display_id,
+            device_id: announcement.device_id,
+            display_name: announcement.display_name,
+            width: announcement.width,
+            height: announcement.height,
+            platform: announcement.platform,
+            cert_fingerprint: announcement.cert_sha256.filter(|fp| !fp.is_empty()),
+            protocol_version: Some(announcement.protocol_version),
+            interface: None,
+            transports: vec![Transport::Udp],
+        });
     }
 
-    info!("Sent UDP broadcast to {}", broadcast_addr);
+    // Parse legacy response: "MW-HERE<port>"
+    let port = response.strip_prefix(RESPONSE_MESSAGE)?;
+    let port = String::from_utf8_lossy(port).trim().parse::<u16>().unwrap_or(8080);
+    Some(DiscoveredDevice {
+        name: format!("Display@{}", ip),
+        host: ip.to_string(),
+        port,
+        service_type: "udp-broadcast".to_string(),
+        display_id: fallback_display_id(ip),
+        device_id: None, // Legacy responses don't include device ID
+        display_name: None,
+        width: None,
+        height: None,
+        platform: None, // Legacy responses don't include platform info
+        cert_fingerprint: None,
+        protocol_version: None,
+        interface: None,
+        transports: vec![Transport::Udp],
+    })
+}
 
-    let mut devices = Vec::new();
-    let mut buf = [0u8; 1024];
-    let start = std::time::Instant::now();
-    let timeout = Duration::from_secs(timeout_secs);
+/// Prefix of display IDs made up for legacy responders
+pub const FALLBACK_ID_PREFIX: &str = "udp-fallback-";
 
-    // Listen for responses
-    while start.elapsed() < timeout {
-        match socket.recv_from(&mut buf) {
-            Ok((len, addr)) => {
-                if len >= RESPONSE_MESSAGE.len()
-                    && &buf[..RESPONSE_MESSAGE.len()] == RESPONSE_MESSAGE {
-                    // Parse response: "MW-HERE<port>"
-                    let response = String::from_utf8_lossy(&buf[RESPONSE_MESSAGE.len()..len]);
-                    let port = response.trim().parse::<u16>().unwrap_or(8080);
-
-                    info!("UDP broadcast response from {}: port {}", addr.ip(), port);
-
-                    // UDP broadcast doesn't include display_id, generate a fallback from IP
-                    let fallback_display_id = format!("udp-fallback-{}", addr.ip().to_string().replace('.', "-"));
-                    devices.push(DiscoveredDevice {
-                        name: format!("Display@{}", addr.ip()),
-                        host: addr.ip().to_string(),
-                        port,
-                        service_type: "udp-broadcast".to_string(),
-                        display_id: fallback_display_id,
-                        device_id: None, // UDP broadcast doesn't include device ID
-                        display_name: None,
-                        width: None,
-                        height: None,
-                        platform: None, // UDP broadcast doesn't include platform info
-                    });
-                }
+/// Legacy responses don't include display_id, generate a fallback from IP
+fn fallback_display_id(ip: IpAddr) -> String {
+    format!("{}{}", FALLBACK_ID_PREFIX, ip.to_string().replace(['.', ':'], "-"))
+}
+
+/// Host to connect to for an answer from `addr`
+/// Link-local IPv6 addresses only work with their zone, e.g. `fe80::2%eth0`
+pub fn scoped_host(addr: SocketAddr, interface: Option<&str>) -> String {
+    match (addr, interface) {
+        (SocketAddr::V6(v6), Some(name)) if v6.scope_id() != 0 => format!("{}%{}", v6.ip(), name),
+        (SocketAddr::V6(v6), None) if v6.scope_id() != 0 => format!("{}%{}", v6.ip(), v6.scope_id()),
+        _ => addr.ip().to_string(),
+    }
+}
+
+/// Where to send discovery requests on one interface
+#[derive(Debug, Clone, PartialEq)]
+struct Probe {
+    /// Interface name, or None for the limited broadcast fallback
+    interface: Option<String>,
+    /// Address to bind the probing socket to
+    local: SocketAddr,
+    /// Directed broadcast or multicast address to send to
+    target: SocketAddr,
+    /// Interface index, for IPv6 multicast
+    index: u32,
+}
+
+/// The probe for one interface address, if it can reach other hosts
+///
+/// IPv4 addresses get their subnet's directed broadcast; IPv6 link-local
+/// addresses get the multicast group, scoped to the interface.
+fn probe_for(name: &str, addr: &if_addrs::IfAddr, index: Option<u32>) -> Option<Probe> {
+    match addr {
+        if_addrs::IfAddr::V4(v4) => {
+            if v4.ip.is_loopback() {
+                return None;
             }
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => {
-                break; // Timeout
+            let broadcast = v4
+                .broadcast
+                .unwrap_or_else(|| Ipv4Addr::from(u32::from(v4.ip) | !u32::from(v4.netmask)));
+            // Point-to-point links (e.g. VPNs) have no one to broadcast to
+            if broadcast == v4.ip {
+                return None;
             }
-            Err(e) => {
+            Some(Probe {
+                interface: Some(name.to_string()),
+                local: SocketAddr::new(IpAddr::V4(v4.ip), 0),
+                target: SocketAddr::new(IpAddr::V4(broadcast), DISCOVERY_PORT),
+                index: 0,
+            })
+        }
+        if_addrs::IfAddr::V6(v6) => {
+            let index = index?;
+            let link_local = (v6.ip.segments()[0] & 0xffc0) == 0xfe80;
+            if v6.ip.is_loopback() || !link_local {
+                return None;
+            }
+            Some(Probe {
+                interface: Some(name.to_string()),
+                local: SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0)),
+                target: SocketAddr::V6(SocketAddrV6::new(MULTICAST_GROUP_V6, DISCOVERY_PORT, 0, index)),
+                index,
+            })
+        }
+    }
+}
+
+/// One probe per interface and address family
+fn probes() -> Vec<Probe> {
+    let interfaces = if_addrs::get_if_addrs().unwrap_or_else(|e| {
+        warn!("Failed to list network interfaces: {}", e);
+        Vec::new()
+    });
+    let mut probes: Vec<Probe> = Vec::new();
+    for iface in &interfaces {
+        if let Some(probe) = probe_for(&iface.name, &iface.addr, iface.index) {
+            // An interface may have several IPv6 link-local addresses, one multicast probe is enough
+            if !probes.contains(&probe) {
+                probes.push(probe);
+            }
+        }
+    }
+    if !probes.iter().any(|probe| probe.target.is_ipv4()) {
+        probes.push(Probe {
+            interface: None,
+            local: SocketAddr::from(([0, 0, 0, 0], 0)),
+            target: SocketAddr::from(([255, 255, 255, 255], DISCOVERY_PORT)),
+            index: 0,
+        });
+    }
+    probes
+}
+
+fn probe_socket(probe: &Probe) -> std::io::Result<TokioUdpSocket> {
+    let socket = Socket::new(Domain::for_address(probe.local), Type::DGRAM, Some(Protocol::UDP))?;
+    if probe.target.is_ipv4() {
+        socket.set_broadcast(true)?;
+    } else {
+        socket.set_multicast_if_v6(probe.index)?;
+    }
+    socket.bind(&probe.local.into())?;
+    socket.set_nonblocking(true)?;
+    TokioUdpSocket::from_std(socket.into())
+}
+
+/// Send discovery requests on one interface and pass the answers to
+/// `found` until `deadline`; returns how many answers were received
+async fn run_probe(probe: Probe, deadline: tokio::time::Instant, found: UnboundedSender<DiscoveredDevice>) -> usize {
+    let socket = match probe_socket(&probe) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Failed to open UDP discovery socket on {:?}: {}", probe.interface, e);
+            return 0;
+        }
+    };
+
+    // Version 1 displays only answer the legacy request, and only over IPv4
+    let requests: &[&[u8]] = if probe.target.is_ipv4() {
+        &[BROADCAST_MESSAGE_V2, BROADCAST_MESSAGE]
+    } else {
+        &[BROADCAST_MESSAGE_V2]
+    };
+    for request in requests {
+        if let Err(e) = socket.send_to(request, probe.target).await {
+            warn!("Failed to send UDP discovery to {} on {:?}: {}", probe.target, probe.interface, e);
+            return 0;
+        }
+    }
+    info!("Sent UDP discovery to {} on {:?}", probe.target, probe.interface);
+
+    let mut answers = 0;
+    let mut buf = [0u8; 2048];
+    loop {
+        let received = tokio::select! {
+            received = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)) => received,
+            // Nobody is waiting for more answers
+            _ = found.closed() => break,
+        };
+        match received {
+            Ok(Ok((len, addr))) => {
+                if let Some(mut device) = parse_response(&buf[..len], addr.ip()) {
+                    info!("UDP broadcast response from {} on {:?}: port {} (display_id: {})",
+                          addr.ip(), probe.interface, device.port, device.display_id);
+                    device.host = scoped_host(addr, probe.interface.as_deref());
+                    device.interface = probe.interface.clone();
+                    answers += 1;
+                    let _ = found.send(device);
+                }
+            }
+            Ok(Err(e)) => {
                 warn!("Error receiving UDP broadcast response: {}", e);
             }
+            Err(_) => break, // Timeout
         }
     }
+    answers
+}
 
-    info!("UDP broadcast discovery complete, found {} devices", devices.len());
-    devices
+/// Discover displays via UDP broadcast (fallback for networks that block mDNS)
+/// Probes every interface, so displays on each attached network are found.
+/// Every answer is sent to `found` as it arrives, so a display answering on
+/// several interfaces or both protocol versions shows up more than once
+/// (see `DeviceSet`)
+pub async fn udp_broadcast_discover(timeout_secs: u64, found: UnboundedSender<DiscoveredDevice>) {
+    info!("Starting UDP broadcast discovery for {} seconds", timeout_secs);
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(timeout_secs);
+    let mut probing = tokio::task::JoinSet::new();
+    for probe in probes() {
+        probing.spawn(run_probe(probe, deadline, found.clone()));
+    }
+    drop(found);
+
+    let mut answers = 0;
+    while let Some(result) = probing.join_next().await {
+        match result {
+            Ok(count) => answers += count,
+            Err(e) => error!("UDP discovery probe failed: {}", e),
+        }
+    }
+
+    info!("UDP broadcast discovery complete, {} answers", answers);
 }
 
 /// Start a UDP broadcast listener that responds to discovery requests
 /// This should be called on the display (Android TV) side
-pub fn start_udp_listener(port: u16, ws_port: u16) -> tokio::task::JoinHandle<()> {
+/// Version 2 responses describe the displays in `announcements` when the request arrives
+pub fn start_udp_listener(port: u16, ws_port: u16, announcements: SharedAnnouncements) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
         let socket = match TokioUdpSocket::bind(&format!("0.0.0.0:{}", port)).await {
             Ok(s) => s,
@@ -97,26 +345,237 @@ pub fn start_udp_listener(port: u16, ws_port: u16) -> tokio::task::JoinHandle<()
 
         info!("UDP broadcast listener started on port {}", port);
 
-        let mut buf = [0u8; 1024];
+        let requests = answer_requests(socket, ws_port, announcements.clone());
+        match bind_multicast_v6(port) {
+            Ok(socket_v6) => {
+                info!("Listening for IPv6 discovery on [{}]:{}", MULTICAST_GROUP_V6, port);
+                tokio::join!(requests, answer_requests(socket_v6, ws_port, announcements));
+            }
+            Err(e) => {
+                warn!("IPv6 discovery unavailable: {}", e);
+                requests.await;
+            }
+        }
+    })
+}
+
+/// The display's UDP listener, restarted when the network changes
+///
+/// The IPv6 socket joins the discovery group on the interfaces present when
+/// it is bound, so new interfaces are only covered after a restart.
+#[derive(Default)]
+pub struct UdpListener {
+    running: tokio::sync::Mutex<Option<RunningListener>>,
+}
 
-        loop {
-            match socket.recv_from(&mut buf).await {
-                Ok((len, addr)) => {
-                    if len == BROADCAST_MESSAGE.len()
-                        && &buf[..len] == BROADCAST_MESSAGE {
-                        info!("Received discovery request from {}", addr);
+struct RunningListener {
+    port: u16,
+    ws_port: u16,
+    announcements: SharedAnnouncements,
+    handle: tokio::task::JoinHandle<()>,
+}
 
-                        // Respond with our WebSocket port
-                        let response = format!("{}{}", String::from_utf8_lossy(RESPONSE_MESSAGE), ws_port);
-                        if let Err(e) = socket.send_to(response.as_bytes(), addr).await {
-                            error!("Failed to send UDP response to {}: {}", addr, e);
-                        }
-                    }
+impl UdpListener {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start listening, replacing any listener already running
+    pub async fn start(&self, port: u16, ws_port: u16, announcements: SharedAnnouncements) {
+        let mut running = self.running.lock().await;
+        if let Some(previous) = running.take() {
+            previous.handle.abort();
+            // Wait for the sockets to close so the port can be bound again
+            let _ = previous.handle.await;
+        }
+        let handle = start_udp_listener(port, ws_port, announcements.clone());
+        *running = Some(RunningListener { port, ws_port, announcements, handle });
+    }
+
+    /// Bind again to pick up interface changes; returns false if not started
+    pub async fn restart(&self) -> bool {
+        self.restart_with(None).await
+    }
+
+    /// Answer with a new WebSocket port; returns false if not started
+    pub async fn set_ws_port(&self, ws_port: u16) -> bool {
+        self.restart_with(Some(ws_port)).await
+    }
+
+    async fn restart_with(&self, new_ws_port: Option<u16>) -> bool {
+        let (port, ws_port, announcements) = {
+            let running = self.running.lock().await;
+            let Some(listener) = running.as_ref() else {
+                return false;
+            };
+            (listener.port, new_ws_port.unwrap_or(listener.ws_port), listener.announcements.clone())
+        };
+        info!("Restarting UDP broadcast listener on port {} for WS port {}", port, ws_port);
+        self.start(port, ws_port, announcements).await;
+        true
+    }
+}
+
+/// Bind an IPv6-only socket on `port` and join the discovery group on every interface
+fn bind_multicast_v6(port: u16) -> std::io::Result<TokioUdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    // Leave the IPv4 port to the broadcast listener
+    socket.set_only_v6(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+
+    let mut indexes: Vec<u32> = if_addrs::get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|iface| iface.ip().is_ipv6() && !iface.is_loopback())
+        .filter_map(|iface| iface.index)
+        .collect();
+    indexes.sort_unstable();
+    indexes.dedup();
+
+    let mut joined = 0;
+    for index in indexes {
+        match socket.join_multicast_v6(&MULTICAST_GROUP_V6, index) {
+            Ok(()) => joined += 1,
+            Err(e) => debug!("Failed to join IPv6 discovery group on interface {}: {}", index, e),
+        }
+    }
+    if joined == 0 {
+        // Let the system pick the interface
+        socket.join_multicast_v6(&MULTICAST_GROUP_V6, 0)?;
+    }
+
+    socket.set_nonblocking(true)?;
+    TokioUdpSocket::from_std(socket.into())
+}
+
+/// Answer discovery requests arriving on `socket`
+async fn answer_requests(socket: TokioUdpSocket, ws_port: u16, announcements: SharedAnnouncements) {
+    let mut buf = [0u8; 1024];
+
+    loop {
+        match socket.recv_from(&mut buf).await {
+            Ok((len, addr)) => {
+                let responses = responses_for(&buf[..len], ws_port, &announcements.read().unwrap());
+                if !responses.is_empty() {
+                    info!("Received discovery request from {}", addr);
                 }
-                Err(e) => {
-                    error!("UDP listener error: {}", e);
+                for response in responses {
+                    if let Err(e) = socket.send_to(&response, addr).await {
+                        error!("Failed to send UDP response to {}: {}", addr, e);
+                    }
                 }
             }
+            Err(e) => {
+                error!("UDP listener error: {}", e);
+            }
         }
-    })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v2_response_carries_display_info() {
+        let announcement = DisplayAnnouncement {
+            port: 0,
+            protocol_version: 0,
+            display_id: Some("display-1".to_string()),
+            device_id: Some("device-1".to_string()),
+            display_name: Some("Lobby".to_string()),
+            width: Some(1920),
+            height: Some(1080),
+            platform: Some("Fire OS (Android 9)".to_string()),
+            cert_sha256: None,
+        };
+        let ip: IpAddr = "192.168.1.20".parse().unwrap();
+        let mut announcements = BTreeMap::from([("display-1".to_string(), announcement.clone())]);
+
+        // The listener fills in the port it serves and the protocol version
+        let responses = responses_for(BROADCAST_MESSAGE_V2, 8765, &announcements);
+        assert_eq!(responses.len(), 1);
+        let device = parse_response(&responses[0], ip).unwrap();
+        assert_eq!(device.display_id, "display-1");
+        assert_eq!(device.device_id.as_deref(), Some("device-1"));
+        assert_eq!(device.display_name.as_deref(), Some("Lobby"));
+        assert_eq!((device.width, device.height), (Some(1920), Some(1080)));
+        assert_eq!(device.protocol_version, Some(crate::websocket::types::PROTOCOL_VERSION));
+        assert_eq!((device.host.as_str(), device.port), ("192.168.1.20", 8765));
+
+        // One answer per display advertised from this host
+        let second = DisplayAnnouncement { display_id: Some("display-2".to_string()), ..announcement };
+        announcements.insert("display-2".to_string(), second);
+        let display_ids: Vec<String> = responses_for(BROADCAST_MESSAGE_V2, 8765, &announcements)
+            .iter()
+            .map(|response| parse_response(response, ip).unwrap().display_id)
+            .collect();
+        assert_eq!(display_ids, vec!["display-1", "display-2"]);
+
+        assert!(responses_for(b"MW-DISCOVER/3", 8765, &announcements).is_empty());
+        assert!(parse_response(b"MW-HERE/2 {not json", ip).is_none());
+        assert!(parse_response(b"HELLO", ip).is_none());
+    }
+
+    #[test]
+    fn test_legacy_requests_and_responses() {
+        let ip: IpAddr = "192.168.1.20".parse().unwrap();
+
+        // Older controllers still get the response they understand, once,
+        // even before anything is advertised
+        assert_eq!(responses_for(BROADCAST_MESSAGE, 8765, &BTreeMap::new()), vec![b"MW-HERE8765".to_vec()]);
+        assert_eq!(responses_for(BROADCAST_MESSAGE_V2, 8765, &BTreeMap::new()).len(), 1);
+
+        let device = parse_response(b"MW-HERE8765", ip).unwrap();
+        assert_eq!(device.port, 8765);
+        assert_eq!(device.display_id, "udp-fallback-192-168-1-20");
+        assert_eq!(device.protocol_version, None);
+    }
+
+    #[test]
+    fn test_link_local_answers_keep_their_zone() {
+        let v4: SocketAddr = "192.168.1.20:48488".parse().unwrap();
+        assert_eq!(scoped_host(v4, Some("eth0")), "192.168.1.20");
+        let link_local = SocketAddr::V6(SocketAddrV6::new("fe80::2".parse().unwrap(), 48488, 0, 3));
+        assert_eq!(scoped_host(link_local, Some("eth0")), "fe80::2%eth0");
+        assert_eq!(scoped_host(link_local, None), "fe80::2%3");
+        let global = SocketAddr::V6(SocketAddrV6::new("2001:db8::2".parse().unwrap(), 48488, 0, 0));
+        assert_eq!(scoped_host(global, Some("eth0")), "2001:db8::2");
+    }
+
+    #[test]
+    fn test_probes_per_interface() {
+        let v4 = |ip: [u8; 4], netmask: [u8; 4], broadcast: Option<[u8; 4]>| {
+            if_addrs::IfAddr::V4(if_addrs::Ifv4Addr {
+                ip: ip.into(),
+                netmask: netmask.into(),
+                prefixlen: u32::from(Ipv4Addr::from(netmask)).count_ones() as u8,
+                broadcast: broadcast.map(Ipv4Addr::from),
+            })
+        };
+
+        let wifi = probe_for("wlan0", &v4([192, 168, 1, 20], [255, 255, 255, 0], None), Some(3)).unwrap();
+        assert_eq!(wifi.interface.as_deref(), Some("wlan0"));
+        assert_eq!(wifi.local, "192.168.1.20:0".parse().unwrap());
+        assert_eq!(wifi.target, "192.168.1.255:48488".parse().unwrap());
+        let wired = probe_for("eth0", &v4([10, 0, 4, 7], [255, 255, 252, 0], Some([10, 0, 7, 255])), Some(2)).unwrap();
+        assert_eq!(wired.target, "10.0.7.255:48488".parse().unwrap());
+
+        assert!(probe_for("lo", &v4([127, 0, 0, 1], [255, 0, 0, 0], None), Some(1)).is_none());
+        assert!(probe_for("tun0", &v4([10, 8, 0, 2], [255, 255, 255, 255], None), Some(4)).is_none());
+
+        let v6 = |ip: &str| {
+            if_addrs::IfAddr::V6(if_addrs::Ifv6Addr {
+                ip: ip.parse().unwrap(),
+                netmask: Ipv6Addr::UNSPECIFIED,
+                prefixlen: 64,
+                broadcast: None,
+            })
+        };
+        let multicast = probe_for("eth0", &v6("fe80::1c2b:3aff:fe4d:5e6f"), Some(2)).unwrap();
+        assert_eq!(multicast.target, SocketAddr::V6(SocketAddrV6::new(MULTICAST_GROUP_V6, DISCOVERY_PORT, 0, 2)));
+        assert_eq!(multicast.index, 2);
+        assert!(probe_for("eth0", &v6("2001:db8::1"), Some(2)).is_none());
+        assert!(probe_for("lo", &v6("::1"), Some(1)).is_none());
+    }
 }
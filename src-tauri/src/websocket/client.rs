@@ -0,0 +1,497 @@
+//! WebSocket client with automatic reconnect
+//!
+//! Connects to another device's server (usually a display found over mDNS,
+//! see `mdns::DiscoveredDevice`) and keeps the connection up independently of
+//! any webview:
+//! - reconnects with exponential backoff and jitter after any failure
+//! - sends the `hello` again on every connection, so a display registration
+//!   survives reconnects and the server replays its current state
+//! - answers `clock_sync` requests and, when registered as a display,
+//!   acknowledges sequenced updates once they have been handed to a subscriber
+//! - gives up on a connection once the server has been silent for
+//!   `READ_TIMEOUT` (servers ping every 10 seconds)
+//!
+//! Incoming messages and connection changes are published as `ClientEvent`s;
+//! `commands::connect_to_display` turns them into Tauri events.
+
+use crate::mdns::DiscoveredDevice;
+use crate::websocket::clock;
+use crate::websocket::codec::{self, FRAME_MESSAGE};
+use crate::websocket::events::EVENT_CHANNEL_CAPACITY;
+use crate::websocket::tls::pinned_connector;
+use crate::websocket::types::{AckData, Capability, Envelope, HelloData, WsMessage};
+use futures_util::sink::SinkExt;
+use futures_util::stream::StreamExt;
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// Capabilities this client implements, for its `hello`
+pub const CLIENT_CAPABILITIES: [Capability; 3] = [Capability::ClockSync, Capability::MsgPack, Capability::Deflate];
+
+/// Delay before the first reconnect attempt
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Upper bound for the reconnect delay
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Time allowed for TCP, TLS and the WebSocket handshake together
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Silence after which the connection is considered dead
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Exponential backoff with jitter, so a room full of displays does not
+/// reconnect in lockstep after the controller restarts
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max, attempt: 0 }
+    }
+
+    /// Delay before the next attempt: between half and all of
+    /// `initial * 2^attempt`, capped at `max`
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+        let half = ceiling / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+
+    /// Start over after a successful connection
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// Where to connect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientTarget {
+    pub host: String,
+    pub port: u16,
+    /// Certificate to pin; the connection uses wss:// when set
+    pub cert_fingerprint: Option<String>,
+    /// Pairing token presented in the handshake
+    pub token: Option<String>,
+}
+
+impl ClientTarget {
+    pub fn from_device(device: &DiscoveredDevice, token: Option<String>) -> Self {
+        Self {
+            host: device.host.clone(),
+            port: device.port,
+            cert_fingerprint: device.cert_fingerprint.clone(),
+            token,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        let scheme = if self.cert_fingerprint.is_some() { "wss" } else { "ws" };
+        let host = if self.host.contains(':') {
+            // The zone of a link-local address is percent-encoded (RFC 6874)
+            format!("[{}]", self.host.replace('%', "%25"))
+        } else {
+            self.host.clone()
+        };
+        match &self.token {
+            Some(token) => format!("{}://{}:{}/?token={}", scheme, host, self.port, urlencoding::encode(token)),
+            None => format!("{}://{}:{}/", scheme, host, self.port),
+        }
+    }
+
+    /// Socket address of a link-local IPv6 host with a zone (`fe80::2%eth0`
+    /// or `fe80::2%3`), which name resolution cannot be relied on for
+    fn scoped_addr(&self) -> Option<SocketAddr> {
+        let (ip, zone) = self.host.split_once('%')?;
+        let ip: Ipv6Addr = ip.parse().ok()?;
+        let scope_id = zone.parse::<u32>().ok().or_else(|| {
+            if_addrs::get_if_addrs()
+                .ok()?
+                .into_iter()
+                .find(|iface| iface.name == zone)
+                .and_then(|iface| iface.index)
+        })?;
+        Some(SocketAddr::V6(SocketAddrV6::new(ip, self.port, 0, scope_id)))
+    }
+
+    /// Host without any IPv6 zone
+    fn unscoped_host(&self) -> &str {
+        self.host.split('%').next().unwrap_or(&self.host)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// The handshake succeeded and the hello was sent
+    Connected { url: String },
+    /// A message from the server (`clock_sync` is handled internally)
+    Message(Envelope),
+    /// The connection failed or dropped; the next attempt follows after `retry_in`
+    Disconnected { reason: String, retry_in: Duration },
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+type Socket = WebSocketStream<Box<dyn Stream>>;
+
+/// Open client connections by display_id (managed Tauri state)
+pub type ClientConnections = HashMap<String, WebSocketClient>;
+
+/// A connection to one server that reconnects until closed
+pub struct WebSocketClient {
+    outgoing: mpsc::UnboundedSender<WsMessage>,
+    events: broadcast::Sender<ClientEvent>,
+    connected: Arc<AtomicBool>,
+    shutdown: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl WebSocketClient {
+    /// Start connecting in the background; `hello` is sent on every connection
+    pub fn connect(target: ClientTarget, hello: HelloData) -> Self {
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let connected = Arc::new(AtomicBool::new(false));
+        let shutdown = Arc::new(Notify::new());
+
+        let task = tokio::spawn(run(
+            target,
+            hello,
+            outgoing_rx,
+            events.clone(),
+            connected.clone(),
+            shutdown.clone(),
+        ));
+
+        Self { outgoing, events, connected, shutdown, task }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Send a message over the current connection
+    ///
+    /// Fails while disconnected rather than queueing: an update sent after
+    /// the reconnect would be stale by then.
+    pub fn send(&self, message: WsMessage) -> Result<(), String> {
+        if !self.is_connected() {
+            return Err("Not connected".to_string());
+        }
+        self.outgoing.send(message).map_err(|_| "Client closed".to_string())
+    }
+
+    /// Close the connection and stop reconnecting
+    pub async fn close(self) {
+        self.shutdown.notify_one();
+        let mut task = self.task;
+        if tokio::time::timeout(Duration::from_secs(1), &mut task).await.is_err() {
+            task.abort();
+        }
+    }
+}
+
+/// Connect, run the session, and reconnect until shut down
+async fn run(
+    target: ClientTarget,
+    hello: HelloData,
+    mut outgoing: mpsc::UnboundedReceiver<WsMessage>,
+    events: broadcast::Sender<ClientEvent>,
+    connected: Arc<AtomicBool>,
+    shutdown: Arc<Notify>,
+) {
+    let url = target.url();
+    let mut backoff = Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF);
+    loop {
+        let connection = tokio::select! {
+            _ = shutdown.notified() => return,
+            result = tokio::time::timeout(CONNECT_TIMEOUT, open(&target, &url)) => {
+                result.unwrap_or_else(|_| Err("connection timed out".to_string()))
+            }
+        };
+
+        let reason = match connection {
+            Ok(socket) => {
+                tracing::info!("Connected to {}", url);
+                backoff.reset();
+                // Anything queued for the previous connection is stale
+                while outgoing.try_recv().is_ok() {}
+                connected.store(true, Ordering::SeqCst);
+                let _ = events.send(ClientEvent::Connected { url: url.clone() });
+
+                let result = session(socket, &hello, &mut outgoing, &events, &shutdown).await;
+                connected.store(false, Ordering::SeqCst);
+                match result {
+                    Ok(()) => return,
+                    Err(reason) => reason,
+                }
+            }
+            Err(reason) => reason,
+        };
+
+        let retry_in = backoff.next_delay();
+        tracing::warn!("Connection to {} lost ({}); retrying in {:?}", url, reason, retry_in);
+        let _ = events.send(ClientEvent::Disconnected { reason, retry_in });
+
+        tokio::select! {
+            _ = shutdown.notified() => return,
+            _ = tokio::time::sleep(retry_in) => {}
+        }
+    }
+}
+
+/// Open the TCP connection, the TLS session if pinned, and the WebSocket
+async fn open(target: &ClientTarget, url: &str) -> Result<Socket, String> {
+    let tcp = match target.scoped_addr() {
+        Some(addr) => TcpStream::connect(addr).await,
+        None => TcpStream::connect((target.host.as_str(), target.port)).await,
+    }
+    .map_err(|e| format!("Failed to connect: {}", e))?;
+    let _ = tcp.set_nodelay(true);
+
+    let stream: Box<dyn Stream> = match &target.cert_fingerprint {
+        Some(fingerprint) => {
+            // The pinned verifier ignores the name; it only has to be valid
+            let name = ServerName::try_from(target.unscoped_host().to_string())
+                .map_err(|e| format!("Invalid host {}: {}", target.host, e))?;
+            let tls = pinned_connector(fingerprint)?
+                .connect(name, tcp)
+                .await
+                .map_err(|e| format!("TLS handshake failed: {}", e))?;
+            Box::new(tls)
+        }
+        None => Box::new(tcp),
+    };
+
+    let (socket, _) = tokio_tungstenite::client_async(url, stream)
+        .await
+        .map_err(|e| format!("WebSocket handshake failed: {}", e))?;
+    Ok(socket)
+}
+
+/// Exchange messages until the connection drops (Err) or the client is closed (Ok)
+async fn session(
+    socket: Socket,
+    hello: &HelloData,
+    outgoing: &mut mpsc::UnboundedReceiver<WsMessage>,
+    events: &broadcast::Sender<ClientEvent>,
+    shutdown: &Notify,
+) -> Result<(), String> {
+    let (mut sink, mut stream) = socket.split();
+    let is_display = hello.display_id.is_some();
+
+    send(&mut sink, &WsMessage::Hello(hello.clone())).await?;
+
+    loop {
+        tokio::select! {
+            _ = shutdown.notified() => {
+                let _ = sink.send(Message::Close(None)).await;
+                return Ok(());
+            }
+            message = outgoing.recv() => match message {
+                Some(message) => send(&mut sink, &message).await?,
+                None => return Ok(()),
+            },
+            frame = tokio::time::timeout(READ_TIMEOUT, stream.next()) => match frame {
+                Err(_) => return Err(format!("no frames for {:?}", READ_TIMEOUT)),
+                Ok(None) => return Err("connection closed".to_string()),
+                Ok(Some(Err(e))) => return Err(format!("receive error: {}", e)),
+                Ok(Some(Ok(Message::Close(frame)))) => {
+                    let reason = frame.map(|f| f.reason.to_string()).unwrap_or_default();
+                    return Err(format!("closed by server: {}", reason));
+                }
+                Ok(Some(Ok(frame @ (Message::Text(_) | Message::Binary(_))))) => {
+                    let received_at = clock::now_ms();
+                    let Some(text) = message_text(frame) else {
+                        continue;
+                    };
+                    let envelope = match serde_json::from_str::<Envelope>(&text) {
+                        Ok(envelope) => envelope,
+                        Err(e) => {
+                            tracing::warn!("Ignoring invalid message from server: {}", e);
+                            continue;
+                        }
+                    };
+                    match &envelope.message {
+                        WsMessage::ClockSync(sync) => {
+                            if sync.receive.is_none() {
+                                let answer = clock::answer(sync, received_at, clock::now_ms());
+                                send(&mut sink, &WsMessage::ClockSync(answer)).await?;
+                            }
+                            continue;
+                        }
+                        WsMessage::Error(error) => {
+                            tracing::warn!("Server rejected a message: {:?} {}", error.code, error.message);
+                        }
+                        _ => {}
+                    }
+                    // Only acknowledge what someone is there to apply
+                    let seq = envelope.seq;
+                    let applied = events.send(ClientEvent::Message(envelope)).is_ok();
+                    if let (true, true, Some(seq)) = (is_display, applied, seq) {
+                        send(&mut sink, &WsMessage::Ack(AckData { seq })).await?;
+                    }
+                }
+                // Pongs go out automatically
+                Ok(Some(Ok(_))) => {}
+            },
+        }
+    }
+}
+
+/// JSON text of a message frame, whatever its encoding (see `codec`)
+///
+/// None for media chunk frames, which are not handled here, and invalid frames.
+fn message_text(frame: Message) -> Option<String> {
+    match frame {
+        Message::Text(text) => Some(text),
+        Message::Binary(data) if data.first() == Some(&FRAME_MESSAGE) => match codec::decode(&data) {
+            Ok(text) => Some(text),
+            Err(e) => {
+                tracing::warn!("Ignoring invalid message frame from server: {}", e);
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+async fn send<S>(sink: &mut S, message: &WsMessage) -> Result<(), String>
+where
+    S: SinkExt<Message> + Unpin,
+    S::Error: std::fmt::Display,
+{
+    let json = serde_json::to_string(message).map_err(|e| format!("Failed to serialize message: {}", e))?;
+    sink.send(Message::Text(json)).await.map_err(|e| format!("send error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::types::SlideData;
+    use crate::websocket::WebSocketServer;
+
+    async fn next_event(events: &mut broadcast::Receiver<ClientEvent>) -> ClientEvent {
+        tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_backoff_grows_with_jitter_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        let delays: Vec<Duration> = (0..6).map(|_| backoff.next_delay()).collect();
+        for (attempt, delay) in delays.iter().enumerate() {
+            let ceiling = Duration::from_millis(100 * 2u64.pow(attempt as u32)).min(Duration::from_secs(1));
+            assert!(*delay >= ceiling / 2 && *delay <= ceiling, "attempt {}: {:?}", attempt, delay);
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_target_url() {
+        let mut target = ClientTarget {
+            host: "fe80::1".to_string(),
+            port: 8080,
+            cert_fingerprint: None,
+            token: Some("a b".to_string()),
+        };
+        assert_eq!(target.url(), "ws://[fe80::1]:8080/?token=a%20b");
+        target.host = "fe80::2%eth0".to_string();
+        assert_eq!(target.url(), "ws://[fe80::2%25eth0]:8080/?token=a%20b");
+        assert_eq!(target.unscoped_host(), "fe80::2");
+        target.host = "fe80::2%3".to_string();
+        assert_eq!(target.scoped_addr(), Some("[fe80::2%3]:8080".parse().unwrap()));
+        target.host = "192.168.1.20".to_string();
+        assert_eq!(target.scoped_addr(), None);
+        target.token = None;
+        target.cert_fingerprint = Some("AB:CD".to_string());
+        assert_eq!(target.url(), "wss://192.168.1.20:8080/");
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_and_registers_again() {
+        let mut server = WebSocketServer::new();
+        let port = server.start(0).await.unwrap();
+
+        let target = ClientTarget {
+            host: "127.0.0.1".to_string(),
+            port,
+            cert_fingerprint: None,
+            token: None,
+        };
+        let hello = HelloData {
+            display_id: Some("display-a".to_string()),
+            ..Default::default()
+        };
+        let client = WebSocketClient::connect(target, hello);
+        let mut events = client.subscribe();
+        assert!(matches!(next_event(&mut events).await, ClientEvent::Connected { .. }));
+
+        server.stop().await;
+        assert!(matches!(next_event(&mut events).await, ClientEvent::Disconnected { .. }));
+        assert!(client.send(WsMessage::Ping).is_err());
+
+        // Comes back on the same port; the client finds it and says hello again
+        server.restart().await.unwrap();
+        loop {
+            if let ClientEvent::Connected { .. } = next_event(&mut events).await {
+                break;
+            }
+        }
+        for _ in 0..50 {
+            let clients = server.connected_clients().await;
+            if clients.iter().any(|info| info.display_id.as_deref() == Some("display-a")) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        server
+            .broadcast(WsMessage::Slide(SlideData {
+                target_display_id: Some("display-a".to_string()),
+                church_id: "church-123".to_string(),
+                event_id: "event-456".to_string(),
+                song_id: "song-789".to_string(),
+                slide_index: 4,
+                timestamp: 1234567890,
+                execute_at: None,
+            }))
+            .await
+            .unwrap();
+        match next_event(&mut events).await {
+            ClientEvent::Message(Envelope { message: WsMessage::Slide(slide), seq }) => {
+                assert_eq!(slide.slide_index, 4);
+                assert!(seq.is_some());
+            }
+            other => panic!("Expected the slide, got {:?}", other),
+        }
+
+        client.close().await;
+    }
+}
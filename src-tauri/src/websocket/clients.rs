@@ -0,0 +1,718 @@
+//! Registry of connected WebSocket clients
+//!
+//! Tracks every open connection by socket address and keeps an index from
+//! `display_id` to the connection that registered it, so targeted messages
+//! can be delivered to a single display instead of every client.
+//!
+//! Clients may join rooms (see `types::Room`) to only receive the broadcasts of
+//! their church and event. The rooms of each display are remembered after it
+//! disconnects, so updates for other rooms are not counted as missed.
+//!
+//! Each client also carries its heartbeat state (last frame seen, outstanding
+//! ping, round-trip time) for the connection health roster, along with the
+//! stats of its outgoing queue, the measured offset of its clock and the
+//! encoding its messages are sent in.
+
+use crate::websocket::clock::ClockEstimator;
+use crate::websocket::codec::Encoding;
+use crate::websocket::queue::{ClientQueue, Coalesce, QueueStats};
+use crate::websocket::roles::Role;
+use crate::websocket::types::{Capability, ClockSyncData, Room, LEGACY_PROTOCOL_VERSION};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+
+/// A connection that went away, and why
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientDisconnected {
+    #[serde(flatten)]
+    pub client: ClientInfo,
+    pub reason: String,
+}
+
+/// Health snapshot of one connection, as shown in the controller's roster
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientInfo {
+    pub addr: String,
+    pub display_id: Option<String>,
+    pub device_id: Option<String>,
+    pub role: Role,
+    /// Negotiated in the hello exchange
+    pub protocol_version: u32,
+    pub capabilities: Vec<Capability>,
+    /// How messages to the client are encoded (see `codec`)
+    pub encoding: Encoding,
+    /// Client clock minus server clock, once measured (see `clock`)
+    pub clock_offset_ms: Option<i64>,
+    /// Rooms joined; none means every broadcast is received
+    pub rooms: Vec<Room>,
+    /// Connected with a pairing token (or PIN)
+    pub paired: bool,
+    /// Unix timestamp (seconds) of the connection
+    pub connected_at: i64,
+    /// Time since the last frame from the client
+    pub last_seen_ms: u64,
+    /// Round-trip time of the last answered ping
+    pub rtt_ms: Option<u64>,
+    /// Outgoing queue depth and how many messages were coalesced or dropped
+    pub queue: QueueStats,
+}
+
+/// A single connected client
+pub struct Client {
+    /// Messages waiting to be written to the client's socket
+    pub tx: ClientQueue,
+    /// Display ID registered via the hello frame
+    pub display_id: Option<String>,
+    /// Device ID registered via the hello frame
+    pub device_id: Option<String>,
+    /// Pairing the connection authenticated with, if any
+    pub pair_id: Option<String>,
+    /// Connected over loopback, from this device
+    pub local: bool,
+    /// What the connection may send (see `roles`)
+    pub role: Role,
+    /// Protocol version negotiated in the hello exchange
+    pub protocol_version: u32,
+    /// Capabilities both ends support
+    pub capabilities: Vec<Capability>,
+    /// How messages to the client are encoded, from its capabilities
+    pub encoding: Encoding,
+    /// Offset of the client's clock, from `clock_sync` round trips
+    pub clock: ClockEstimator,
+    /// Rooms the client joined
+    pub rooms: BTreeSet<Room>,
+    /// Sequence numbers replayed to the client when it connected
+    pub replayed: Vec<u64>,
+    /// Signals the connection task to close the connection
+    pub shutdown: Arc<Notify>,
+    /// Why the server closed the connection, if it did
+    pub close_reason: Option<String>,
+    connected_at: i64,
+    last_seen: Instant,
+    /// Payload and send time of the ping awaiting a pong
+    ping_sent: Option<(u64, Instant)>,
+    rtt: Option<Duration>,
+}
+
+impl Client {
+    /// A client with the role its handshake granted (see `Role::granted`)
+    pub fn new(tx: ClientQueue, role: Role) -> Self {
+        let connected_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+
+        Self {
+            tx,
+            display_id: None,
+            device_id: None,
+            pair_id: None,
+            local: false,
+            role,
+            protocol_version: LEGACY_PROTOCOL_VERSION,
+            capabilities: Vec::new(),
+            encoding: Encoding::JSON,
+            clock: ClockEstimator::new(),
+            rooms: BTreeSet::new(),
+            replayed: Vec::new(),
+            shutdown: Arc::new(Notify::new()),
+            close_reason: None,
+            connected_at,
+            last_seen: Instant::now(),
+            ping_sent: None,
+            rtt: None,
+        }
+    }
+
+    /// Whether the connection proved who it is, rather than being let in with pairing off
+    fn authenticated(&self) -> bool {
+        self.local || self.pair_id.is_some()
+    }
+
+    /// Whether two connections authenticated as the same device or pairing
+    fn same_identity(&self, other: &Client) -> bool {
+        (self.local && other.local) || (self.pair_id.is_some() && self.pair_id == other.pair_id)
+    }
+
+    pub fn info(&self, addr: SocketAddr, now: Instant) -> ClientInfo {
+        ClientInfo {
+            addr: addr.to_string(),
+            display_id: self.display_id.clone(),
+            device_id: self.device_id.clone(),
+            role: self.role,
+            protocol_version: self.protocol_version,
+            capabilities: self.capabilities.clone(),
+            encoding: self.encoding,
+            clock_offset_ms: self.clock.offset_ms(),
+            rooms: self.rooms.iter().cloned().collect(),
+            paired: self.pair_id.is_some(),
+            connected_at: self.connected_at,
+            last_seen_ms: now.saturating_duration_since(self.last_seen).as_millis() as u64,
+            rtt_ms: self.rtt.map(|rtt| rtt.as_millis() as u64),
+            queue: self.tx.stats(),
+        }
+    }
+}
+
+/// Connections in one room
+#[derive(Debug, Clone, Serialize)]
+pub struct RoomInfo {
+    #[serde(flatten)]
+    pub room: Room,
+    pub members: Vec<ClientInfo>,
+}
+
+/// Connected clients plus a display_id -> connection index
+#[derive(Default)]
+pub struct ClientRegistry {
+    clients: HashMap<SocketAddr, Client>,
+    displays: HashMap<String, SocketAddr>,
+    /// Rooms of every display an authenticated connection registered, kept
+    /// while it is offline until it is forgotten
+    display_rooms: HashMap<String, BTreeSet<Room>>,
+}
+
+impl ClientRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a newly connected client
+    pub fn insert(&mut self, addr: SocketAddr, client: Client) {
+        self.clients.insert(addr, client);
+    }
+
+    /// Remove a client, dropping its display registration if it still owns it
+    pub fn remove(&mut self, addr: &SocketAddr) -> Option<Client> {
+        let client = self.clients.remove(addr)?;
+        if let Some(display_id) = &client.display_id {
+            // A reconnecting display may already have re-registered from a new address
+            if self.displays.get(display_id) == Some(addr) {
+                self.displays.remove(display_id);
+            }
+        }
+        Some(client)
+    }
+
+    /// Record the display/device IDs a client announced in its hello frame
+    ///
+    /// The most recent connection wins if two connections claim the same
+    /// display_id, so a display that reconnects takes its registration back.
+    /// A display_id held by an authenticated connection can only be taken over
+    /// by one authenticated as the same device or pairing.
+    pub fn register(
+        &mut self,
+        addr: SocketAddr,
+        display_id: Option<String>,
+        device_id: Option<String>,
+    ) -> Result<(), String> {
+        let Some(client) = self.clients.get(&addr) else {
+            return Err(format!("Unknown client {}", addr));
+        };
+        let holder = display_id
+            .as_ref()
+            .and_then(|id| self.displays.get(id))
+            .filter(|holder| **holder != addr)
+            .and_then(|holder| self.clients.get(holder));
+        if let Some(holder) = holder {
+            if holder.authenticated() && !client.same_identity(holder) {
+                return Err(format!(
+                    "Display {} is registered by another connection",
+                    display_id.unwrap_or_default()
+                ));
+            }
+        }
+
+        let client = self.clients.get_mut(&addr).expect("client checked above");
+
+        // Drop any previous registration held by this connection
+        if let Some(old_id) = client.display_id.take() {
+            if self.displays.get(&old_id) == Some(&addr) {
+                self.displays.remove(&old_id);
+            }
+        }
+
+        if let Some(id) = &display_id {
+            if let Some(previous) = self.displays.insert(id.clone(), addr) {
+                if previous != addr {
+                    tracing::warn!(
+                        "Display {} re-registered from {} (previously {})",
+                        id, addr, previous
+                    );
+                    if let Some(stale) = self.clients.get_mut(&previous) {
+                        stale.display_id = None;
+                    }
+                }
+            }
+        }
+
+        let client = self.clients.get_mut(&addr).expect("client checked above");
+        if let Some(id) = display_id.as_ref().filter(|_| client.authenticated()) {
+            self.display_rooms.insert(id.clone(), client.rooms.clone());
+        }
+        client.display_id = display_id;
+        client.device_id = device_id;
+        Ok(())
+    }
+
+    /// Add a connection to a room; false if it was already a member
+    pub fn join(&mut self, addr: &SocketAddr, room: Room) -> bool {
+        let Some(client) = self.clients.get_mut(addr) else {
+            return false;
+        };
+        let joined = client.rooms.insert(room);
+        if let Some(id) = client.display_id.as_ref().filter(|_| client.authenticated()) {
+            self.display_rooms.insert(id.clone(), client.rooms.clone());
+        }
+        joined
+    }
+
+    /// Remove a connection from a room; false if it was not a member
+    pub fn leave(&mut self, addr: &SocketAddr, room: &Room) -> bool {
+        let Some(client) = self.clients.get_mut(addr) else {
+            return false;
+        };
+        let left = client.rooms.remove(room);
+        if let Some(id) = client.display_id.as_ref().filter(|_| client.authenticated()) {
+            self.display_rooms.insert(id.clone(), client.rooms.clone());
+        }
+        left
+    }
+
+    /// Rooms a connection joined
+    pub fn rooms_of(&self, addr: &SocketAddr) -> BTreeSet<Room> {
+        self.clients.get(addr).map(|client| client.rooms.clone()).unwrap_or_default()
+    }
+
+    /// Drop the rooms remembered for a display that is gone for good
+    pub fn forget_display(&mut self, display_id: &str) {
+        self.display_rooms.remove(display_id);
+    }
+
+    /// Whether a display (connected or not) receives the broadcasts of a room
+    pub fn display_in_room(&self, display_id: &str, room: &Room) -> bool {
+        self.display_rooms.get(display_id).is_none_or(|rooms| rooms.is_empty() || rooms.contains(room))
+    }
+
+    /// Every room with at least one member
+    pub fn rooms(&self, now: Instant) -> Vec<RoomInfo> {
+        let mut rooms: BTreeMap<&Room, Vec<ClientInfo>> = BTreeMap::new();
+        for (addr, client) in &self.clients {
+            for room in &client.rooms {
+                rooms.entry(room).or_default().push(client.info(*addr, now));
+            }
+        }
+        rooms
+            .into_iter()
+            .map(|(room, mut members)| {
+                members.sort_by(|a, b| a.addr.cmp(&b.addr));
+                RoomInfo { room: room.clone(), members }
+            })
+            .collect()
+    }
+
+    /// Whether a connection proved who it is (see `Client::authenticated`)
+    pub fn is_authenticated(&self, addr: &SocketAddr) -> bool {
+        self.clients.get(addr).is_some_and(Client::authenticated)
+    }
+
+    /// Look up the connection registered for a display
+    pub fn addr_for_display(&self, display_id: &str) -> Option<SocketAddr> {
+        self.displays.get(display_id).copied()
+    }
+
+    /// Display IDs registered by the connected clients
+    pub fn display_ids(&self) -> Vec<String> {
+        self.displays.keys().cloned().collect()
+    }
+
+    /// Role of a connection
+    pub fn role(&self, addr: &SocketAddr) -> Option<Role> {
+        self.clients.get(addr).map(|client| client.role)
+    }
+
+    pub fn set_role(&mut self, addr: &SocketAddr, role: Role) {
+        if let Some(client) = self.clients.get_mut(addr) {
+            client.role = role;
+        }
+    }
+
+    /// Record what a connection negotiated in its hello
+    ///
+    /// Messages queued from now on use the encoding the capabilities allow.
+    pub fn set_protocol(&mut self, addr: &SocketAddr, version: u32, capabilities: Vec<Capability>) {
+        if let Some(client) = self.clients.get_mut(addr) {
+            client.protocol_version = version;
+            client.encoding = Encoding::negotiated(&capabilities);
+            client.capabilities = capabilities;
+        }
+    }
+
+    /// Whether a connection negotiated a capability
+    pub fn supports(&self, addr: &SocketAddr, capability: Capability) -> bool {
+        self.clients.get(addr).is_some_and(|client| client.capabilities.contains(&capability))
+    }
+
+    /// Controllers whose clock offset the server keeps measuring
+    pub fn clock_sync_addrs(&self) -> Vec<SocketAddr> {
+        self.clients
+            .iter()
+            .filter(|(_, client)| {
+                client.role == Role::Controller && client.capabilities.contains(&Capability::ClockSync)
+            })
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// Feed the answer to a `clock_sync` request into the client's estimator
+    pub fn record_clock_answer(&mut self, addr: &SocketAddr, answer: &ClockSyncData, now: i64) -> bool {
+        self.clients.get_mut(addr).is_some_and(|client| client.clock.on_answer(answer, now))
+    }
+
+    /// Client clock minus server clock, if measured
+    pub fn clock_offset(&self, addr: &SocketAddr) -> Option<i64> {
+        self.clients.get(addr)?.clock.offset_ms()
+    }
+
+    /// Convert a time on the client's clock to the server's, if its offset is known
+    pub fn to_server_time(&self, addr: &SocketAddr, client_ms: i64) -> Option<i64> {
+        self.clients.get(addr)?.clock.to_local(client_ms)
+    }
+
+    /// Connections authenticated with the given pairing
+    pub fn addrs_for_pairing(&self, pair_id: &str) -> Vec<SocketAddr> {
+        self.clients
+            .iter()
+            .filter(|(_, client)| client.pair_id.as_deref() == Some(pair_id))
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// Addresses that should receive a message with the given target and room
+    ///
+    /// Untargeted messages go to every client in the room, plus the clients
+    /// that joined no room; targeted messages only go to the connection that
+    /// registered that display_id (if any).
+    pub fn recipients(&self, target_display_id: Option<&str>, room: Option<&Room>) -> Vec<SocketAddr> {
+        match (target_display_id, room) {
+            (Some(id), _) => self.addr_for_display(id).into_iter().collect(),
+            (None, None) => self.clients.keys().copied().collect(),
+            (None, Some(room)) => self
+                .clients
+                .iter()
+                .filter(|(_, client)| client.rooms.is_empty() || client.rooms.contains(room))
+                .map(|(addr, _)| *addr)
+                .collect(),
+        }
+    }
+
+    /// Controllers that share a room with `addr`, or either side joined none,
+    /// excluding `addr` itself
+    pub fn controllers_near(&self, addr: &SocketAddr) -> Vec<SocketAddr> {
+        let rooms = self.rooms_of(addr);
+        self.clients
+            .iter()
+            .filter(|(other, client)| {
+                *other != addr
+                    && client.role == Role::Controller
+                    && (rooms.is_empty() || client.rooms.is_empty() || !client.rooms.is_disjoint(&rooms))
+            })
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// Sequence numbers replayed to a client when it connected
+    pub fn replayed(&self, addr: &SocketAddr) -> Vec<u64> {
+        self.clients.get(addr).map(|client| client.replayed.clone()).unwrap_or_default()
+    }
+
+    /// Display registered by a connection
+    pub fn display_for_addr(&self, addr: &SocketAddr) -> Option<&str> {
+        self.clients.get(addr)?.display_id.as_deref()
+    }
+
+    /// Send a message to each address, returning the addresses whose channel is closed
+    pub fn send_to(&self, addrs: &[SocketAddr], message: &Message) -> Vec<SocketAddr> {
+        self.queue_to(addrs, message, None)
+    }
+
+    /// Like `send_to`, replacing queued updates the message supersedes
+    ///
+    /// Text messages are re-encoded for clients that negotiated another
+    /// encoding, once per encoding.
+    pub fn queue_to(&self, addrs: &[SocketAddr], message: &Message, coalesce: Option<&Coalesce>) -> Vec<SocketAddr> {
+        let mut disconnected = Vec::new();
+        let mut encoded: Vec<(Encoding, Message)> = Vec::new();
+        for addr in addrs {
+            if let Some(client) = self.clients.get(addr) {
+                let message = if client.encoding.is_json() {
+                    message.clone()
+                } else if let Some((_, message)) = encoded.iter().find(|(encoding, _)| *encoding == client.encoding) {
+                    message.clone()
+                } else {
+                    let message = client.encoding.encode(message);
+                    encoded.push((client.encoding, message.clone()));
+                    message
+                };
+                if client.tx.push(message, coalesce.cloned()).is_err() {
+                    disconnected.push(*addr);
+                }
+            }
+        }
+        disconnected
+    }
+
+    /// Ask a connection to close; its task removes it from the registry
+    pub fn disconnect(&mut self, addr: &SocketAddr, reason: &str) {
+        if let Some(client) = self.clients.get_mut(addr) {
+            client.close_reason.get_or_insert_with(|| reason.to_string());
+            client.shutdown.notify_one();
+        }
+    }
+
+    /// Note that a frame arrived from a client
+    pub fn touch(&mut self, addr: &SocketAddr, now: Instant) {
+        if let Some(client) = self.clients.get_mut(addr) {
+            client.last_seen = now;
+        }
+    }
+
+    /// Record a pong, updating the round-trip time if it answers our last ping
+    pub fn record_pong(&mut self, addr: &SocketAddr, payload: &[u8], now: Instant) {
+        let Some(client) = self.clients.get_mut(addr) else {
+            return;
+        };
+        client.last_seen = now;
+        if let Some((nonce, sent)) = client.ping_sent {
+            if payload == nonce.to_be_bytes() {
+                client.rtt = Some(now.saturating_duration_since(sent));
+                client.ping_sent = None;
+            }
+        }
+    }
+
+    /// Send a ping to every client, returning the clients that have been silent
+    /// for longer than `timeout` instead
+    pub fn ping_all(&mut self, nonce: u64, now: Instant, timeout: Duration) -> Vec<SocketAddr> {
+        let mut silent = Vec::new();
+        for (addr, client) in self.clients.iter_mut() {
+            if now.saturating_duration_since(client.last_seen) > timeout {
+                silent.push(*addr);
+                continue;
+            }
+            // Keep the older ping if it is still unanswered, so a slow pong still measures RTT
+            if client.ping_sent.is_none() {
+                client.ping_sent = Some((nonce, now));
+            }
+            let payload = client.ping_sent.map(|(nonce, _)| nonce).unwrap_or(nonce);
+            let _ = client.tx.push(Message::Ping(payload.to_be_bytes().to_vec()), None);
+        }
+        silent
+    }
+
+    /// Health snapshot of one connection
+    pub fn info(&self, addr: &SocketAddr, now: Instant) -> Option<ClientInfo> {
+        self.clients.get(addr).map(|client| client.info(*addr, now))
+    }
+
+    /// Health snapshot of every connection
+    pub fn roster(&self, now: Instant) -> Vec<ClientInfo> {
+        let mut roster: Vec<ClientInfo> = self
+            .clients
+            .iter()
+            .map(|(addr, client)| client.info(*addr, now))
+            .collect();
+        roster.sort_by(|a, b| a.display_id.cmp(&b.display_id).then_with(|| a.addr.cmp(&b.addr)));
+        roster
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::queue::CLIENT_QUEUE_CAPACITY;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn registry_with(ports: &[u16]) -> ClientRegistry {
+        let mut registry = ClientRegistry::new();
+        for port in ports {
+            registry.insert(addr(*port), Client::new(ClientQueue::new(CLIENT_QUEUE_CAPACITY), Role::Controller));
+        }
+        registry
+    }
+
+    #[test]
+    fn test_untargeted_goes_to_everyone() {
+        let mut registry = registry_with(&[1, 2, 3]);
+        registry.register(addr(1), Some("display-a".to_string()), None).unwrap();
+
+        let mut recipients = registry.recipients(None, None);
+        recipients.sort();
+        assert_eq!(recipients, vec![addr(1), addr(2), addr(3)]);
+    }
+
+    #[test]
+    fn test_targeted_goes_to_registered_display_only() {
+        let mut registry = registry_with(&[1, 2]);
+        registry.register(addr(1), Some("display-a".to_string()), Some("device-1".to_string())).unwrap();
+        registry.register(addr(2), Some("display-b".to_string()), Some("device-1".to_string())).unwrap();
+
+        assert_eq!(registry.recipients(Some("display-a"), None), vec![addr(1)]);
+        assert_eq!(registry.recipients(Some("display-b"), None), vec![addr(2)]);
+        assert!(registry.recipients(Some("display-unknown"), None).is_empty());
+    }
+
+    #[test]
+    fn test_reconnect_takes_over_registration() {
+        let mut registry = registry_with(&[1, 2]);
+        registry.register(addr(1), Some("display-a".to_string()), None).unwrap();
+        registry.register(addr(2), Some("display-a".to_string()), None).unwrap();
+
+        assert_eq!(registry.addr_for_display("display-a"), Some(addr(2)));
+        assert_eq!(registry.recipients(Some("display-a"), None), vec![addr(2)]);
+
+        // Removing the stale connection must not drop the new registration
+        registry.remove(&addr(1));
+        assert_eq!(registry.addr_for_display("display-a"), Some(addr(2)));
+
+        registry.remove(&addr(2));
+        assert_eq!(registry.addr_for_display("display-a"), None);
+        assert_eq!(registry.len(), 0);
+    }
+
+    #[test]
+    fn test_authenticated_display_cannot_be_taken_over() {
+        let mut registry = registry_with(&[1, 2, 3, 4]);
+        for port in [1, 3] {
+            registry.clients.get_mut(&addr(port)).unwrap().local = true;
+        }
+        registry.register(addr(1), Some("display-a".to_string()), None).unwrap();
+
+        // A connection let in without credentials cannot claim it
+        assert!(registry.register(addr(2), Some("display-a".to_string()), None).is_err());
+        assert_eq!(registry.addr_for_display("display-a"), Some(addr(1)));
+        assert_eq!(registry.display_for_addr(&addr(1)), Some("display-a"));
+
+        // The display reconnecting from the same device can
+        registry.register(addr(3), Some("display-a".to_string()), None).unwrap();
+        assert_eq!(registry.addr_for_display("display-a"), Some(addr(3)));
+
+        // Paired connections keep what they registered from other pairings
+        registry.clients.get_mut(&addr(2)).unwrap().pair_id = Some("pair-1".to_string());
+        registry.clients.get_mut(&addr(4)).unwrap().pair_id = Some("pair-2".to_string());
+        registry.register(addr(2), Some("display-b".to_string()), None).unwrap();
+        assert!(registry.register(addr(4), Some("display-b".to_string()), None).is_err());
+        assert!(registry.register(addr(3), Some("display-b".to_string()), None).is_err());
+        assert_eq!(registry.addr_for_display("display-b"), Some(addr(2)));
+    }
+
+    #[test]
+    fn test_only_authenticated_displays_are_remembered() {
+        let room = Room {
+            church_id: "church-123".to_string(),
+            event_id: "main".to_string(),
+        };
+        let mut registry = registry_with(&[1, 2]);
+        registry.clients.get_mut(&addr(1)).unwrap().local = true;
+        registry.join(&addr(1), room.clone());
+        registry.join(&addr(2), room.clone());
+        registry.register(addr(1), Some("display-a".to_string()), None).unwrap();
+        registry.register(addr(2), Some("display-typo".to_string()), None).unwrap();
+
+        assert!(registry.is_authenticated(&addr(1)));
+        assert!(!registry.is_authenticated(&addr(2)));
+        assert!(registry.display_rooms.contains_key("display-a"));
+        assert!(!registry.display_rooms.contains_key("display-typo"));
+    }
+
+    #[test]
+    fn test_re_register_replaces_display_id() {
+        let mut registry = registry_with(&[1]);
+        registry.register(addr(1), Some("display-a".to_string()), None).unwrap();
+        registry.register(addr(1), Some("display-b".to_string()), None).unwrap();
+
+        assert_eq!(registry.addr_for_display("display-a"), None);
+        assert_eq!(registry.addr_for_display("display-b"), Some(addr(1)));
+    }
+
+    #[test]
+    fn test_room_broadcasts_skip_other_rooms() {
+        let room = |event_id: &str| Room {
+            church_id: "church-123".to_string(),
+            event_id: event_id.to_string(),
+        };
+        let mut registry = registry_with(&[1, 2, 3]);
+        registry.clients.get_mut(&addr(1)).unwrap().local = true;
+        registry.register(addr(1), Some("sanctuary".to_string()), None).unwrap();
+        registry.join(&addr(1), room("main"));
+        assert!(registry.join(&addr(2), room("youth")));
+        assert!(!registry.join(&addr(2), room("youth")));
+
+        let mut recipients = registry.recipients(None, Some(&room("main")));
+        recipients.sort();
+        // Client 3 joined no room and still gets everything
+        assert_eq!(recipients, vec![addr(1), addr(3)]);
+        // Targeted messages ignore rooms
+        assert_eq!(registry.recipients(Some("sanctuary"), Some(&room("youth"))), vec![addr(1)]);
+
+        // A display's rooms outlive its connection
+        registry.remove(&addr(1));
+        assert!(!registry.display_in_room("sanctuary", &room("youth")));
+        assert!(registry.display_in_room("sanctuary", &room("main")));
+        assert!(registry.display_in_room("unknown", &room("youth")));
+        registry.forget_display("sanctuary");
+        assert!(registry.display_in_room("sanctuary", &room("youth")));
+
+        let rooms = registry.rooms(Instant::now());
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].room, room("youth"));
+        assert!(registry.leave(&addr(2), &room("youth")));
+        assert!(registry.rooms(Instant::now()).is_empty());
+    }
+
+    #[test]
+    fn test_register_unknown_client() {
+        let mut registry = ClientRegistry::new();
+        assert!(registry.register(addr(1), Some("display-a".to_string()), None).is_err());
+        assert_eq!(registry.addr_for_display("display-a"), None);
+    }
+
+    #[test]
+    fn test_pong_sets_rtt_and_silent_clients_time_out() {
+        let mut registry = registry_with(&[1, 2]);
+        let start = Instant::now();
+        let timeout = Duration::from_secs(30);
+
+        assert!(registry.ping_all(7, start, timeout).is_empty());
+        let later = start + Duration::from_millis(40);
+        registry.record_pong(&addr(1), &7u64.to_be_bytes(), later);
+        // A pong with the wrong payload does not count as an answer
+        registry.record_pong(&addr(2), &8u64.to_be_bytes(), later);
+
+        let info = registry.info(&addr(1), later).unwrap();
+        assert_eq!(info.rtt_ms, Some(40));
+        assert_eq!(info.last_seen_ms, 0);
+        assert_eq!(registry.info(&addr(2), later).unwrap().rtt_ms, None);
+
+        let much_later = later + timeout + Duration::from_secs(1);
+        registry.touch(&addr(1), much_later);
+        assert_eq!(registry.ping_all(8, much_later, timeout), vec![addr(2)]);
+        assert_eq!(registry.roster(much_later).len(), 2);
+    }
+}
@@ -0,0 +1,137 @@
+//! NTP-style clock offset estimation between the server and its peers
+//!
+//! A `clock_sync` request carries the time it was sent (`origin`, t0). The
+//! peer answers with the time it received the request (`receive`, t1) and sent
+//! the answer (`transmit`, t2); the requester notes when the answer arrived
+//! (t3). As in NTP:
+//! - offset = ((t1 - t0) + (t2 - t3)) / 2 (peer clock minus local clock)
+//! - delay = (t3 - t0) - (t2 - t1) (network round trip)
+//!
+//! The offset is only exact when both directions take equally long, so the
+//! estimator keeps the last `CLOCK_SAMPLES` samples and trusts the one with
+//! the lowest delay, which had the least room for asymmetry.
+//!
+//! All timestamps are Unix milliseconds.
+
+use crate::websocket::types::ClockSyncData;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Samples kept per peer
+pub const CLOCK_SAMPLES: usize = 8;
+
+/// Current Unix time in milliseconds
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// A new `clock_sync` request
+pub fn request(now: i64) -> ClockSyncData {
+    ClockSyncData { origin: now, receive: None, transmit: None }
+}
+
+/// Answer to a peer's `clock_sync` request
+pub fn answer(request: &ClockSyncData, received_at: i64, now: i64) -> ClockSyncData {
+    ClockSyncData {
+        origin: request.origin,
+        receive: Some(received_at),
+        transmit: Some(now),
+    }
+}
+
+/// One request/answer round trip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSample {
+    /// Peer clock minus local clock
+    pub offset_ms: i64,
+    pub delay_ms: i64,
+}
+
+impl ClockSample {
+    pub fn from_timestamps(t0: i64, t1: i64, t2: i64, t3: i64) -> Self {
+        Self {
+            offset_ms: ((t1 - t0) + (t2 - t3)) / 2,
+            delay_ms: (t3 - t0) - (t2 - t1),
+        }
+    }
+}
+
+/// Offset of one peer's clock
+#[derive(Debug, Clone, Default)]
+pub struct ClockEstimator {
+    samples: VecDeque<ClockSample>,
+}
+
+impl ClockEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the answer to one of our requests, arriving at `now`.
+    /// Returns false if it is not an answer or cannot be trusted.
+    pub fn on_answer(&mut self, answer: &ClockSyncData, now: i64) -> bool {
+        let (Some(receive), Some(transmit)) = (answer.receive, answer.transmit) else {
+            return false;
+        };
+        let sample = ClockSample::from_timestamps(answer.origin, receive, transmit, now);
+        // A negative delay means a clock was stepped mid-exchange
+        if sample.delay_ms < 0 {
+            return false;
+        }
+        if self.samples.len() == CLOCK_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        true
+    }
+
+    /// The sample with the lowest delay, if any
+    pub fn best(&self) -> Option<ClockSample> {
+        self.samples.iter().min_by_key(|sample| sample.delay_ms).copied()
+    }
+
+    /// Peer clock minus local clock
+    pub fn offset_ms(&self) -> Option<i64> {
+        self.best().map(|sample| sample.offset_ms)
+    }
+
+    /// Convert a time on the peer's clock to the local clock
+    pub fn to_local(&self, peer_ms: i64) -> Option<i64> {
+        self.offset_ms().map(|offset| peer_ms - offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_and_delay_from_timestamps() {
+        // Peer is 500ms ahead; 20ms each way; 5ms to answer
+        let sample = ClockSample::from_timestamps(1_000, 1_520, 1_525, 1_045);
+        assert_eq!(sample, ClockSample { offset_ms: 500, delay_ms: 40 });
+    }
+
+    #[test]
+    fn test_estimator_trusts_lowest_delay() {
+        let mut clock = ClockEstimator::new();
+        assert_eq!(clock.offset_ms(), None);
+        assert!(!clock.on_answer(&request(1_000), 1_100));
+
+        // Slow, asymmetric round trip: 10ms out, 190ms back
+        let slow = answer(&request(1_000), 1_510, 1_510);
+        assert!(clock.on_answer(&slow, 1_200));
+        // Fast round trip: 10ms each way
+        let fast = answer(&request(2_000), 2_510, 2_510);
+        assert!(clock.on_answer(&fast, 2_020));
+
+        assert_eq!(clock.offset_ms(), Some(500));
+        assert_eq!(clock.to_local(10_500), Some(10_000));
+
+        // Stepped clock
+        assert!(!clock.on_answer(&answer(&request(3_000), 3_500, 3_600), 3_010));
+    }
+}
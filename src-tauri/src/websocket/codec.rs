@@ -0,0 +1,245 @@
+//! Per-connection message encoding: JSON text, MessagePack and deflate
+//!
+//! JSON text frames are understood by every client, and are all a client gets
+//! unless its hello offers the `msgpack` or `deflate` capability. Everything
+//! after the server's `welcome` is then sent as a binary frame:
+//!
+//! ```text
+//! [kind: u8 = 0x02][flags: u8][payload]
+//! ```
+//!
+//! - `FLAG_MSGPACK`: the payload is MessagePack instead of UTF-8 JSON (maps
+//!   keep their field names, so the shape is the same as the JSON)
+//! - `FLAG_DEFLATE`: the payload is raw deflate; only set for payloads of at
+//!   least `DEFLATE_MIN_BYTES`, where compressing pays off (full song lyrics
+//!   shrink several times over)
+//!
+//! Clients may send message frames in any encoding, whatever they negotiated.
+//!
+//! tungstenite does not implement the permessage-deflate extension, so
+//! compression is negotiated as a capability and applied per message instead.
+
+use crate::websocket::types::Capability;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use serde::Serialize;
+use std::io::Write;
+use tokio_tungstenite::tungstenite::Message;
+
+/// First byte of a binary frame carrying an encoded message
+pub const FRAME_MESSAGE: u8 = 0x02;
+
+/// The payload is MessagePack
+pub const FLAG_MSGPACK: u8 = 0x01;
+
+/// The payload is deflate-compressed
+pub const FLAG_DEFLATE: u8 = 0x02;
+
+/// kind + flags
+const HEADER_LEN: usize = 2;
+
+/// Smaller payloads are sent uncompressed
+pub const DEFLATE_MIN_BYTES: usize = 256;
+
+/// Largest message accepted once decompressed
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// How messages are encoded for one connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct Encoding {
+    pub msgpack: bool,
+    pub deflate: bool,
+}
+
+impl Encoding {
+    /// Plain JSON text frames
+    pub const JSON: Encoding = Encoding { msgpack: false, deflate: false };
+
+    /// Encoding for the capabilities negotiated with a client
+    pub fn negotiated(capabilities: &[Capability]) -> Self {
+        Self {
+            msgpack: capabilities.contains(&Capability::MsgPack),
+            deflate: capabilities.contains(&Capability::Deflate),
+        }
+    }
+
+    pub fn is_json(self) -> bool {
+        self == Self::JSON
+    }
+
+    /// Encode a JSON message as a frame for this connection
+    pub fn encode_text(self, json: &str) -> Result<Message, String> {
+        if self.is_json() {
+            return Ok(Message::Text(json.to_string()));
+        }
+        let mut flags = 0;
+        let mut payload = if self.msgpack {
+            flags |= FLAG_MSGPACK;
+            let value: serde_json::Value =
+                serde_json::from_str(json).map_err(|e| format!("Invalid JSON message: {}", e))?;
+            rmp_serde::to_vec_named(&value).map_err(|e| format!("Failed to encode MessagePack: {}", e))?
+        } else {
+            json.as_bytes().to_vec()
+        };
+        if self.deflate && payload.len() >= DEFLATE_MIN_BYTES {
+            flags |= FLAG_DEFLATE;
+            payload = compress(&payload)?;
+        }
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.push(FRAME_MESSAGE);
+        frame.push(flags);
+        frame.extend_from_slice(&payload);
+        Ok(Message::Binary(frame))
+    }
+
+    /// Encode an outgoing frame: text frames are re-encoded, anything else
+    /// (media chunks, pings, close frames) is passed through
+    pub fn encode(self, message: &Message) -> Message {
+        match message {
+            Message::Text(json) if !self.is_json() => self.encode_text(json).unwrap_or_else(|e| {
+                tracing::error!("{}; sending as JSON", e);
+                message.clone()
+            }),
+            _ => message.clone(),
+        }
+    }
+}
+
+/// Decode a message frame back to JSON text
+pub fn decode(frame: &[u8]) -> Result<String, String> {
+    if frame.len() < HEADER_LEN {
+        return Err(format!("Message frame too short ({} bytes)", frame.len()));
+    }
+    if frame[0] != FRAME_MESSAGE {
+        return Err(format!("Not a message frame (kind {:#04x})", frame[0]));
+    }
+    let flags = frame[1];
+    if flags & !(FLAG_MSGPACK | FLAG_DEFLATE) != 0 {
+        return Err(format!("Unknown message frame flags {:#04x}", flags));
+    }
+
+    let mut payload = &frame[HEADER_LEN..];
+    let decompressed;
+    if flags & FLAG_DEFLATE != 0 {
+        decompressed = decompress(payload)?;
+        payload = &decompressed;
+    }
+    if flags & FLAG_MSGPACK != 0 {
+        let value: serde_json::Value =
+            rmp_serde::from_slice(payload).map_err(|e| format!("Invalid MessagePack: {}", e))?;
+        Ok(value.to_string())
+    } else {
+        String::from_utf8(payload.to_vec()).map_err(|e| format!("Message is not UTF-8: {}", e))
+    }
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data).map_err(|e| format!("Failed to compress message: {}", e))?;
+    encoder.finish().map_err(|e| format!("Failed to compress message: {}", e))
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    // One byte over the limit is enough to tell the message is too large
+    let limit = MAX_MESSAGE_SIZE + 1;
+    let mut inflater = Decompress::new(false);
+    let mut decompressed = Vec::with_capacity(data.len().saturating_mul(4).clamp(1024, limit));
+    loop {
+        if decompressed.len() == decompressed.capacity() {
+            if decompressed.len() > MAX_MESSAGE_SIZE {
+                return Err(format!("Message exceeds {} bytes when decompressed", MAX_MESSAGE_SIZE));
+            }
+            decompressed.reserve_exact(decompressed.capacity().min(limit - decompressed.len()).max(1));
+        }
+        let (read, written) = (inflater.total_in(), inflater.total_out());
+        let input = &data[read as usize..];
+        let status = inflater
+            .decompress_vec(input, &mut decompressed, FlushDecompress::None)
+            .map_err(|e| format!("Failed to decompress message: {}", e))?;
+        if status == Status::StreamEnd {
+            break;
+        }
+        if inflater.total_in() == read && inflater.total_out() == written {
+            // Room for more output, yet the stream did not end: input ran out
+            return Err("Failed to decompress message: truncated deflate stream".to_string());
+        }
+    }
+    if decompressed.len() > MAX_MESSAGE_SIZE {
+        return Err(format!("Message exceeds {} bytes when decompressed", MAX_MESSAGE_SIZE));
+    }
+    if decompressed.is_empty() {
+        return Err("Compressed message is empty".to_string());
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::types::{Envelope, LyricsData, WsMessage};
+
+    fn lyrics_json() -> String {
+        let lyrics = WsMessage::Lyrics(LyricsData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            title: "Amazing Grace".to_string(),
+            lyrics: "Amazing grace, how sweet the sound\nThat saved a wretch like me\n".repeat(20),
+            background_url: None,
+            timestamp: 1234567890,
+            execute_at: None,
+        });
+        serde_json::to_string(&Envelope::new(lyrics, Some(7))).unwrap()
+    }
+
+    #[test]
+    fn test_every_encoding_round_trips() {
+        let json = lyrics_json();
+        for (msgpack, deflate) in [(false, false), (true, false), (false, true), (true, true)] {
+            let encoding = Encoding { msgpack, deflate };
+            let decoded = match encoding.encode_text(&json).unwrap() {
+                Message::Text(text) => text,
+                Message::Binary(frame) => {
+                    assert!(frame.len() < json.len(), "{:?} did not shrink the lyrics", encoding);
+                    decode(&frame).unwrap()
+                }
+                other => panic!("unexpected frame {:?}", other),
+            };
+            let envelope: Envelope = serde_json::from_str(&decoded).unwrap();
+            assert_eq!(envelope.seq, Some(7));
+            assert!(matches!(envelope.message, WsMessage::Lyrics(data) if data.title == "Amazing Grace"));
+        }
+
+        // Short messages are not worth compressing
+        let Message::Binary(frame) = Encoding { msgpack: false, deflate: true }.encode_text(r#"{"type":"ping"}"#).unwrap()
+        else {
+            panic!("expected a binary frame");
+        };
+        assert_eq!(frame[1], 0);
+    }
+
+    #[test]
+    fn test_rejects_malformed_frames() {
+        assert!(decode(&[FRAME_MESSAGE]).is_err());
+        assert!(decode(&[0x01, 0, b'{', b'}']).is_err());
+        assert!(decode(&[FRAME_MESSAGE, 0x80, b'{', b'}']).is_err());
+        assert!(decode(&[FRAME_MESSAGE, FLAG_DEFLATE, 1, 2, 3]).is_err());
+
+        // A valid stream cut short, and one with nothing in it
+        let truncated = compress(lyrics_json().as_bytes()).unwrap();
+        let mut frame = vec![FRAME_MESSAGE, FLAG_DEFLATE];
+        frame.extend_from_slice(&truncated[..truncated.len() / 2]);
+        assert!(decode(&frame).is_err());
+        let mut frame = vec![FRAME_MESSAGE, FLAG_DEFLATE];
+        frame.extend_from_slice(&compress(b"").unwrap());
+        assert!(decode(&frame).is_err());
+
+        // A deflate bomb is cut off at the limit
+        let bomb = compress(&vec![b' '; MAX_MESSAGE_SIZE + 1]).unwrap();
+        let mut frame = vec![FRAME_MESSAGE, FLAG_DEFLATE];
+        frame.extend_from_slice(&bomb);
+        assert!(decode(&frame).unwrap_err().contains("exceeds"));
+    }
+}
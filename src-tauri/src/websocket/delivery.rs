@@ -0,0 +1,267 @@
+//! Per-display delivery tracking for sequenced messages
+//!
+//! Every state-changing message gets a sequence number. The tracker remembers,
+//! for each display that should show it, which sequence numbers are still
+//! waiting for an `ack`. A display that stays behind for longer than
+//! `ACK_TIMEOUT` is reported as lagging; one that is not connected when a
+//! message goes out has missed an update and is caught up when it reconnects.
+//!
+//! Only displays that registered from an authenticated connection are
+//! tracked, and one that stays away for `FORGET_DISPLAY_AFTER` is forgotten.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// How long a display may leave a message unacknowledged before it counts as lagging
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long a disconnected display is still expected back (and reported as
+/// missing updates) before it is forgotten
+pub const FORGET_DISPLAY_AFTER: Duration = Duration::from_secs(15 * 60);
+
+/// Unacknowledged messages remembered per display (oldest are forgotten first)
+const MAX_PENDING_PER_DISPLAY: usize = 256;
+
+/// Outcome of sending one message
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeliveryReport {
+    /// Sequence number assigned to the message (state-changing messages only)
+    pub seq: Option<u64>,
+    /// Number of connections the message was written to
+    pub sent_to: usize,
+    /// Displays that should show the message but are not connected
+    pub missed_displays: Vec<String>,
+}
+
+/// A sequenced message went out while the display it was meant for was offline
+#[derive(Debug, Clone, Serialize)]
+pub struct MissedUpdate {
+    pub display_id: String,
+    pub seq: u64,
+}
+
+/// A display that has not acknowledged its messages in time
+#[derive(Debug, Clone, Serialize)]
+pub struct DisplayLag {
+    pub display_id: String,
+    /// Number of messages waiting for an ack
+    pub unacked: usize,
+    /// Last sequence number the display acknowledged
+    pub last_acked: u64,
+    /// Age of the oldest unacknowledged message
+    pub oldest_unacked_ms: u64,
+}
+
+#[derive(Debug, Default)]
+struct DisplayDelivery {
+    /// Highest sequence number acknowledged
+    acked: u64,
+    /// Unacknowledged sequence numbers and when they were (last) sent
+    pending: BTreeMap<u64, Instant>,
+    /// Whether lag has already been reported for the current backlog
+    lag_reported: bool,
+    /// When the display's connection went away, while it is away
+    disconnected_at: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+pub struct DeliveryTracker {
+    last_seq: u64,
+    displays: HashMap<String, DisplayDelivery>,
+}
+
+impl DeliveryTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocate the next sequence number
+    pub fn next_seq(&mut self) -> u64 {
+        self.last_seq += 1;
+        self.last_seq
+    }
+
+    /// Displays that have registered at some point (and so expect untargeted updates)
+    pub fn known_displays(&self) -> Vec<String> {
+        self.displays.keys().cloned().collect()
+    }
+
+    /// Expect an ack for `seq` from a display. Displays that never registered
+    /// (see `resent`) are not tracked.
+    pub fn track(&mut self, display_id: &str, seq: u64, now: Instant) {
+        let Some(display) = self.displays.get_mut(display_id) else {
+            return;
+        };
+        display.pending.insert(seq, now);
+        while display.pending.len() > MAX_PENDING_PER_DISPLAY {
+            display.pending.pop_first();
+        }
+    }
+
+    /// Record a (cumulative) ack. Returns false for displays that are not tracked.
+    pub fn ack(&mut self, display_id: &str, seq: u64) -> bool {
+        let Some(display) = self.displays.get_mut(display_id) else {
+            return false;
+        };
+        display.acked = display.acked.max(seq);
+        display.pending = display.pending.split_off(&(seq + 1));
+        if display.pending.is_empty() {
+            display.lag_reported = false;
+        }
+        true
+    }
+
+    /// Called when a display (re)registers: only the messages just resent to it
+    /// are still expected; anything older was superseded and will never be acked.
+    pub fn resent(&mut self, display_id: &str, seqs: &[u64], now: Instant) {
+        let display = self.displays.entry(display_id.to_string()).or_default();
+        let acked = display.acked;
+        display.pending = seqs
+            .iter()
+            .filter(|seq| **seq > acked)
+            .map(|seq| (*seq, now))
+            .collect();
+        display.lag_reported = false;
+        display.disconnected_at = None;
+    }
+
+    /// Called when a display's connection goes away
+    pub fn disconnected(&mut self, display_id: &str, now: Instant) {
+        if let Some(display) = self.displays.get_mut(display_id) {
+            display.disconnected_at = Some(now);
+        }
+    }
+
+    /// Forget displays that have been away for longer than `after`, returning their IDs
+    pub fn forget_departed(&mut self, now: Instant, after: Duration) -> Vec<String> {
+        let departed: Vec<String> = self
+            .displays
+            .iter()
+            .filter(|(_, display)| {
+                display.disconnected_at.is_some_and(|at| now.saturating_duration_since(at) >= after)
+            })
+            .map(|(display_id, _)| display_id.clone())
+            .collect();
+        for display_id in &departed {
+            self.displays.remove(display_id);
+        }
+        departed
+    }
+
+    /// Connected displays whose oldest unacked message is older than `timeout`.
+    /// Each backlog is only reported once.
+    pub fn newly_lagging(
+        &mut self,
+        now: Instant,
+        timeout: Duration,
+        is_connected: impl Fn(&str) -> bool,
+    ) -> Vec<DisplayLag> {
+        let mut lagging = Vec::new();
+        for (display_id, display) in self.displays.iter_mut() {
+            if display.lag_reported || !is_connected(display_id) {
+                continue;
+            }
+            let Some(oldest) = display.pending.values().min() else {
+                continue;
+            };
+            let age = now.saturating_duration_since(*oldest);
+            if age >= timeout {
+                display.lag_reported = true;
+                lagging.push(DisplayLag {
+                    display_id: display_id.clone(),
+                    unacked: display.pending.len(),
+                    last_acked: display.acked,
+                    oldest_unacked_ms: age.as_millis() as u64,
+                });
+            }
+        }
+        lagging
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_numbers_increase() {
+        let mut tracker = DeliveryTracker::new();
+        assert_eq!(tracker.next_seq(), 1);
+        assert_eq!(tracker.next_seq(), 2);
+    }
+
+    #[test]
+    fn test_cumulative_ack_clears_lag() {
+        let mut tracker = DeliveryTracker::new();
+        let start = Instant::now();
+        tracker.resent("display-a", &[], start);
+        tracker.track("display-a", 1, start);
+        tracker.track("display-a", 2, start);
+        tracker.track("display-a", 3, start);
+
+        let later = start + ACK_TIMEOUT;
+        let lagging = tracker.newly_lagging(later, ACK_TIMEOUT, |_| true);
+        assert_eq!(lagging.len(), 1);
+        assert_eq!(lagging[0].unacked, 3);
+
+        // Reported once per backlog
+        assert!(tracker.newly_lagging(later, ACK_TIMEOUT, |_| true).is_empty());
+
+        assert!(tracker.ack("display-a", 2));
+        assert_eq!(tracker.displays["display-a"].pending.len(), 1);
+        assert!(tracker.ack("display-a", 3));
+        assert!(tracker.newly_lagging(later, ACK_TIMEOUT, |_| true).is_empty());
+        assert!(!tracker.ack("display-unknown", 3));
+    }
+
+    #[test]
+    fn test_disconnected_displays_are_not_lagging() {
+        let mut tracker = DeliveryTracker::new();
+        let start = Instant::now();
+        tracker.resent("display-a", &[], start);
+        tracker.track("display-a", 1, start);
+
+        let later = start + ACK_TIMEOUT;
+        assert!(tracker.newly_lagging(later, ACK_TIMEOUT, |_| false).is_empty());
+    }
+
+    #[test]
+    fn test_resent_replaces_backlog() {
+        let mut tracker = DeliveryTracker::new();
+        let start = Instant::now();
+        tracker.resent("display-a", &[], start);
+        for seq in 1..=5 {
+            tracker.track("display-a", seq, start);
+        }
+        tracker.ack("display-a", 1);
+
+        tracker.resent("display-a", &[1, 4, 5], start);
+        let pending: Vec<u64> = tracker.displays["display-a"].pending.keys().copied().collect();
+        assert_eq!(pending, vec![4, 5]);
+    }
+
+    #[test]
+    fn test_departed_displays_are_forgotten() {
+        let mut tracker = DeliveryTracker::new();
+        let start = Instant::now();
+        // Never registered, e.g. a typo in a targeted update
+        tracker.track("display-typo", 1, start);
+        assert!(tracker.known_displays().is_empty());
+
+        tracker.resent("display-a", &[], start);
+        tracker.resent("display-b", &[], start);
+        tracker.disconnected("display-a", start);
+        tracker.disconnected("display-b", start);
+        // display-b comes back before it is forgotten
+        tracker.resent("display-b", &[], start + ACK_TIMEOUT);
+
+        let later = start + FORGET_DISPLAY_AFTER;
+        assert_eq!(tracker.forget_departed(later, FORGET_DISPLAY_AFTER), vec!["display-a".to_string()]);
+        assert_eq!(tracker.known_displays(), vec!["display-b".to_string()]);
+
+        // Later updates no longer expect the departed display
+        tracker.track("display-a", 2, later);
+        assert!(tracker.known_displays().iter().all(|id| id != "display-a"));
+    }
+}
@@ -0,0 +1,46 @@
+//! Events raised by the WebSocket server for the rest of the app
+//!
+//! The server has no access to the Tauri app handle; it publishes these on a
+//! broadcast channel and `commands::forward_websocket_events` turns them into
+//! Tauri events (and persists state where needed).
+
+use crate::websocket::clients::{ClientDisconnected, ClientInfo};
+use crate::websocket::delivery::{DisplayLag, MissedUpdate};
+use crate::websocket::pairing::Pairing;
+use crate::websocket::precache::PrecacheReady;
+use crate::websocket::transfer::ReceivedMedia;
+use crate::websocket::types::MediaTransferStatus;
+use serde::Serialize;
+
+/// Capacity of the server event channel
+pub const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    /// The set of paired controllers changed and should be persisted
+    PairingsChanged(Vec<Pairing>),
+    /// A connected display has not acknowledged its updates in time
+    DisplayLagging(DisplayLag),
+    /// A display was offline when an update for it went out
+    DisplayMissedUpdate(MissedUpdate),
+    /// A client registered itself as a display
+    DisplayConnected(ClientInfo),
+    /// A registered display's connection closed (or timed out)
+    DisplayDisconnected(ClientDisconnected),
+    /// A display has every media item for an event cached
+    DisplayPrecacheReady(PrecacheReady),
+    /// A media file pushed to this device arrived intact and should be cached
+    MediaReceived(ReceivedMedia),
+    /// A peer reported progress on a file this device is sending
+    MediaTransferStatus(MediaTransferStatus),
+    /// A journal replay published its last message
+    JournalReplayFinished(ReplayFinished),
+}
+
+/// Summary of a completed journal replay
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayFinished {
+    pub event_id: String,
+    /// Messages re-published
+    pub published: usize,
+}
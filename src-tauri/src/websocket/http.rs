@@ -0,0 +1,359 @@
+//! Plain HTTP on the WebSocket port
+//!
+//! The server reads each connection's request head first. WebSocket upgrades
+//! are handed to tungstenite together with the bytes already read (see
+//! `Prefixed`); anything else is answered here:
+//! - `GET /status`: JSON with the app version, connected clients, what the
+//!   displays currently show and the media cache stats
+//! - `GET /media/<media_id>`: a file from the media cache, with `Range`
+//!   support so displays can stream (and seek in) video backgrounds
+//!
+//! `HEAD` works on both. Requests are authorized like WebSocket handshakes
+//! (loopback, a pairing token in the `token` query parameter or an
+//! `Authorization: Bearer` header, or pairing not required), except that the
+//! pairing PIN is not accepted. Connections are kept alive between requests.
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio_tungstenite::tungstenite::handshake::server::Request;
+use tokio_tungstenite::tungstenite::http::Version;
+
+/// Largest request head accepted
+pub const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Idle time after which a kept-alive connection is closed
+pub const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Most headers parsed per request
+const MAX_HEADERS: usize = 64;
+
+/// Media cache statistics
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub entry_count: usize,
+    pub total_size: u64,
+    pub max_size: u64,
+}
+
+/// The media cache the HTTP endpoints serve from
+///
+/// The cache itself lives in the app (see `commands`), which registers it with
+/// `WebSocketServer::set_media_library`.
+pub trait MediaLibrary: Send + Sync {
+    /// Cached file for a media ID
+    fn path(&self, media_id: &str) -> Option<PathBuf>;
+
+    /// Reported by `/status`
+    fn stats(&self) -> Option<CacheStats>;
+}
+
+/// Read from `stream` into `buf` until it holds a complete request head
+///
+/// Returns the length of the head (bytes after it are the start of the next
+/// request or the WebSocket stream), or None if the peer closed the connection
+/// before sending anything.
+pub async fn read_head<S>(stream: &mut S, buf: &mut Vec<u8>) -> io::Result<Option<usize>>
+where
+    S: AsyncRead + Unpin,
+{
+    loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            return Ok(Some(end + 4));
+        }
+        if buf.len() >= MAX_HEAD_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request head too large"));
+        }
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Parse a request head
+pub fn parse_request(head: &[u8]) -> Result<Request, String> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut parsed = httparse::Request::new(&mut headers);
+    match parsed.parse(head) {
+        Ok(httparse::Status::Complete(_)) => {}
+        Ok(httparse::Status::Partial) => return Err("Incomplete request head".to_string()),
+        Err(e) => return Err(format!("Invalid request: {}", e)),
+    }
+
+    let version = match parsed.version {
+        Some(0) => Version::HTTP_10,
+        _ => Version::HTTP_11,
+    };
+    let mut builder = Request::builder()
+        .method(parsed.method.unwrap_or_default())
+        .uri(parsed.path.unwrap_or_default())
+        .version(version);
+    for header in parsed.headers.iter() {
+        builder = builder.header(header.name, header.value);
+    }
+    builder.body(()).map_err(|e| format!("Invalid request: {}", e))
+}
+
+fn header<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Whether the request asks for a WebSocket upgrade
+pub fn is_websocket_upgrade(req: &Request) -> bool {
+    header(req, "upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Whether the client wants the connection kept open after the response
+pub fn keep_alive(req: &Request) -> bool {
+    let connection = header(req, "connection").unwrap_or_default();
+    if req.version() == Version::HTTP_10 {
+        connection.eq_ignore_ascii_case("keep-alive")
+    } else {
+        !connection.eq_ignore_ascii_case("close")
+    }
+}
+
+/// Part of a file to send in answer to a `Range` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// No range, or a form that is not supported (such as multiple ranges)
+    Full,
+    /// First and last byte, inclusive
+    Partial(u64, u64),
+    /// The range lies outside the file
+    Unsatisfiable,
+}
+
+/// Resolve a `Range` header against a file of `len` bytes
+pub fn parse_range(header: Option<&str>, len: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|value| value.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    let (first, last) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // bytes=-N: the last N bytes
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 || len == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+            (len.saturating_sub(suffix), len - 1)
+        }
+        // bytes=N-
+        (Ok(start), Err(_)) if end.is_empty() => (start, len.saturating_sub(1)),
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+        _ => return ByteRange::Full,
+    };
+    if first >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(first, last)
+}
+
+/// Content type for a cached file, by extension
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    match extension.as_str() {
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "wav" => "audio/wav",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Write a response with a small in-memory body
+pub async fn write_response<S>(
+    stream: &mut S,
+    status: u16,
+    reason: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+    head_only: bool,
+) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut response = format!("HTTP/1.1 {} {}\r\nContent-Length: {}\r\n", status, reason, body.len());
+    for (name, value) in common_headers().iter().chain(headers) {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    stream.write_all(response.as_bytes()).await?;
+    if !head_only {
+        stream.write_all(body).await?;
+    }
+    stream.flush().await
+}
+
+/// A plain-text error response
+pub async fn write_error<S>(stream: &mut S, status: u16, reason: &str, head_only: bool) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let headers = [("Content-Type", "text/plain; charset=utf-8".to_string())];
+    write_response(stream, status, reason, &headers, reason.as_bytes(), head_only).await
+}
+
+/// Serve a file, or the part of it a `Range` header asks for
+pub async fn write_file<S>(stream: &mut S, path: &Path, range: Option<&str>, head_only: bool) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut file = match File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return write_error(stream, 404, "Not Found", head_only).await;
+        }
+        Err(e) => return Err(e),
+    };
+    let len = file.metadata().await?.len();
+    let content_type = ("Content-Type", content_type(path).to_string());
+    let accept_ranges = ("Accept-Ranges", "bytes".to_string());
+
+    let (status, reason, start, count, mut headers) = match parse_range(range, len) {
+        ByteRange::Full => (200, "OK", 0, len, vec![content_type, accept_ranges]),
+        ByteRange::Partial(start, end) => {
+            let content_range = ("Content-Range", format!("bytes {}-{}/{}", start, end, len));
+            (206, "Partial Content", start, end - start + 1, vec![content_type, accept_ranges, content_range])
+        }
+        ByteRange::Unsatisfiable => {
+            let headers = [("Content-Range", format!("bytes */{}", len))];
+            return write_response(stream, 416, "Range Not Satisfiable", &headers, b"", head_only).await;
+        }
+    };
+    headers.extend(common_headers());
+
+    let mut response = format!("HTTP/1.1 {} {}\r\nContent-Length: {}\r\n", status, reason, count);
+    for (name, value) in &headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    stream.write_all(response.as_bytes()).await?;
+    if !head_only {
+        file.seek(io::SeekFrom::Start(start)).await?;
+        tokio::io::copy(&mut file.take(count), stream).await?;
+    }
+    stream.flush().await
+}
+
+/// Headers on every response; displays fetch from their own origin, so allow any
+fn common_headers() -> [(&'static str, String); 2] {
+    [
+        ("Access-Control-Allow-Origin", "*".to_string()),
+        ("Cache-Control", "no-cache".to_string()),
+    ]
+}
+
+/// A stream that yields `prefix` before reading from `inner`
+///
+/// Used to hand a connection to the WebSocket handshake after its request
+/// head has already been read.
+pub struct Prefixed<S> {
+    prefix: Vec<u8>,
+    position: usize,
+    inner: S,
+}
+
+impl<S> Prefixed<S> {
+    pub fn new(prefix: Vec<u8>, inner: S) -> Self {
+        Self { prefix, position: 0, inner }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Prefixed<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.position < self.prefix.len() {
+            let remaining = &self.prefix[self.position..];
+            let count = remaining.len().min(buf.remaining());
+            buf.put_slice(&remaining[..count]);
+            self.position += count;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Prefixed<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_forms() {
+        assert_eq!(parse_range(None, 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-9"), 100), ByteRange::Partial(0, 9));
+        assert_eq!(parse_range(Some("bytes=90-"), 100), ByteRange::Partial(90, 99));
+        assert_eq!(parse_range(Some("bytes=-10"), 100), ByteRange::Partial(90, 99));
+        // Clamped to the end of the file
+        assert_eq!(parse_range(Some("bytes=50-500"), 100), ByteRange::Partial(50, 99));
+        assert_eq!(parse_range(Some("bytes=-500"), 100), ByteRange::Partial(0, 99));
+        // Unsupported forms fall back to the whole file
+        assert_eq!(parse_range(Some("bytes=0-1,5-9"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("items=0-9"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=9-0"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=100-"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+    }
+
+    #[tokio::test]
+    async fn test_head_is_read_and_the_rest_kept() {
+        let data = b"GET /status?token=abc HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\nGET /next";
+        let mut stream = &data[..];
+        let mut buf = Vec::new();
+        let head_len = read_head(&mut stream, &mut buf).await.unwrap().unwrap();
+        assert_eq!(&buf[head_len..], b"GET /next");
+
+        let req = parse_request(&buf[..head_len]).unwrap();
+        assert_eq!(req.uri().path(), "/status");
+        assert!(!is_websocket_upgrade(&req));
+        assert!(!keep_alive(&req));
+
+        // Bytes already read are replayed before the rest of the stream
+        let mut prefixed = Prefixed::new(b"ab".to_vec(), &b"cd"[..]);
+        let mut all = String::new();
+        prefixed.read_to_string(&mut all).await.unwrap();
+        assert_eq!(all, "abcd");
+    }
+}
@@ -0,0 +1,273 @@
+//! Append-only journal of published messages, one JSONL file per event
+//!
+//! Every message with an `event_id` the server publishes is appended to
+//! `<dir>/<event_id>.jsonl` together with the time it went out, so a service
+//! can be re-driven onto the displays later: for rehearsals, for training new
+//! operators, or to reproduce what a screen did at a given moment.
+//!
+//! Each line is written with a single `write`, so a crash loses at most the
+//! line being written; `read` skips lines it cannot parse.
+
+use crate::websocket::types::{Envelope, WsMessage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+const JOURNAL_EXTENSION: &str = "jsonl";
+
+/// One published message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Unix milliseconds when the message was published
+    pub recorded_at: i64,
+    #[serde(flatten)]
+    pub envelope: Envelope,
+}
+
+/// A recorded event, as listed for the operator
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalInfo {
+    pub event_id: String,
+    /// File size in bytes
+    pub size: u64,
+    /// Unix milliseconds of the last write
+    pub modified: i64,
+}
+
+/// Journal files of the events published so far
+#[derive(Default)]
+pub struct Journal {
+    dir: Option<PathBuf>,
+    files: HashMap<String, File>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start journaling into `dir` (nothing is recorded until this is set)
+    pub fn set_dir(&mut self, dir: PathBuf) {
+        self.dir = Some(dir);
+        self.files.clear();
+    }
+
+    /// Journal file of an event, if journaling is enabled and the ID is usable
+    /// as a file name
+    pub fn path_for(&self, event_id: &str) -> Option<PathBuf> {
+        let valid = !event_id.is_empty()
+            && event_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return None;
+        }
+        Some(self.dir.as_ref()?.join(format!("{}.{}", event_id, JOURNAL_EXTENSION)))
+    }
+
+    /// Append a published message to its event's journal
+    ///
+    /// Messages without an event are not journaled.
+    pub fn record(&mut self, recorded_at: i64, message: &WsMessage, seq: Option<u64>) -> Result<(), String> {
+        let Some(event_id) = message.event_id() else {
+            return Ok(());
+        };
+        if self.dir.is_none() {
+            return Ok(());
+        }
+        let path = self
+            .path_for(event_id)
+            .ok_or_else(|| format!("Event ID {:?} cannot be journaled", event_id))?;
+
+        let entry = JournalEntry {
+            recorded_at,
+            envelope: Envelope::new(message.clone(), seq),
+        };
+        let mut line = serde_json::to_string(&entry)
+            .map_err(|e| format!("Failed to serialize journal entry: {}", e))?;
+        line.push('\n');
+
+        if !self.files.contains_key(event_id) {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|e| format!("Failed to create journal dir: {}", e))?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| format!("Failed to open journal {}: {}", path.display(), e))?;
+            self.files.insert(event_id.to_string(), file);
+        }
+        let file = self.files.get_mut(event_id).expect("journal file was just opened");
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            // Reopen on the next write (e.g. the file was deleted)
+            self.files.remove(event_id);
+            return Err(format!("Failed to write journal {}: {}", path.display(), e));
+        }
+        Ok(())
+    }
+
+    /// Every recorded event, most recently written first
+    pub fn list(&self) -> Result<Vec<JournalInfo>, String> {
+        let Some(dir) = &self.dir else {
+            return Ok(Vec::new());
+        };
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read journal dir: {}", e)),
+        };
+
+        let mut journals: Vec<JournalInfo> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension()? != JOURNAL_EXTENSION {
+                    return None;
+                }
+                let metadata = entry.metadata().ok()?;
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_millis() as i64)
+                    .unwrap_or_default();
+                Some(JournalInfo {
+                    event_id: path.file_stem()?.to_string_lossy().into_owned(),
+                    size: metadata.len(),
+                    modified,
+                })
+            })
+            .collect();
+        journals.sort_by_key(|journal| std::cmp::Reverse(journal.modified));
+        Ok(journals)
+    }
+}
+
+/// Read a journal file, skipping lines that cannot be parsed
+pub fn read(path: &Path) -> Result<Vec<JournalEntry>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read journal {}: {}", path.display(), e))?;
+    Ok(contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(number, line)| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                tracing::warn!("Skipping line {} of {}: {}", number + 1, path.display(), e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// A message to re-publish during a replay
+#[derive(Debug, Clone)]
+pub struct ReplayStep {
+    /// When to publish it, relative to the start of the replay
+    pub at: Duration,
+    /// When it was originally published
+    pub recorded_at: i64,
+    pub message: WsMessage,
+}
+
+/// Lay out a replay of `entries` at `speed` times real time
+///
+/// Entries recorded before `start_at` (Unix milliseconds) are published
+/// straight away, so the displays show the state as of that moment before
+/// the replay continues in real time.
+pub fn schedule(entries: Vec<JournalEntry>, speed: f64, start_at: Option<i64>) -> Result<Vec<ReplayStep>, String> {
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err(format!("Invalid replay speed {}", speed));
+    }
+    let Some(first) = entries.iter().map(|entry| entry.recorded_at).min() else {
+        return Ok(Vec::new());
+    };
+    let origin = start_at.unwrap_or(first).max(first);
+
+    let mut steps: Vec<ReplayStep> = entries
+        .into_iter()
+        .map(|entry| {
+            let elapsed = (entry.recorded_at - origin).max(0) as f64 / speed;
+            ReplayStep {
+                at: Duration::from_secs_f64(elapsed / 1000.0),
+                recorded_at: entry.recorded_at,
+                message: entry.envelope.message,
+            }
+        })
+        .collect();
+    // Stable, so messages recorded in the same millisecond keep their order
+    steps.sort_by_key(|step| step.recorded_at);
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::types::SlideData;
+
+    fn slide(index: usize) -> WsMessage {
+        WsMessage::Slide(SlideData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            slide_index: index,
+            timestamp: 1234567890,
+            execute_at: None,
+        })
+    }
+
+    #[test]
+    fn test_record_and_read_back() {
+        let dir = std::env::temp_dir().join(format!("mw-journal-test-{}", uuid::Uuid::new_v4()));
+        let mut journal = Journal::new();
+        // Disabled until a directory is set
+        journal.record(1_000, &slide(0), Some(1)).unwrap();
+        assert!(journal.list().unwrap().is_empty());
+
+        journal.set_dir(dir.clone());
+        journal.record(1_000, &slide(1), Some(1)).unwrap();
+        journal.record(2_500, &slide(2), Some(2)).unwrap();
+        journal.record(3_000, &WsMessage::Ping, None).unwrap();
+
+        let path = journal.path_for("event-456").unwrap();
+        // A torn final line from a crash is skipped
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"recorded_at\":4").unwrap();
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].recorded_at, 2_500);
+        assert_eq!(entries[1].envelope.seq, Some(2));
+
+        let listed = journal.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].event_id, "event-456");
+        assert!(journal.path_for("../etc/passwd").is_none());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_schedule_speed_and_start() {
+        let entries = [(1_000, 1), (3_000, 2), (5_000, 3), (9_000, 4)]
+            .into_iter()
+            .map(|(recorded_at, index)| JournalEntry {
+                recorded_at,
+                envelope: Envelope::new(slide(index), None),
+            })
+            .collect::<Vec<_>>();
+
+        let steps = schedule(entries.clone(), 2.0, None).unwrap();
+        let at: Vec<u64> = steps.iter().map(|step| step.at.as_millis() as u64).collect();
+        assert_eq!(at, vec![0, 1_000, 2_000, 4_000]);
+
+        // Jump to 4s in: the first two catch the displays up immediately
+        let steps = schedule(entries.clone(), 1.0, Some(4_000)).unwrap();
+        let at: Vec<u64> = steps.iter().map(|step| step.at.as_millis() as u64).collect();
+        assert_eq!(at, vec![0, 0, 1_000, 5_000]);
+
+        assert!(schedule(entries, 0.0, None).is_err());
+    }
+}
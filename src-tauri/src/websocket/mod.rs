@@ -1,5 +1,22 @@
+pub mod client;
+pub mod clients;
+pub mod clock;
+pub mod codec;
+pub mod delivery;
+pub mod events;
+pub mod http;
+pub mod journal;
+pub mod pairing;
+pub mod precache;
+pub mod queue;
+pub mod roles;
 pub mod server;
+pub mod snapshot;
+pub mod tls;
+pub mod transfer;
 pub mod types;
 
-pub use server::WebSocketServer;
+pub use delivery::DeliveryReport;
+pub use events::ServerEvent;
+pub use server::{ServerConfig, WebSocketServer};
 pub use types::{WsMessage, LyricsData, SlideData};
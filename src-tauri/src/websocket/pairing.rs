@@ -0,0 +1,432 @@
+//! Pairing and connection authentication for the WebSocket server
+//!
+//! A display shows a short-lived PIN (or a QR code containing it). A controller
+//! proves it knows the PIN by connecting with `?pin=<pin>`; the server then
+//! issues a per-pair token that must accompany every later connection, either
+//! as `?token=<token>` (browsers cannot set WebSocket headers) or as an
+//! `Authorization: Bearer <token>` header.
+//!
+//! Only a SHA-256 hash of each token is kept, so the persisted pairings cannot
+//! be replayed by someone reading the store file.
+//!
+//! Pairing is required by default. The operator can turn it off to let any
+//! controller on the network connect without credentials, as before pairing
+//! existed.
+
+use crate::websocket::roles::Role;
+use base64::Engine;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::handshake::server::Request;
+
+/// How long a pairing PIN stays valid
+pub const DEFAULT_PIN_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Wrong PIN attempts allowed before the pending PIN is discarded
+const MAX_PIN_ATTEMPTS: u32 = 5;
+
+/// A persisted controller pairing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pairing {
+    pub pair_id: String,
+    /// Name the controller supplied when pairing (e.g. "Sound booth iPad")
+    pub client_name: String,
+    /// Hex-encoded SHA-256 of the pairing token
+    pub token_hash: String,
+    pub created_at: String,
+    #[serde(default)]
+    pub last_used_at: Option<String>,
+}
+
+/// Pairing info safe to hand to the frontend (no token hash)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairingSummary {
+    pub pair_id: String,
+    pub client_name: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+impl From<&Pairing> for PairingSummary {
+    fn from(pairing: &Pairing) -> Self {
+        Self {
+            pair_id: pairing.pair_id.clone(),
+            client_name: pairing.client_name.clone(),
+            created_at: pairing.created_at.clone(),
+            last_used_at: pairing.last_used_at.clone(),
+        }
+    }
+}
+
+/// PIN the display shows to the operator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairingCode {
+    pub pin: String,
+    /// RFC 3339 expiry time
+    pub expires_at: String,
+}
+
+/// Credentials extracted from the handshake request
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HandshakeCredentials {
+    pub token: Option<String>,
+    pub pin: Option<String>,
+    pub client_name: Option<String>,
+    /// Role the client asks for (see `roles`)
+    pub role: Option<Role>,
+}
+
+impl HandshakeCredentials {
+    /// Read `token`, `pin`, `name` and `role` from the query string and the Authorization header
+    pub fn from_request(req: &Request) -> Self {
+        let mut creds = Self::default();
+
+        if let Some(query) = req.uri().query() {
+            for pair in query.split('&') {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                let value = urlencoding::decode(value)
+                    .map(|v| v.into_owned())
+                    .unwrap_or_else(|_| value.to_string());
+                if value.is_empty() {
+                    continue;
+                }
+                match key {
+                    "token" => creds.token = Some(value),
+                    "pin" => creds.pin = Some(value),
+                    "name" => creds.client_name = Some(value),
+                    "role" => creds.role = Role::parse(&value),
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(header) = req.headers().get("authorization").and_then(|v| v.to_str().ok()) {
+            if let Some(token) = header.strip_prefix("Bearer ") {
+                creds.token = Some(token.trim().to_string());
+            }
+        }
+
+        creds
+    }
+}
+
+/// Result of authenticating a handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthOutcome {
+    /// Loopback connection from this device (e.g. the display's own webview)
+    Local,
+    /// Pairing is not required, connection accepted without credentials
+    Open,
+    /// Connection presented a valid pairing token
+    Paired { pair_id: String },
+    /// Connection redeemed the pairing PIN and was issued a new token
+    NewlyPaired { pair_id: String, token: String },
+}
+
+impl AuthOutcome {
+    pub fn pair_id(&self) -> Option<&str> {
+        match self {
+            AuthOutcome::Paired { pair_id } | AuthOutcome::NewlyPaired { pair_id, .. } => Some(pair_id),
+            AuthOutcome::Local | AuthOutcome::Open => None,
+        }
+    }
+}
+
+struct PendingPin {
+    pin: String,
+    expires_at: Instant,
+    attempts: u32,
+}
+
+/// Holds the active PIN and the set of paired controllers
+pub struct PairingManager {
+    pairings: HashMap<String, Pairing>,
+    pending: Option<PendingPin>,
+    require_pairing: bool,
+}
+
+impl PairingManager {
+    pub fn new() -> Self {
+        Self {
+            pairings: HashMap::new(),
+            pending: None,
+            require_pairing: true,
+        }
+    }
+
+    /// Replace the known pairings (used when loading from the store)
+    pub fn load(&mut self, pairings: Vec<Pairing>) {
+        self.pairings = pairings.into_iter().map(|p| (p.pair_id.clone(), p)).collect();
+    }
+
+    /// All pairings, for persisting
+    pub fn pairings(&self) -> Vec<Pairing> {
+        let mut pairings: Vec<Pairing> = self.pairings.values().cloned().collect();
+        pairings.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        pairings
+    }
+
+    pub fn summaries(&self) -> Vec<PairingSummary> {
+        self.pairings().iter().map(PairingSummary::from).collect()
+    }
+
+    pub fn require_pairing(&self) -> bool {
+        self.require_pairing
+    }
+
+    pub fn set_require_pairing(&mut self, required: bool) {
+        self.require_pairing = required;
+    }
+
+    /// Generate a new 6-digit PIN, replacing any pending one
+    pub fn begin_pairing(&mut self, ttl: Duration) -> PairingCode {
+        let pin = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+        self.pending = Some(PendingPin {
+            pin: pin.clone(),
+            expires_at: Instant::now() + ttl,
+            attempts: 0,
+        });
+
+        let expires_at = chrono::Utc::now()
+            + chrono::Duration::from_std(ttl).unwrap_or_else(|_| chrono::Duration::zero());
+        PairingCode {
+            pin,
+            expires_at: expires_at.to_rfc3339(),
+        }
+    }
+
+    /// Discard the pending PIN
+    pub fn cancel_pairing(&mut self) {
+        self.pending = None;
+    }
+
+    /// Forget a pairing. Returns false if it did not exist.
+    pub fn revoke(&mut self, pair_id: &str) -> bool {
+        self.pairings.remove(pair_id).is_some()
+    }
+
+    /// Decide whether a handshake may proceed
+    pub fn authenticate(
+        &mut self,
+        creds: &HandshakeCredentials,
+        is_loopback: bool,
+    ) -> Result<AuthOutcome, String> {
+        if let Some(token) = &creds.token {
+            let hash = hash_token(token);
+            if let Some(pairing) = self.pairings.values_mut().find(|p| p.token_hash == hash) {
+                pairing.last_used_at = Some(chrono::Utc::now().to_rfc3339());
+                return Ok(AuthOutcome::Paired { pair_id: pairing.pair_id.clone() });
+            }
+            // A stale token is not fatal for connections that need no credentials
+            if !is_loopback && self.require_pairing {
+                return Err("Unknown or revoked pairing token".to_string());
+            }
+        }
+
+        if let Some(pin) = &creds.pin {
+            let name = creds.client_name.clone().unwrap_or_else(|| "Controller".to_string());
+            return self.redeem_pin(pin, &name);
+        }
+
+        if is_loopback {
+            Ok(AuthOutcome::Local)
+        } else if !self.require_pairing {
+            Ok(AuthOutcome::Open)
+        } else {
+            Err("Pairing required".to_string())
+        }
+    }
+
+    fn redeem_pin(&mut self, pin: &str, client_name: &str) -> Result<AuthOutcome, String> {
+        let Some(pending) = self.pending.as_mut() else {
+            return Err("No pairing in progress".to_string());
+        };
+
+        if Instant::now() > pending.expires_at {
+            self.pending = None;
+            return Err("Pairing PIN expired".to_string());
+        }
+
+        if pending.pin != pin {
+            pending.attempts += 1;
+            if pending.attempts >= MAX_PIN_ATTEMPTS {
+                tracing::warn!("Too many wrong pairing PIN attempts, discarding PIN");
+                self.pending = None;
+            }
+            return Err("Wrong pairing PIN".to_string());
+        }
+
+        // PINs are single use
+        self.pending = None;
+
+        let token = generate_token();
+        let pairing = Pairing {
+            pair_id: uuid::Uuid::new_v4().to_string(),
+            client_name: client_name.to_string(),
+            token_hash: hash_token(&token),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            last_used_at: None,
+        };
+        let pair_id = pairing.pair_id.clone();
+        tracing::info!("Paired new controller '{}' ({})", client_name, pair_id);
+        self.pairings.insert(pair_id.clone(), pairing);
+
+        Ok(AuthOutcome::NewlyPaired { pair_id, token })
+    }
+}
+
+impl Default for PairingManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Generate a random URL-safe token
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Hex-encoded SHA-256 of a token
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin_creds(pin: &str) -> HandshakeCredentials {
+        HandshakeCredentials {
+            pin: Some(pin.to_string()),
+            client_name: Some("Booth".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn token_creds(token: &str) -> HandshakeCredentials {
+        HandshakeCredentials {
+            token: Some(token.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn pair(manager: &mut PairingManager) -> (String, String) {
+        let code = manager.begin_pairing(DEFAULT_PIN_TTL);
+        match manager.authenticate(&pin_creds(&code.pin), false).unwrap() {
+            AuthOutcome::NewlyPaired { pair_id, token } => (pair_id, token),
+            other => panic!("Expected NewlyPaired, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_remote_requires_pairing() {
+        let mut manager = PairingManager::new();
+        assert!(manager.require_pairing());
+        assert!(manager.authenticate(&HandshakeCredentials::default(), false).is_err());
+        assert_eq!(
+            manager.authenticate(&HandshakeCredentials::default(), true),
+            Ok(AuthOutcome::Local)
+        );
+
+        manager.set_require_pairing(false);
+        assert_eq!(
+            manager.authenticate(&HandshakeCredentials::default(), false),
+            Ok(AuthOutcome::Open)
+        );
+    }
+
+    #[test]
+    fn test_pin_pairing_issues_token() {
+        let mut manager = PairingManager::new();
+        let (pair_id, token) = pair(&mut manager);
+
+        assert_eq!(
+            manager.authenticate(&token_creds(&token), false),
+            Ok(AuthOutcome::Paired { pair_id: pair_id.clone() })
+        );
+        assert!(manager.authenticate(&token_creds("not-a-token"), false).is_err());
+
+        // The token itself is never stored
+        let stored = manager.pairings();
+        assert_eq!(stored.len(), 1);
+        assert_ne!(stored[0].token_hash, token);
+        assert_eq!(stored[0].client_name, "Booth");
+        assert!(stored[0].last_used_at.is_some());
+    }
+
+    #[test]
+    fn test_pin_is_single_use() {
+        let mut manager = PairingManager::new();
+        let code = manager.begin_pairing(DEFAULT_PIN_TTL);
+        assert!(manager.authenticate(&pin_creds(&code.pin), false).is_ok());
+        assert!(manager.authenticate(&pin_creds(&code.pin), false).is_err());
+    }
+
+    #[test]
+    fn test_pin_expires() {
+        let mut manager = PairingManager::new();
+        let code = manager.begin_pairing(Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(manager.authenticate(&pin_creds(&code.pin), false).is_err());
+    }
+
+    #[test]
+    fn test_wrong_pin_attempts_discard_pin() {
+        let mut manager = PairingManager::new();
+        let code = manager.begin_pairing(DEFAULT_PIN_TTL);
+        let wrong = if code.pin == "000000" { "111111" } else { "000000" };
+        for _ in 0..MAX_PIN_ATTEMPTS {
+            assert!(manager.authenticate(&pin_creds(wrong), false).is_err());
+        }
+        assert!(manager.authenticate(&pin_creds(&code.pin), false).is_err());
+    }
+
+    #[test]
+    fn test_revoke() {
+        let mut manager = PairingManager::new();
+        let (pair_id, token) = pair(&mut manager);
+
+        assert!(manager.revoke(&pair_id));
+        assert!(!manager.revoke(&pair_id));
+        assert!(manager.authenticate(&token_creds(&token), false).is_err());
+        // Loopback still works with a stale token
+        assert_eq!(manager.authenticate(&token_creds(&token), true), Ok(AuthOutcome::Local));
+    }
+
+    #[test]
+    fn test_load_round_trip() {
+        let mut manager = PairingManager::new();
+        let (_, token) = pair(&mut manager);
+
+        let mut restored = PairingManager::new();
+        restored.load(manager.pairings());
+        assert!(restored.authenticate(&token_creds(&token), false).is_ok());
+    }
+
+    #[test]
+    fn test_credentials_from_request() {
+        let req = Request::builder()
+            .uri("/?pin=123456&name=Sound%20Booth")
+            .body(())
+            .unwrap();
+        let creds = HandshakeCredentials::from_request(&req);
+        assert_eq!(creds.pin.as_deref(), Some("123456"));
+        assert_eq!(creds.client_name.as_deref(), Some("Sound Booth"));
+        assert_eq!(creds.token, None);
+
+        let req = Request::builder()
+            .uri("/")
+            .header("Authorization", "Bearer abc123")
+            .body(())
+            .unwrap();
+        assert_eq!(HandshakeCredentials::from_request(&req).token.as_deref(), Some("abc123"));
+    }
+}
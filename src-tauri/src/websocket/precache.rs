@@ -0,0 +1,230 @@
+//! Aggregated precache readiness per event
+//!
+//! When a `precache` request goes out the server remembers which media items
+//! the event needs. Displays report progress (`precache_status`) and completion
+//! (`precache_ack`); the tracker folds those into a display × media matrix and
+//! notices the moment each display becomes ready.
+
+use crate::websocket::types::{PrecacheData, PrecacheReport, PrecacheState, PrecacheStatus};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Precache state of one display for one event
+#[derive(Debug, Clone, Serialize)]
+pub struct DisplayReadiness {
+    pub display_id: String,
+    /// Every media item is cached (or the display acked as ready)
+    pub ready: bool,
+    /// Status per media ID; None if the display has not reported that item yet
+    pub media: BTreeMap<String, Option<PrecacheStatus>>,
+}
+
+/// Readiness of every known display for one event
+#[derive(Debug, Clone, Serialize)]
+pub struct ReadinessMatrix {
+    pub event_id: String,
+    /// Media IDs requested for the event
+    pub media_ids: Vec<String>,
+    pub displays: Vec<DisplayReadiness>,
+    /// There is at least one display and every display listed is ready
+    pub all_ready: bool,
+}
+
+/// A display finished precaching an event
+#[derive(Debug, Clone, Serialize)]
+pub struct PrecacheReady {
+    pub event_id: String,
+    pub display_id: String,
+}
+
+#[derive(Debug, Default)]
+struct EventPrecache {
+    media_ids: Vec<String>,
+    /// display -> media ID -> latest status
+    statuses: HashMap<String, HashMap<String, PrecacheStatus>>,
+    /// Displays that acked as ready
+    acked: HashSet<String>,
+    /// Displays already reported as ready
+    ready: HashSet<String>,
+}
+
+impl EventPrecache {
+    fn is_ready(&self, display_id: &str) -> bool {
+        if self.acked.contains(display_id) {
+            return true;
+        }
+        let Some(statuses) = self.statuses.get(display_id) else {
+            return false;
+        };
+        !self.media_ids.is_empty()
+            && self.media_ids.iter().all(|id| {
+                statuses.get(id).is_some_and(|status| status.status == PrecacheState::Ready)
+            })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PrecacheTracker {
+    events: HashMap<String, EventPrecache>,
+}
+
+impl PrecacheTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A precache request went out: start over for that event
+    pub fn expect(&mut self, request: &PrecacheData) {
+        let media_ids = request.media.iter().map(|item| item.media_id.clone()).collect();
+        self.events.insert(
+            request.event_id.clone(),
+            EventPrecache { media_ids, ..Default::default() },
+        );
+    }
+
+    /// Fold in a status report or ack. Returns the readiness notice if this
+    /// report made the display ready.
+    pub fn update(&mut self, display_id: &str, report: &PrecacheReport, is_ack: bool) -> Option<PrecacheReady> {
+        let event = self.events.entry(report.event_id.clone()).or_default();
+
+        let statuses = event.statuses.entry(display_id.to_string()).or_default();
+        for status in &report.statuses {
+            statuses.insert(status.media_id.clone(), status.clone());
+        }
+        if is_ack && report.ready {
+            event.acked.insert(display_id.to_string());
+        }
+
+        if event.is_ready(display_id) && event.ready.insert(display_id.to_string()) {
+            return Some(PrecacheReady {
+                event_id: report.event_id.clone(),
+                display_id: display_id.to_string(),
+            });
+        }
+        None
+    }
+
+    /// Display × media matrix for an event. `displays` lists displays that
+    /// should appear even if they have not reported anything yet.
+    pub fn matrix(&self, event_id: &str, displays: &[String]) -> ReadinessMatrix {
+        let event = self.events.get(event_id);
+        let media_ids = event.map(|event| event.media_ids.clone()).unwrap_or_default();
+
+        let mut display_ids: Vec<String> = displays.to_vec();
+        if let Some(event) = event {
+            display_ids.extend(event.statuses.keys().cloned());
+        }
+        display_ids.sort();
+        display_ids.dedup();
+
+        let displays = display_ids
+            .into_iter()
+            .map(|display_id| {
+                let statuses = event.and_then(|event| event.statuses.get(&display_id));
+                let mut media: BTreeMap<String, Option<PrecacheStatus>> = media_ids
+                    .iter()
+                    .map(|id| (id.clone(), statuses.and_then(|s| s.get(id)).cloned()))
+                    .collect();
+                // Items the display reported that were not part of the request
+                if let Some(statuses) = statuses {
+                    for (id, status) in statuses {
+                        media.entry(id.clone()).or_insert_with(|| Some(status.clone()));
+                    }
+                }
+                DisplayReadiness {
+                    ready: event.is_some_and(|event| event.is_ready(&display_id)),
+                    display_id,
+                    media,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        ReadinessMatrix {
+            event_id: event_id.to_string(),
+            media_ids,
+            all_ready: !displays.is_empty() && displays.iter().all(|display| display.ready),
+            displays,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::types::PrecacheMediaItem;
+
+    fn request(media_ids: &[&str]) -> PrecacheData {
+        PrecacheData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            media: media_ids
+                .iter()
+                .map(|id| PrecacheMediaItem {
+                    media_id: id.to_string(),
+                    url: format!("https://example.com/{}.jpg", id),
+                    media_type: "image".to_string(),
+                    expires_at: 1234567890,
+                })
+                .collect(),
+            songs: Vec::new(),
+        }
+    }
+
+    fn report(statuses: &[(&str, PrecacheState)], ready: bool) -> PrecacheReport {
+        PrecacheReport {
+            event_id: "event-456".to_string(),
+            ready,
+            statuses: statuses
+                .iter()
+                .map(|(id, state)| PrecacheStatus {
+                    media_id: id.to_string(),
+                    status: *state,
+                    progress: None,
+                    error: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_ready_once_all_media_cached() {
+        let mut tracker = PrecacheTracker::new();
+        tracker.expect(&request(&["media-1", "media-2"]));
+
+        let partial = report(&[("media-1", PrecacheState::Ready), ("media-2", PrecacheState::Downloading)], false);
+        assert!(tracker.update("display-a", &partial, false).is_none());
+
+        let done = report(&[("media-2", PrecacheState::Ready)], false);
+        let ready = tracker.update("display-a", &done, false).unwrap();
+        assert_eq!(ready.display_id, "display-a");
+
+        // Only reported once
+        assert!(tracker.update("display-a", &done, true).is_none());
+    }
+
+    #[test]
+    fn test_matrix_lists_silent_displays() {
+        let mut tracker = PrecacheTracker::new();
+        tracker.expect(&request(&["media-1"]));
+        tracker.update("display-a", &report(&[("media-1", PrecacheState::Error)], false), false);
+
+        let matrix = tracker.matrix("event-456", &["display-b".to_string()]);
+        assert_eq!(matrix.media_ids, vec!["media-1".to_string()]);
+        assert_eq!(matrix.displays.len(), 2);
+        assert_eq!(
+            matrix.displays[0].media["media-1"].as_ref().unwrap().status,
+            PrecacheState::Error
+        );
+        assert!(matrix.displays[1].media["media-1"].is_none());
+        assert!(!matrix.all_ready);
+    }
+
+    #[test]
+    fn test_ack_marks_ready() {
+        let mut tracker = PrecacheTracker::new();
+        tracker.expect(&request(&[]));
+        assert!(tracker.update("display-a", &report(&[], true), true).is_some());
+        assert!(tracker.matrix("event-456", &[]).all_ready);
+    }
+}
@@ -0,0 +1,337 @@
+//! Bounded outgoing queue per client
+//!
+//! Messages for a client wait here until its connection task writes them to
+//! the socket. A slow display therefore cannot build an unbounded backlog:
+//! - a lyrics/slide update replaces the ones still queued for the same event
+//!   and display (it moves to the back of the queue), so only the latest state
+//!   is sent after a burst of clicks
+//! - once `CLIENT_QUEUE_CAPACITY` messages are waiting, the oldest is dropped
+//!
+//! Depth, coalesced and dropped counts are reported in the client roster.
+
+use crate::websocket::types::WsMessage;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Messages queued per client before the oldest are dropped
+pub const CLIENT_QUEUE_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateKind {
+    Lyrics,
+    Slide,
+}
+
+/// How a queued message relates to later ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Coalesce {
+    /// A lyrics or slide update that later updates for the same event and display replace
+    Update {
+        kind: UpdateKind,
+        event_id: String,
+        target_display_id: Option<String>,
+        song_id: String,
+    },
+    /// A freeze: updates queued before it are still sent before it, so the
+    /// display freezes on the frame the operator saw
+    Barrier,
+}
+
+impl Coalesce {
+    pub fn for_message(message: &WsMessage) -> Option<Self> {
+        match message {
+            WsMessage::Lyrics(data) => Some(Coalesce::Update {
+                kind: UpdateKind::Lyrics,
+                event_id: data.event_id.clone(),
+                target_display_id: data.target_display_id.clone(),
+                song_id: data.song_id.clone(),
+            }),
+            WsMessage::Slide(data) => Some(Coalesce::Update {
+                kind: UpdateKind::Slide,
+                event_id: data.event_id.clone(),
+                target_display_id: data.target_display_id.clone(),
+                song_id: data.song_id.clone(),
+            }),
+            WsMessage::Freeze(_) => Some(Coalesce::Barrier),
+            _ => None,
+        }
+    }
+
+    /// Whether this message makes a queued one obsolete
+    fn supersedes(&self, queued: &Coalesce) -> bool {
+        let (
+            Coalesce::Update { kind, event_id, target_display_id, song_id },
+            Coalesce::Update {
+                kind: queued_kind,
+                event_id: queued_event_id,
+                target_display_id: queued_target,
+                song_id: queued_song_id,
+            },
+        ) = (self, queued)
+        else {
+            return false;
+        };
+        if event_id != queued_event_id || target_display_id != queued_target {
+            return false;
+        }
+        match (kind, queued_kind) {
+            (UpdateKind::Lyrics, UpdateKind::Lyrics) | (UpdateKind::Slide, UpdateKind::Slide) => true,
+            // A slide index from a different song is meaningless with the new lyrics
+            (UpdateKind::Lyrics, UpdateKind::Slide) => song_id != queued_song_id,
+            (UpdateKind::Slide, UpdateKind::Lyrics) => false,
+        }
+    }
+}
+
+/// Queue health of one client
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct QueueStats {
+    /// Messages waiting to be written
+    pub depth: usize,
+    pub capacity: usize,
+    /// Messages handed to the socket
+    pub sent: u64,
+    /// Updates replaced by a newer one before they were sent
+    pub coalesced: u64,
+    /// Messages dropped because the queue was full
+    pub dropped: u64,
+}
+
+/// The connection behind a queue is gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueClosed;
+
+impl std::fmt::Display for QueueClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "client queue closed")
+    }
+}
+
+impl std::error::Error for QueueClosed {}
+
+struct Queued {
+    message: Message,
+    coalesce: Option<Coalesce>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    items: VecDeque<Queued>,
+    /// No more messages are accepted; the rest of the queue is still sent
+    finished: bool,
+    /// The socket is gone; nothing more is sent
+    closed: bool,
+    stats: QueueStats,
+}
+
+struct Shared {
+    state: Mutex<QueueState>,
+    notify: Notify,
+}
+
+/// Handle to a client's outgoing queue (cloned into its connection task)
+#[derive(Clone)]
+pub struct ClientQueue {
+    shared: Arc<Shared>,
+}
+
+impl ClientQueue {
+    pub fn new(capacity: usize) -> Self {
+        let state = QueueState {
+            stats: QueueStats { capacity, ..Default::default() },
+            ..Default::default()
+        };
+        Self {
+            shared: Arc::new(Shared { state: Mutex::new(state), notify: Notify::new() }),
+        }
+    }
+
+    /// Queue a message, replacing the updates it supersedes
+    pub fn push(&self, message: Message, coalesce: Option<Coalesce>) -> Result<(), QueueClosed> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.finished || state.closed {
+            return Err(QueueClosed);
+        }
+
+        if let Some(new) = &coalesce {
+            // Only look back as far as the last barrier
+            let start = state
+                .items
+                .iter()
+                .rposition(|item| item.coalesce == Some(Coalesce::Barrier))
+                .map_or(0, |barrier| barrier + 1);
+            let before = state.items.len();
+            let mut index = 0;
+            state.items.retain(|item| {
+                let keep = index < start || !item.coalesce.as_ref().is_some_and(|queued| new.supersedes(queued));
+                index += 1;
+                keep
+            });
+            state.stats.coalesced += (before - state.items.len()) as u64;
+        }
+
+        if state.items.len() >= state.stats.capacity {
+            state.items.pop_front();
+            state.stats.dropped += 1;
+        }
+        state.items.push_back(Queued { message, coalesce });
+        state.stats.depth = state.items.len();
+        drop(state);
+
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+
+    /// Replace whatever is still queued with a final message (a close frame)
+    /// and accept nothing after it
+    pub fn close_with(&self, message: Message) {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.closed {
+            state.items.clear();
+            state.items.push_back(Queued { message, coalesce: None });
+            state.stats.depth = 1;
+        }
+        state.finished = true;
+        drop(state);
+        self.shared.notify.notify_one();
+    }
+
+    /// Accept no more messages; `pop` returns None once the queue is drained
+    pub fn finish(&self) {
+        self.shared.state.lock().unwrap().finished = true;
+        self.shared.notify.notify_one();
+    }
+
+    /// The socket is gone: drop the queue and refuse further messages
+    pub fn close(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        state.items.clear();
+        state.stats.depth = 0;
+    }
+
+    /// Next message to write, waiting for one if the queue is empty.
+    /// None once the queue is finished and drained (or closed).
+    pub async fn pop(&self) -> Option<Message> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if state.closed {
+                    return None;
+                }
+                if let Some(item) = state.items.pop_front() {
+                    state.stats.depth = state.items.len();
+                    state.stats.sent += 1;
+                    return Some(item.message);
+                }
+                if state.finished {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.shared.state.lock().unwrap().stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::types::{LyricsData, ScreenData, SlideData};
+
+    fn slide(song_id: &str, index: usize) -> (Message, Option<Coalesce>) {
+        let message = WsMessage::Slide(SlideData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: song_id.to_string(),
+            slide_index: index,
+            timestamp: 1234567890,
+            execute_at: None,
+        });
+        let coalesce = Coalesce::for_message(&message);
+        (Message::Text(format!("slide {} {}", song_id, index)), coalesce)
+    }
+
+    fn lyrics(song_id: &str) -> (Message, Option<Coalesce>) {
+        let message = WsMessage::Lyrics(LyricsData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: song_id.to_string(),
+            title: song_id.to_string(),
+            lyrics: "Verse 1".to_string(),
+            background_url: None,
+            timestamp: 1234567890,
+            execute_at: None,
+        });
+        let coalesce = Coalesce::for_message(&message);
+        (Message::Text(format!("lyrics {}", song_id)), coalesce)
+    }
+
+    fn freeze() -> (Message, Option<Coalesce>) {
+        let message = WsMessage::Freeze(ScreenData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            enabled: true,
+            timestamp: 1234567890,
+        });
+        (Message::Text("freeze".to_string()), Coalesce::for_message(&message))
+    }
+
+    async fn drain(queue: &ClientQueue) -> Vec<String> {
+        queue.finish();
+        let mut texts = Vec::new();
+        while let Some(message) = queue.pop().await {
+            texts.push(message.into_text().unwrap());
+        }
+        texts
+    }
+
+    #[tokio::test]
+    async fn test_slides_coalesce_to_latest() {
+        let queue = ClientQueue::new(CLIENT_QUEUE_CAPACITY);
+        let ping = (Message::Text("ping".to_string()), None);
+        for (message, coalesce) in [lyrics("song-a"), slide("song-a", 1), ping, slide("song-a", 2), slide("song-a", 3)] {
+            queue.push(message, coalesce).unwrap();
+        }
+
+        let stats = queue.stats();
+        assert_eq!((stats.depth, stats.coalesced), (3, 2));
+        assert_eq!(drain(&queue).await, vec!["lyrics song-a", "ping", "slide song-a 3"]);
+        assert_eq!(queue.stats().sent, 3);
+    }
+
+    #[tokio::test]
+    async fn test_new_song_supersedes_its_slides_but_not_past_a_freeze() {
+        let queue = ClientQueue::new(CLIENT_QUEUE_CAPACITY);
+        for (message, coalesce) in [lyrics("song-a"), slide("song-a", 4), freeze(), slide("song-a", 5), lyrics("song-b")] {
+            queue.push(message, coalesce).unwrap();
+        }
+
+        // What was on screen when freeze was pressed still reaches the display first
+        assert_eq!(drain(&queue).await, vec!["lyrics song-a", "slide song-a 4", "freeze", "lyrics song-b"]);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops_oldest_and_close_frame_jumps_the_queue() {
+        let queue = ClientQueue::new(2);
+        for i in 0..3 {
+            queue.push(Message::Text(i.to_string()), None).unwrap();
+        }
+        assert_eq!(queue.stats().dropped, 1);
+        assert_eq!(queue.pop().await.unwrap().into_text().unwrap(), "1");
+
+        queue.close_with(Message::Close(None));
+        assert!(queue.push(Message::Text("late".to_string()), None).is_err());
+        assert!(matches!(queue.pop().await, Some(Message::Close(None))));
+        assert!(queue.pop().await.is_none());
+    }
+}
@@ -0,0 +1,155 @@
+//! Connection roles and what each may send
+//!
+//! - controller: publishes content (lyrics, slides, screen commands, precache, media)
+//! - display: shows content; may only acknowledge it and report status
+//! - observer: read-only (e.g. a stage monitor or a second operator screen)
+//!
+//! Any connection may join and leave rooms.
+//!
+//! The highest role a connection may hold comes from how it authenticated.
+//! Loopback and paired connections may be controllers, and so may unpaired
+//! remote connections once the operator has turned pairing off (see
+//! `pairing`), which is how controllers connected before pairing existed.
+//!
+//! A connection starts with the highest role it may hold. A `role` handshake
+//! parameter or a `hello` lowers it; a hello with a `display_id` makes the
+//! connection a display unless it names another role. A role can only ever be
+//! lowered on a connection, so a display cannot talk its way back into
+//! publishing.
+
+use crate::websocket::pairing::AuthOutcome;
+use crate::websocket::types::{HelloData, WsMessage};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Ordered by privilege
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Observer,
+    Display,
+    Controller,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "observer" => Some(Role::Observer),
+            "display" => Some(Role::Display),
+            "controller" => Some(Role::Controller),
+            _ => None,
+        }
+    }
+
+    /// Highest role a connection authenticated with `auth` may hold
+    pub fn granted(auth: &AuthOutcome) -> Role {
+        match auth {
+            AuthOutcome::Local
+            | AuthOutcome::Open
+            | AuthOutcome::Paired { .. }
+            | AuthOutcome::NewlyPaired { .. } => Role::Controller,
+        }
+    }
+
+    /// Role a connection starts with: the one its handshake asked for, if
+    /// it is no more than the connection was granted
+    pub fn initial(auth: &AuthOutcome, requested: Option<Role>) -> Role {
+        let granted = Role::granted(auth);
+        requested.map_or(granted, |requested| requested.min(granted))
+    }
+
+    /// Whether a connection with this role may send `message`
+    pub fn may_send(self, message: &WsMessage) -> bool {
+        match message {
+            WsMessage::Ping | WsMessage::Hello(_) | WsMessage::ClockSync(_) => true,
+            WsMessage::Join(_) | WsMessage::Leave(_) => true,
+            WsMessage::Ack(_)
+            | WsMessage::PrecacheStatus(_)
+            | WsMessage::PrecacheAck(_)
+            | WsMessage::MediaTransferStatus(_) => self >= Role::Display,
+            WsMessage::Lyrics(_)
+            | WsMessage::Slide(_)
+            | WsMessage::Black(_)
+            | WsMessage::Clear(_)
+            | WsMessage::Logo(_)
+            | WsMessage::Freeze(_)
+            | WsMessage::Media(_)
+            | WsMessage::Precache(_)
+            | WsMessage::MediaTransferStart(_) => self == Role::Controller,
+            // Only ever sent by the server
+            WsMessage::Welcome(_) | WsMessage::Paired(_) | WsMessage::Error(_) => false,
+        }
+    }
+
+    /// Whether a connection with this role may send binary frames (media chunks)
+    pub fn may_send_binary(self) -> bool {
+        self == Role::Controller
+    }
+
+    /// Role after a `hello`, or Err if it asks for more than the connection has
+    pub fn after_hello(self, hello: &HelloData) -> Result<Role, String> {
+        let requested = hello.role.unwrap_or(if hello.display_id.is_some() {
+            Role::Display
+        } else {
+            self
+        });
+        if requested > self {
+            return Err(format!("Cannot raise role from {:?} to {:?}", self, requested));
+        }
+        Ok(requested)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::types::{AckData, ScreenData};
+
+    #[test]
+    fn test_permissions() {
+        let black = WsMessage::Black(ScreenData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            enabled: true,
+            timestamp: 1234567890,
+        });
+        let ack = WsMessage::Ack(AckData { seq: 1 });
+
+        assert!(Role::Controller.may_send(&black));
+        assert!(!Role::Display.may_send(&black));
+        assert!(Role::Display.may_send(&ack));
+        assert!(!Role::Observer.may_send(&ack));
+        assert!(Role::Observer.may_send(&WsMessage::Ping));
+        assert!(!Role::Display.may_send_binary());
+    }
+
+    #[test]
+    fn test_hello_can_only_lower_the_role() {
+        let display = HelloData {
+            display_id: Some("display-a".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(Role::Controller.after_hello(&display), Ok(Role::Display));
+        assert_eq!(Role::Controller.after_hello(&HelloData::default()), Ok(Role::Controller));
+
+        let controller = HelloData {
+            role: Some(Role::Controller),
+            ..Default::default()
+        };
+        assert!(Role::Display.after_hello(&controller).is_err());
+        assert_eq!(Role::parse("observer"), Some(Role::Observer));
+    }
+
+    #[test]
+    fn test_controller_role_comes_from_authentication() {
+        let paired = AuthOutcome::Paired { pair_id: "pair-1".to_string() };
+        assert_eq!(Role::initial(&AuthOutcome::Local, None), Role::Controller);
+        assert_eq!(Role::initial(&paired, None), Role::Controller);
+        assert_eq!(Role::initial(&paired, Some(Role::Observer)), Role::Observer);
+
+        // With pairing turned off, remote controllers publish as they did before pairing
+        assert_eq!(Role::initial(&AuthOutcome::Open, None), Role::Controller);
+        assert_eq!(Role::initial(&AuthOutcome::Open, Some(Role::Observer)), Role::Observer);
+    }
+}
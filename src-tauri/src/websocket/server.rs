@@ -4,43 +4,316 @@
 //! - Lyrics updates when songs are displayed
 //! - Slide navigation changes
 //! - Background media changes
+//!
+//! The server pings every client periodically and closes connections that stay
+//! silent past `CLIENT_TIMEOUT`, so half-open TCP connections do not linger.
+//! Per-client RTT and last-seen times are exposed through `connected_clients`,
+//! together with the stats of each client's bounded outgoing queue (see `queue`).
+//!
+//! Clients identify themselves with a `hello` frame after connecting. Messages
+//! with a `target_display_id` are then delivered only to the connection that
+//! registered that display, instead of being broadcast to every client. A
+//! hello that carries a `protocol_version` is answered with a `welcome` naming
+//! the negotiated version and capabilities. Clients that negotiated
+//! MessagePack or deflate get every later message in that encoding (see `codec`).
+//!
+//! State-changing messages are sequenced (see `types::Envelope`) and displays
+//! acknowledge them; `delivery` tracks which displays are behind or missed an
+//! update while offline.
+//!
+//! The last lyrics/slide per event and target display is kept (see `snapshot`)
+//! and replayed to clients as they connect and register, so a display that
+//! restarts mid-service picks up the current slide straight away.
+//!
+//! Clients can `join` rooms scoped by church and event (see `types::Room`);
+//! broadcasts then only reach the clients in their room, and joining replays
+//! the room's current state.
+//!
+//! Remote connections must be paired (see `pairing`) unless the operator turned
+//! pairing off: the handshake is rejected unless it carries a valid pairing
+//! token or the current pairing PIN.
+//!
+//! Updates may carry an `execute_at` time so every display switches in the same
+//! frame. The server measures each controller's clock offset with `clock_sync`
+//! round trips (see `clock`) and converts `execute_at` to its own clock when
+//! relaying; displays can measure the server's clock the same way.
+//!
+//! Every frame a client sends is parsed as a `WsMessage`; invalid frames and
+//! messages the connection's role may not send (see `roles`) are answered
+//! with an `error` and never relayed.
+//!
+//! With a TLS identity configured the server speaks wss:// on the same port.
+//! Loopback clients (the display's own webview) may still connect in plain
+//! text, since they cannot be taught to trust a self-signed certificate.
+//!
+//! The server can be stopped and restarted (e.g. after the machine switched
+//! networks) without losing its state; `ServerConfig` sets the bind address,
+//! a fixed port for firewalled networks, and IPv4/IPv6 dual-stack listening.
+//!
+//! Published messages are journaled per event (see `journal`) and can be
+//! replayed onto the displays later, in real time or faster.
+//!
+//! Media files can be pushed over the same connection as binary chunks (see
+//! `transfer`), in either direction.
+//!
+//! The port also answers plain HTTP (see `http`): `/status`, and files from
+//! the media cache so displays can stream video backgrounds from the controller.
 
-use crate::websocket::types::WsMessage;
-use futures_channel::mpsc::{unbounded, UnboundedSender};
+use crate::websocket::clients::{Client, ClientDisconnected, ClientInfo, ClientRegistry, RoomInfo};
+use crate::websocket::clock;
+use crate::websocket::codec::{self, FRAME_MESSAGE};
+use crate::websocket::delivery::{
+    DeliveryReport, DeliveryTracker, MissedUpdate, ACK_TIMEOUT, FORGET_DISPLAY_AFTER,
+};
+use crate::websocket::events::{ReplayFinished, ServerEvent, EVENT_CHANNEL_CAPACITY};
+use crate::websocket::http::{self, CacheStats, MediaLibrary, Prefixed, KEEP_ALIVE_TIMEOUT};
+use crate::websocket::journal::{self, Journal, JournalInfo, ReplayStep};
+use crate::websocket::pairing::{
+    AuthOutcome, HandshakeCredentials, PairingCode, PairingManager, PairingSummary, Pairing,
+};
+use crate::websocket::precache::{PrecacheTracker, ReadinessMatrix};
+use crate::websocket::queue::{ClientQueue, Coalesce, CLIENT_QUEUE_CAPACITY};
+use crate::websocket::snapshot::SnapshotStore;
+use crate::websocket::tls::{TlsIdentity, TLS_HANDSHAKE_RECORD};
+use crate::websocket::transfer::{ChunkFrame, ChunkOutcome, IncomingTransfers, OutgoingTransfers, FRAME_MEDIA_CHUNK};
+use crate::websocket::roles::Role;
+use crate::websocket::types::{
+    Capability, Envelope, ErrorCode, ErrorData, MediaTransferStatus, PairedData, PrecacheReport, Room,
+    WelcomeData, WsMessage, PROTOCOL_VERSION,
+};
 use futures_util::stream::StreamExt;
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::http::{Method, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::accept_hdr_async;
-use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+
+type Clients = Arc<Mutex<ClientRegistry>>;
+type Pairings = Arc<std::sync::Mutex<PairingManager>>;
+type Snapshots = Arc<std::sync::Mutex<SnapshotStore>>;
+type Delivery = Arc<std::sync::Mutex<DeliveryTracker>>;
+type Precache = Arc<std::sync::Mutex<PrecacheTracker>>;
+type IncomingMedia = Arc<Mutex<IncomingTransfers>>;
+type OutgoingMedia = Arc<Mutex<OutgoingTransfers>>;
+type Journals = Arc<std::sync::Mutex<Journal>>;
+type Library = Arc<std::sync::RwLock<Option<Arc<dyn MediaLibrary>>>>;
+
+/// How often acknowledgements are checked for lagging displays
+const ACK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the server pings every client
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Clients silent for longer than this are disconnected
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long `stop` waits for connections to send their close frames
+const STOP_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Where and how the server listens
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Address to listen on; None listens on every interface
+    pub bind_address: Option<IpAddr>,
+    /// Fixed port, or 0 to let the OS pick one
+    pub port: u16,
+    /// Accept IPv4 and IPv6 on one socket when listening on every interface
+    /// (or on `::`). Falls back to IPv4 only if the host has no IPv6.
+    pub dual_stack: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: None,
+            port: 0,
+            dual_stack: true,
+        }
+    }
+}
+
+/// Answer of the `/status` HTTP endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerStatus {
+    /// App version
+    pub version: String,
+    pub protocol_version: u32,
+    pub clients: Vec<ClientInfo>,
+    /// What the displays show: the latest untargeted lyrics, slide and
+    /// screen commands of each event, oldest first
+    pub current: Vec<Envelope>,
+    /// Media cache stats, once the app has registered its cache
+    pub cache: Option<CacheStats>,
+}
+
+/// Shared state handed to the accept loop and every connection task
+#[derive(Clone)]
+struct ServerContext {
+    clients: Clients,
+    pairing: Pairings,
+    snapshots: Snapshots,
+    delivery: Delivery,
+    precache: Precache,
+    incoming: IncomingMedia,
+    outgoing: OutgoingMedia,
+    journal: Journals,
+    media_library: Library,
+    events: broadcast::Sender<ServerEvent>,
+}
+
+impl ServerContext {
+    fn emit(&self, event: ServerEvent) {
+        // No subscribers is fine (e.g. in tests)
+        let _ = self.events.send(event);
+    }
+
+    fn media_library(&self) -> Option<Arc<dyn MediaLibrary>> {
+        self.media_library.read().unwrap().clone()
+    }
+
+    async fn status(&self) -> ServerStatus {
+        ServerStatus {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            clients: self.clients.lock().await.roster(Instant::now()),
+            current: self.snapshots.lock().unwrap().replay(None),
+            cache: self.media_library().and_then(|library| library.stats()),
+        }
+    }
+
+    /// Serialized snapshot messages (with their sequence numbers) to replay for
+    /// a target (None = untargeted state), limited to `rooms` unless empty
+    fn replay(&self, target_display_id: Option<&str>, rooms: &BTreeSet<Room>) -> Vec<(u64, Message)> {
+        snapshot_messages(&self.snapshots.lock().unwrap(), target_display_id, rooms, true)
+    }
+}
 
-type Tx = UnboundedSender<Message>;
+/// Serialize a snapshot replay, limited to `rooms` unless empty. Without
+/// `sequenced` the messages go out without their sequence numbers.
+fn snapshot_messages(
+    snapshots: &SnapshotStore,
+    target_display_id: Option<&str>,
+    rooms: &BTreeSet<Room>,
+    sequenced: bool,
+) -> Vec<(u64, Message)> {
+    snapshots
+        .replay(target_display_id)
+        .into_iter()
+        .filter(|envelope| rooms.is_empty() || envelope.message.room().is_some_and(|room| rooms.contains(&room)))
+        .filter_map(|mut envelope| {
+            let seq = envelope.seq.unwrap_or_default();
+            if !sequenced {
+                envelope.seq = None;
+            }
+            let json = serde_json::to_string(&envelope).ok()?;
+            Some((seq, Message::Text(json)))
+        })
+        .collect()
+}
 
 /// WebSocket server instance
 ///
 /// Manages connected display clients and broadcasts real-time updates
 /// for lyrics, slide navigation, and background changes.
 pub struct WebSocketServer {
-    /// Map of connected clients by their socket address
-    clients: Arc<Mutex<HashMap<SocketAddr, Tx>>>,
+    /// Connected clients by socket address, plus the display_id index
+    clients: Clients,
+    /// Paired controllers and the pending pairing PIN
+    pairing: Pairings,
+    /// Last lyrics/slide per event and target, replayed on connect
+    snapshots: Snapshots,
+    /// Sequence numbers and per-display acknowledgements
+    delivery: Delivery,
+    /// Per-display precache readiness
+    precache: Precache,
+    /// Media files being received from peers
+    incoming: IncomingMedia,
+    /// Media files being sent to peers
+    outgoing: OutgoingMedia,
+    /// Per-event record of published messages
+    journal: Journals,
+    /// Media cache served over HTTP, once the app has registered it
+    media_library: Library,
+    /// Journal replay in progress, if any
+    replay: Option<JoinHandle<()>>,
+    /// Events for the rest of the app (see `commands::forward_websocket_events`)
+    events: broadcast::Sender<ServerEvent>,
+    /// Certificate used for wss://, if TLS is enabled
+    tls: Option<TlsIdentity>,
+    /// Fingerprint of the certificate the running listener serves
+    listening_fingerprint: Option<String>,
+    /// Listening configuration of the last start
+    config: ServerConfig,
+    /// Accept loop, heartbeat and ack monitor of the running server
+    tasks: Vec<JoinHandle<()>>,
     /// The port the server is listening on
     port: u16,
+    /// Port of the last run, reused by `restart` when the port is OS-assigned
+    previous_port: u16,
 }
 
 impl WebSocketServer {
     /// Create a new WebSocket server instance
     pub fn new() -> Self {
         Self {
-            clients: Arc::new(Mutex::new(HashMap::new())),
+            clients: Arc::new(Mutex::new(ClientRegistry::new())),
+            pairing: Arc::new(std::sync::Mutex::new(PairingManager::new())),
+            snapshots: Arc::new(std::sync::Mutex::new(SnapshotStore::new())),
+            delivery: Arc::new(std::sync::Mutex::new(DeliveryTracker::new())),
+            precache: Arc::new(std::sync::Mutex::new(PrecacheTracker::new())),
+            incoming: Arc::new(Mutex::new(IncomingTransfers::new())),
+            outgoing: Arc::new(Mutex::new(OutgoingTransfers::new())),
+            journal: Arc::new(std::sync::Mutex::new(Journal::new())),
+            media_library: Arc::new(std::sync::RwLock::new(None)),
+            replay: None,
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            tls: None,
+            listening_fingerprint: None,
+            config: ServerConfig::default(),
+            tasks: Vec::new(),
             port: 0,
+            previous_port: 0,
+        }
+    }
+
+    fn context(&self) -> ServerContext {
+        ServerContext {
+            clients: self.clients.clone(),
+            pairing: self.pairing.clone(),
+            snapshots: self.snapshots.clone(),
+            delivery: self.delivery.clone(),
+            precache: self.precache.clone(),
+            incoming: self.incoming.clone(),
+            outgoing: self.outgoing.clone(),
+            journal: self.journal.clone(),
+            media_library: self.media_library.clone(),
+            events: self.events.clone(),
         }
     }
 
+    /// Subscribe to server events
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
+
     /// Start the WebSocket server on the specified port
     ///
+    /// Listens on the bind address and dual-stack setting of the current
+    /// configuration (see `set_config`; every interface by default).
+    ///
     /// # Arguments
     /// * `port` - The port to listen on (use 0 for OS-assigned port)
     ///
@@ -53,62 +326,234 @@ impl WebSocketServer {
             return Ok(self.port);
         }
 
-        let addr = format!("0.0.0.0:{}", port);
-        let listener = TcpListener::bind(&addr)
-            .await
-            .map_err(|e| format!("Failed to bind to {}: {}", addr, e))?;
+        let config = ServerConfig { port, ..self.config.clone() };
+        let listener = bind_listener(&config, port)?;
+        self.listen(listener, config)
+    }
 
-        let actual_port = listener.local_addr()
-            .map_err(|e| format!("Failed to get local address: {}", e))?
-            .port();
+    /// Serve connections accepted by `listener`
+    fn listen(&mut self, listener: TcpListener, config: ServerConfig) -> Result<u16, String> {
+        let local_addr = listener.local_addr()
+            .map_err(|e| format!("Failed to get local address: {}", e))?;
 
-        self.port = actual_port;
-        let clients = self.clients.clone();
+        let acceptor = self.tls.as_ref().map(TlsIdentity::acceptor).transpose()?;
 
-        tracing::info!("WebSocket server listening on 0.0.0.0:{}", actual_port);
+        self.port = local_addr.port();
+        self.listening_fingerprint = self.tls_fingerprint();
+        self.config = config;
+        let context = self.context();
 
-        // Spawn the accept loop in a background task
-        tokio::spawn(async move {
-            accept_loop(listener, clients).await;
-        });
+        tracing::info!(
+            "WebSocket server listening on {} ({})",
+            local_addr,
+            if acceptor.is_some() { "wss" } else { "ws" }
+        );
+
+        // Spawn the accept loop, heartbeat and ack monitor in background tasks
+        self.tasks = vec![
+            tokio::spawn(heartbeat(context.clone())),
+            tokio::spawn(monitor_acks(context.clone())),
+            tokio::spawn(async move {
+                accept_loop(listener, context, acceptor).await;
+            }),
+        ];
+
+        Ok(self.port)
+    }
+
+    /// Stop listening and close every connection with a close frame
+    ///
+    /// Pairings, snapshots and delivery state are kept, so displays are caught
+    /// up as usual when they reconnect after a restart.
+    pub async fn stop(&mut self) {
+        if self.port == 0 {
+            return;
+        }
+
+        self.stop_replay();
+        for task in self.tasks.drain(..) {
+            task.abort();
+            // Wait for the listener to be dropped so the port is free again
+            let _ = task.await;
+        }
+
+        {
+            let mut clients_guard = self.clients.lock().await;
+            for addr in clients_guard.recipients(None, None) {
+                clients_guard.disconnect(&addr, "server stopped");
+            }
+        }
 
-        Ok(actual_port)
+        // Give the connections a moment to send their close frames
+        let deadline = Instant::now() + STOP_GRACE_PERIOD;
+        while !self.clients.lock().await.is_empty() && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        tracing::info!("WebSocket server on port {} stopped", self.port);
+        self.previous_port = self.port;
+        self.port = 0;
+        self.listening_fingerprint = None;
+    }
+
+    /// Stop the server and start it again with the current configuration
+    ///
+    /// With an OS-assigned port the previous port is reused if it is still
+    /// free, so controllers that remember it can reconnect.
+    ///
+    /// # Returns
+    /// The actual bound port
+    pub async fn restart(&mut self) -> Result<u16, String> {
+        self.stop().await;
+        let previous_port = self.previous_port;
+
+        let config = self.config.clone();
+        if config.port == 0 && previous_port > 0 {
+            if let Ok(listener) = bind_listener(&config, previous_port) {
+                return self.listen(listener, config);
+            }
+        }
+        self.start(config.port).await
+    }
+
+    /// Replace the listening configuration used by `start` and `restart`
+    ///
+    /// Takes effect the next time the server is (re)started.
+    pub fn set_config(&mut self, config: ServerConfig) {
+        self.config = config;
     }
 
-    /// Broadcast a message to all connected clients
+    /// Broadcast a message to connected clients
+    ///
+    /// Messages with a `target_display_id` are only sent to the client that
+    /// registered that display; all other messages go to every client.
     ///
     /// # Arguments
     /// * `message` - The message to broadcast
     ///
     /// # Returns
-    /// Ok if message was sent to at least one client, Err if serialization failed
-    pub async fn broadcast(&self, message: WsMessage) -> Result<(), String> {
-        // Serialize the message to JSON
-        let json = serde_json::to_string(&message)
-            .map_err(|e| format!("Failed to serialize message: {}", e))?;
+    /// A report of the assigned sequence number, how many connections the
+    /// message was written to and which displays missed it, or Err if
+    /// serialization failed
+    pub async fn broadcast(&self, message: WsMessage) -> Result<DeliveryReport, String> {
+        publish(&self.context(), message).await
+    }
 
-        let ws_message = Message::Text(json);
-        let mut clients = self.clients.lock().await;
+    /// Journal published messages into `dir`, one file per event (see `journal`)
+    pub fn set_journal_dir(&self, dir: PathBuf) {
+        self.journal.lock().unwrap().set_dir(dir);
+    }
 
-        // Send to all connected clients, removing any that have disconnected
-        let mut disconnected = Vec::new();
-        for (addr, tx) in clients.iter() {
-            if let Err(_) = tx.unbounded_send(ws_message.clone()) {
-                disconnected.push(*addr);
-            }
-        }
+    /// Serve files from `library` on `/media/<media_id>` (see `http`)
+    pub fn set_media_library(&self, library: Arc<dyn MediaLibrary>) {
+        *self.media_library.write().unwrap() = Some(library);
+    }
 
-        // Remove disconnected clients
-        for addr in disconnected {
-            tracing::debug!("Removing disconnected client: {}", addr);
-            clients.remove(&addr);
-        }
+    /// What `/status` reports
+    pub async fn status(&self) -> ServerStatus {
+        self.context().status().await
+    }
+
+    /// Events with a journal, most recent first
+    pub fn journals(&self) -> Result<Vec<JournalInfo>, String> {
+        self.journal.lock().unwrap().list()
+    }
+
+    /// Re-publish a recorded event onto the displays at `speed` times real time
+    ///
+    /// Messages recorded before `start_at` (Unix milliseconds) are published
+    /// immediately. Replayed messages are not journaled again and do not
+    /// touch the live snapshots or delivery tracking. Any replay
+    /// already running is stopped; `ServerEvent::JournalReplayFinished`
+    /// reports the end.
+    ///
+    /// # Returns
+    /// The number of messages that will be replayed
+    pub fn replay_journal(&mut self, event_id: &str, speed: f64, start_at: Option<i64>) -> Result<usize, String> {
+        let path = self
+            .journal
+            .lock()
+            .unwrap()
+            .path_for(event_id)
+            .ok_or_else(|| format!("No journal for event {}", event_id))?;
+        let steps = journal::schedule(journal::read(&path)?, speed, start_at)?;
+
+        self.stop_replay();
+        let count = steps.len();
+        tracing::info!("Replaying {} message(s) of event {} at {}x", count, event_id, speed);
+        self.replay = Some(tokio::spawn(replay(self.context(), event_id.to_string(), steps, speed)));
+        Ok(count)
+    }
 
-        if !clients.is_empty() {
-            tracing::debug!("Broadcasted message to {} client(s)", clients.len());
+    /// Stop a running journal replay. Returns whether one was running.
+    pub fn stop_replay(&mut self) -> bool {
+        let Some(task) = self.replay.take() else {
+            return false;
+        };
+        let running = !task.is_finished();
+        task.abort();
+        running
+    }
+
+    /// Health of every open connection (display ID, RTT, last seen)
+    pub async fn connected_clients(&self) -> Vec<ClientInfo> {
+        self.clients.lock().await.roster(Instant::now())
+    }
+
+    /// Rooms with their members
+    pub async fn rooms(&self) -> Vec<RoomInfo> {
+        self.clients.lock().await.rooms(Instant::now())
+    }
+
+    /// Precache readiness of every display (connected or reporting) for an event
+    pub async fn precache_readiness(&self, event_id: &str) -> ReadinessMatrix {
+        let connected = self.clients.lock().await.display_ids();
+        self.precache.lock().unwrap().matrix(event_id, &connected)
+    }
+
+    /// Accept media transfers from peers, staging partial files in `dir`
+    ///
+    /// Completed files are handed over through `ServerEvent::MediaReceived`.
+    pub async fn set_transfer_dir(&self, dir: PathBuf) {
+        self.incoming.lock().await.set_dir(dir);
+    }
+
+    /// Push a media file to a connected display over its WebSocket
+    ///
+    /// Progress is reported through `ServerEvent::MediaTransferStatus`.
+    ///
+    /// # Returns
+    /// The transfer ID, or Err if the display is not connected or the file
+    /// cannot be read
+    pub async fn push_media(
+        &self,
+        display_id: &str,
+        media_id: &str,
+        updated_at: &str,
+        path: &Path,
+    ) -> Result<String, String> {
+        let addr = self
+            .clients
+            .lock()
+            .await
+            .addr_for_display(display_id)
+            .ok_or_else(|| format!("Display {} is not connected", display_id))?;
+        if !self.clients.lock().await.supports(&addr, Capability::BinaryTransfer) {
+            return Err(format!("Display {} does not support binary media transfers", display_id));
         }
 
-        Ok(())
+        // Hash before taking the lock: files may be hundreds of megabytes
+        let start = OutgoingTransfers::prepare(media_id, updated_at, path).await?;
+        self.outgoing.lock().await.begin(addr, path, &start)?;
+        let transfer_id = start.transfer_id.clone();
+        let json = serde_json::to_string(&WsMessage::MediaTransferStart(start))
+            .map_err(|e| format!("Failed to serialize message: {}", e))?;
+
+        if !self.clients.lock().await.send_to(&[addr], &Message::Text(json)).is_empty() {
+            return Err(format!("Display {} disconnected", display_id));
+        }
+        tracing::info!("Pushing media {} to display {} (transfer {})", media_id, display_id, transfer_id);
+        Ok(transfer_id)
     }
 
     /// Get the port the server is listening on
@@ -118,6 +563,82 @@ impl WebSocketServer {
     pub fn port(&self) -> u16 {
         self.port
     }
+
+    /// Enable TLS with the given certificate (or disable it with None)
+    ///
+    /// Takes effect the next time the server is started. Changing it while
+    /// the server is running is an error, so what is advertised never
+    /// differs from what the listener serves.
+    pub fn set_tls(&mut self, identity: Option<TlsIdentity>) -> Result<(), String> {
+        let fingerprint = identity.as_ref().map(|identity| identity.fingerprint().to_string());
+        if self.port > 0 && fingerprint != self.listening_fingerprint {
+            return Err("Stop the WebSocket server before changing its TLS setting".to_string());
+        }
+        self.tls = identity;
+        Ok(())
+    }
+
+    /// SHA-256 fingerprint of the TLS certificate, if TLS is enabled
+    pub fn tls_fingerprint(&self) -> Option<String> {
+        self.tls.as_ref().map(|identity| identity.fingerprint().to_string())
+    }
+
+    /// SHA-256 fingerprint of the certificate the running listener serves
+    /// None when the server is stopped or serving plain ws://
+    pub fn listening_fingerprint(&self) -> Option<String> {
+        self.listening_fingerprint.clone()
+    }
+
+    /// Replace the known pairings and pairing requirement (loaded from the store)
+    pub fn load_pairings(&self, pairings: Vec<Pairing>, require_pairing: bool) {
+        let mut manager = self.pairing.lock().unwrap();
+        manager.load(pairings);
+        manager.set_require_pairing(require_pairing);
+    }
+
+    /// Start pairing: generate a PIN for the display to show
+    pub fn begin_pairing(&self, ttl: Duration) -> PairingCode {
+        self.pairing.lock().unwrap().begin_pairing(ttl)
+    }
+
+    /// Discard the pending pairing PIN
+    pub fn cancel_pairing(&self) {
+        self.pairing.lock().unwrap().cancel_pairing();
+    }
+
+    /// List paired controllers
+    pub fn pairings(&self) -> Vec<PairingSummary> {
+        self.pairing.lock().unwrap().summaries()
+    }
+
+    /// Whether remote connections must present a pairing token
+    pub fn require_pairing(&self) -> bool {
+        self.pairing.lock().unwrap().require_pairing()
+    }
+
+    pub fn set_require_pairing(&self, required: bool) {
+        self.pairing.lock().unwrap().set_require_pairing(required);
+    }
+
+    /// Revoke a pairing and disconnect any connections using it
+    pub async fn revoke_pairing(&self, pair_id: &str) -> Result<(), String> {
+        let pairings = {
+            let mut manager = self.pairing.lock().unwrap();
+            if !manager.revoke(pair_id) {
+                return Err(format!("Pairing {} not found", pair_id));
+            }
+            manager.pairings()
+        };
+        tracing::info!("Revoked pairing {}", pair_id);
+        self.context().emit(ServerEvent::PairingsChanged(pairings));
+
+        let mut clients = self.clients.lock().await;
+        for addr in clients.addrs_for_pairing(pair_id) {
+            tracing::info!("Disconnecting {} (pairing revoked)", addr);
+            clients.disconnect(&addr, "pairing revoked");
+        }
+        Ok(())
+    }
 }
 
 impl Default for WebSocketServer {
@@ -126,155 +647,992 @@ impl Default for WebSocketServer {
     }
 }
 
+/// Create the listening socket for a configuration
+fn bind_listener(config: &ServerConfig, port: u16) -> Result<TcpListener, String> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let ip = config.bind_address.unwrap_or(if config.dual_stack {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    });
+    let addr = SocketAddr::new(ip, port);
+
+    let bind = || -> std::io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(!config.dual_stack)?;
+        }
+        // Lets a restart rebind the port while old connections sit in TIME_WAIT
+        #[cfg(not(windows))]
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        TcpListener::from_std(socket.into())
+    };
+
+    match bind() {
+        Ok(listener) => Ok(listener),
+        Err(e) if config.bind_address.is_none() && config.dual_stack => {
+            tracing::warn!("Failed to listen on {} ({}), falling back to IPv4 only", addr, e);
+            bind_listener(&ServerConfig { dual_stack: false, ..config.clone() }, port)
+        }
+        Err(e) => Err(format!("Failed to bind to {}: {}", addr, e)),
+    }
+}
+
 /// Accept incoming WebSocket connections
-async fn accept_loop(listener: TcpListener, clients: Arc<Mutex<HashMap<SocketAddr, Tx>>>) {
+async fn accept_loop(listener: TcpListener, context: ServerContext, acceptor: Option<TlsAcceptor>) {
     while let Ok((stream, addr)) = listener.accept().await {
+        // Report IPv4 clients of a dual-stack socket as plain IPv4
+        let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
         tracing::info!("New connection from {}", addr);
 
-        let clients_clone = clients.clone();
+        let context = context.clone();
+        let acceptor = acceptor.clone();
 
         // Spawn a task to handle this connection
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, addr, clients_clone).await {
+            if let Err(e) = serve_connection(stream, addr, context, acceptor).await {
                 tracing::error!("Error handling connection from {}: {}", addr, e);
             }
         });
     }
 }
 
-/// Broadcast a message to ALL clients (including the sender for local setups)
-async fn broadcast_to_all(
-    clients: &Arc<Mutex<HashMap<SocketAddr, Tx>>>,
-    message: Message,
-) {
-    let mut clients_guard = clients.lock().await;
-    let mut disconnected = Vec::new();
+/// Complete the TLS handshake if needed, then serve the stream
+async fn serve_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    context: ServerContext,
+    acceptor: Option<TlsAcceptor>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(acceptor) = acceptor else {
+        return serve_stream(stream, addr, context).await;
+    };
 
-    tracing::info!("Broadcasting to {} total clients", clients_guard.len());
+    let mut first_byte = [0u8; 1];
+    let is_tls = matches!(stream.peek(&mut first_byte).await, Ok(1) if first_byte[0] == TLS_HANDSHAKE_RECORD);
 
-    for (addr, tx) in clients_guard.iter() {
-        tracing::info!("Sending to client: {}", addr);
-        if let Err(e) = tx.unbounded_send(message.clone()) {
-            tracing::error!("Failed to send to {}: {:?}", addr, e);
-            disconnected.push(*addr);
-        }
+    if is_tls {
+        let tls_stream = acceptor.accept(stream).await?;
+        serve_stream(tls_stream, addr, context).await
+    } else if addr.ip().to_canonical().is_loopback() {
+        serve_stream(stream, addr, context).await
+    } else {
+        Err("Plain-text connection refused (TLS required)".into())
     }
+}
 
-    // Remove disconnected clients
-    for addr in disconnected {
-        tracing::info!("Removing disconnected client: {}", addr);
-        clients_guard.remove(&addr);
+/// Read the request head, then hand the stream to the WebSocket handler or
+/// answer it over HTTP (see `http`)
+async fn serve_stream<S>(mut stream: S, addr: SocketAddr, context: ServerContext) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut buf = Vec::new();
+    let Some(head_len) = tokio::time::timeout(KEEP_ALIVE_TIMEOUT, http::read_head(&mut stream, &mut buf)).await??
+    else {
+        return Ok(());
+    };
+    let is_upgrade = http::parse_request(&buf[..head_len]).is_ok_and(|req| http::is_websocket_upgrade(&req));
+    if is_upgrade {
+        handle_connection(Prefixed::new(buf, stream), addr, context).await
+    } else {
+        serve_http(stream, addr, &context, buf).await
     }
 }
 
-/// Handle a single WebSocket connection
-async fn handle_connection(
-    stream: tokio::net::TcpStream,
+/// Answer HTTP requests until the client closes the connection or goes idle
+///
+/// `buf` holds what has been read so far, starting with a complete request head.
+async fn serve_http<S>(
+    mut stream: S,
     addr: SocketAddr,
-    clients: Arc<Mutex<HashMap<SocketAddr, Tx>>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Callback to verify the WebSocket handshake
-    let callback = |req: &Request, response: Response| {
-        tracing::debug!("WebSocket handshake from {:?}", req);
-        Ok(response)
-    };
+    context: &ServerContext,
+    mut buf: Vec<u8>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let head_len = match tokio::time::timeout(KEEP_ALIVE_TIMEOUT, http::read_head(&mut stream, &mut buf)).await {
+            Ok(Ok(Some(head_len))) => head_len,
+            Ok(Ok(None)) | Err(_) => return Ok(()),
+            Ok(Err(e)) => return Err(e.into()),
+        };
+        let head: Vec<u8> = buf.drain(..head_len).collect();
+        let req = match http::parse_request(&head) {
+            Ok(req) => req,
+            Err(e) => {
+                tracing::debug!("Bad HTTP request from {}: {}", addr, e);
+                http::write_error(&mut stream, 400, "Bad Request", false).await?;
+                return Ok(());
+            }
+        };
+        handle_http_request(&mut stream, addr, context, &req).await?;
+        if !http::keep_alive(&req) {
+            return Ok(());
+        }
+    }
+}
 
-    // Accept the WebSocket connection
-    let ws_stream = accept_hdr_async(stream, callback).await?;
-    let (ws_sender, mut ws_receiver) = ws_stream.split();
+async fn handle_http_request<S>(
+    stream: &mut S,
+    addr: SocketAddr,
+    context: &ServerContext,
+    req: &Request,
+) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    tracing::debug!("HTTP {} {} from {}", req.method(), req.uri(), addr);
+    let head_only = req.method() == Method::HEAD;
+    if req.method() != Method::GET && !head_only {
+        return http::write_error(stream, 405, "Method Not Allowed", false).await;
+    }
 
-    // Create an unbounded channel for sending messages to this client
-    let (tx, mut rx) = unbounded();
+    // Same rules as the WebSocket handshake, but a PIN cannot be redeemed here
+    let mut creds = HandshakeCredentials::from_request(req);
+    creds.pin = None;
+    let is_loopback = addr.ip().to_canonical().is_loopback();
+    let auth = context.pairing.lock().unwrap().authenticate(&creds, is_loopback);
+    if let Err(reason) = auth {
+        tracing::warn!("Rejected HTTP request from {}: {}", addr, reason);
+        return http::write_error(stream, 401, "Unauthorized", head_only).await;
+    }
 
-    // Add the client to the clients map
-    {
-        let mut clients_guard = clients.lock().await;
-        clients_guard.insert(addr, tx);
-        tracing::info!("Client {} added. Total clients: {}", addr, clients_guard.len());
+    let path = req.uri().path();
+    if path == "/status" {
+        let body = serde_json::to_vec(&context.status().await)?;
+        let headers = [("Content-Type", "application/json".to_string())];
+        return http::write_response(stream, 200, "OK", &headers, &body, head_only).await;
+    }
+    if let Some(media_id) = path.strip_prefix("/media/") {
+        let media_id = urlencoding::decode(media_id).map(|id| id.into_owned()).unwrap_or_default();
+        let file = context.media_library().and_then(|library| library.path(&media_id));
+        if let Some(file) = file {
+            let range = req.headers().get("range").and_then(|value| value.to_str().ok());
+            return http::write_file(stream, &file, range, head_only).await;
+        }
     }
+    http::write_error(stream, 404, "Not Found", head_only).await
+}
 
-    // Spawn a task to forward messages from the channel to the WebSocket
-    let forward_task = tokio::spawn(async move {
-        use futures_util::sink::SinkExt;
-        let mut ws_sender = ws_sender;
-        while let Some(msg) = rx.next().await {
-            if let Err(e) = ws_sender.send(msg).await {
-                tracing::error!("Error forwarding messages to {}: {}", addr, e);
-                break;
-            }
+/// Ping every client periodically and disconnect the ones that stopped answering
+async fn heartbeat(context: ServerContext) {
+    // The first ping goes out one interval after start, not immediately
+    let start = tokio::time::Instant::now() + HEARTBEAT_INTERVAL;
+    let mut interval = tokio::time::interval_at(start, HEARTBEAT_INTERVAL);
+    let mut nonce: u64 = 0;
+    loop {
+        interval.tick().await;
+        nonce = nonce.wrapping_add(1);
+
+        let mut clients_guard = context.clients.lock().await;
+        for addr in clients_guard.ping_all(nonce, Instant::now(), CLIENT_TIMEOUT) {
+            tracing::warn!("Client {} timed out (no frames for {:?})", addr, CLIENT_TIMEOUT);
+            clients_guard.disconnect(&addr, "heartbeat timeout");
         }
-    });
 
-    // Handle incoming messages from the client
-    while let Some(result) = ws_receiver.next().await {
-        match result {
-            Ok(Message::Ping(_msg)) => {
-                tracing::trace!("Received ping from {}", addr);
-                // Pongs are handled automatically by tungstenite
-            }
-            Ok(Message::Pong(_)) => {
-                tracing::trace!("Received pong from {}", addr);
-            }
-            Ok(Message::Close(_)) => {
-                tracing::info!("Client {} initiated close", addr);
-                break;
-            }
-            Ok(Message::Text(text)) => {
-                tracing::info!("Received text message from {}: {} bytes", addr, text.len());
-                // Broadcast this message to ALL connected clients (including sender for local setups)
-                broadcast_to_all(&clients, Message::Text(text)).await;
-            }
-            Ok(Message::Binary(data)) => {
-                tracing::trace!("Received binary data from {}: {} bytes", addr, data.len());
-            }
-            Ok(_) => {
-                // Handle any other message types (Frame, etc.)
-            }
-            Err(e) => {
-                tracing::error!("Error receiving from {}: {}", addr, e);
-                break;
+        // Keep measuring controllers' clocks, for their execute_at times
+        let request = WsMessage::ClockSync(clock::request(clock::now_ms()));
+        send_json(&clients_guard, &clients_guard.clock_sync_addrs(), &request);
+    }
+}
+
+/// Periodically report connected displays that stopped acknowledging updates,
+/// and forget the ones that have been gone for `FORGET_DISPLAY_AFTER`
+async fn monitor_acks(context: ServerContext) {
+    let mut interval = tokio::time::interval(ACK_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let lagging = {
+            let mut clients_guard = context.clients.lock().await;
+            let mut delivery = context.delivery.lock().unwrap();
+            let now = Instant::now();
+            for display_id in delivery.forget_departed(now, FORGET_DISPLAY_AFTER) {
+                tracing::info!("Forgetting display {} (disconnected for {:?})", display_id, FORGET_DISPLAY_AFTER);
+                clients_guard.forget_display(&display_id);
             }
+            delivery.newly_lagging(now, ACK_TIMEOUT, |id| clients_guard.addr_for_display(id).is_some())
+        };
+        for lag in lagging {
+            tracing::warn!(
+                "Display {} is lagging: {} unacked update(s), last ack {}",
+                lag.display_id, lag.unacked, lag.last_acked
+            );
+            context.emit(ServerEvent::DisplayLagging(lag));
         }
     }
+}
 
-    // Remove the client from the map
-    {
-        let mut clients_guard = clients.lock().await;
-        clients_guard.remove(&addr);
-        tracing::info!("Client {} removed. Total clients: {}", addr, clients_guard.len());
+/// Send a message, sequencing, tracking and journaling it if it changes display state
+async fn publish(context: &ServerContext, message: WsMessage) -> Result<DeliveryReport, String> {
+    let target = message.target_display_id().map(str::to_string);
+    let room = message.room();
+    let unfreeze = matches!(&message, WsMessage::Freeze(data) if !data.enabled);
+    if let WsMessage::Precache(request) = &message {
+        context.precache.lock().unwrap().expect(request);
     }
+    let mut clients_guard = context.clients.lock().await;
+    let mut report = DeliveryReport::default();
 
-    // Abort the forward task
-    forward_task.abort();
-
-    Ok(())
-}
+    if message.is_state_change() {
+        let now = Instant::now();
+        let mut delivery = context.delivery.lock().unwrap();
+        let seq = delivery.next_seq();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // Untargeted updates are expected by every display that has registered,
+        // unless it only joined other rooms
+        let expected = match &target {
+            Some(id) => vec![id.clone()],
+            None => delivery
+                .known_displays()
+                .into_iter()
+                .filter(|id| room.as_ref().is_none_or(|room| clients_guard.display_in_room(id, room)))
+                .collect(),
+        };
+        for display_id in expected {
+            delivery.track(&display_id, seq, now);
+            if clients_guard.addr_for_display(&display_id).is_none() {
+                report.missed_displays.push(display_id);
+            }
+        }
 
-    #[tokio::test]
-    async fn test_websocket_server_creation() {
-        let server = WebSocketServer::new();
-        assert_eq!(server.port(), 0);
-        assert_eq!(server.clients.lock().await.len(), 0);
+        context.snapshots.lock().unwrap().record(seq, &message);
+        report.seq = Some(seq);
     }
+    record_journal(context, &message, report.seq);
 
-    #[tokio::test]
-    async fn test_websocket_server_default() {
-        let server = WebSocketServer::default();
-        assert_eq!(server.port(), 0);
+    let coalesce = Coalesce::for_message(&message);
+    let json = serde_json::to_string(&Envelope::new(message, report.seq))
+        .map_err(|e| format!("Failed to serialize message: {}", e))?;
+    report.sent_to = deliver(
+        &mut clients_guard,
+        target.as_deref(),
+        room.as_ref(),
+        Message::Text(json),
+        coalesce.as_ref(),
+    );
+    if unfreeze {
+        catch_up(&mut clients_guard, target.as_deref(), room.as_ref(), |id, rooms| context.replay(id, rooms));
     }
+    drop(clients_guard);
 
-    #[tokio::test]
-    async fn test_broadcast_with_no_clients() {
-        let server = WebSocketServer::new();
-        let message = WsMessage::Ping;
+    if let Some(seq) = report.seq {
+        for display_id in &report.missed_displays {
+            tracing::warn!("Display {} is offline and missed update {}", display_id, seq);
+            context.emit(ServerEvent::DisplayMissedUpdate(MissedUpdate {
+                display_id: display_id.clone(),
+                seq,
+            }));
+        }
+    }
+    Ok(report)
+}
 
-        // Broadcasting with no clients should not fail
-        let result = server.broadcast(message).await;
-        assert!(result.is_ok());
+/// Re-send the current state (as given by `replay`) to displays that were just
+/// unfrozen, since they ignored every update while frozen
+fn catch_up(
+    clients_guard: &mut ClientRegistry,
+    target_display_id: Option<&str>,
+    room: Option<&Room>,
+    replay: impl Fn(Option<&str>, &BTreeSet<Room>) -> Vec<(u64, Message)>,
+) {
+    for addr in clients_guard.recipients(target_display_id, room) {
+        let rooms = clients_guard.rooms_of(&addr);
+        let mut messages = replay(None, &rooms);
+        if let Some(id) = clients_guard.display_for_addr(&addr) {
+            messages.extend(replay(Some(id), &rooms));
+        }
+        for (_, message) in messages {
+            clients_guard.send_to(&[addr], &message);
+        }
+    }
+}
+
+/// Deliver a message to the clients selected by its target and room
+async fn route_message(clients: &Clients, target_display_id: Option<&str>, room: Option<&Room>, message: Message) {
+    let mut clients_guard = clients.lock().await;
+    deliver(&mut clients_guard, target_display_id, room, message, None);
+}
+
+/// Pass a display's report on to the controllers that share a room with it
+async fn relay_to_controllers(clients: &Clients, addr: SocketAddr, message: Message) {
+    let clients_guard = clients.lock().await;
+    let controllers = clients_guard.controllers_near(&addr);
+    clients_guard.send_to(&controllers, &message);
+}
+
+/// Queue a message for the clients selected by its target and room, returning
+/// how many it went to
+///
+/// Untargeted messages go to ALL clients in the room (including the sender for
+/// local setups) and to those that joined no room.
+/// With `coalesce`, queued updates the message supersedes are dropped.
+fn deliver(
+    clients_guard: &mut ClientRegistry,
+    target_display_id: Option<&str>,
+    room: Option<&Room>,
+    message: Message,
+    coalesce: Option<&Coalesce>,
+) -> usize {
+    let recipients = clients_guard.recipients(target_display_id, room);
+
+    match target_display_id {
+        Some(id) if recipients.is_empty() => {
+            tracing::warn!("No connected client registered for display {}, message dropped", id);
+        }
+        Some(id) => tracing::debug!("Routing message to display {}", id),
+        None => tracing::debug!("Broadcasting to {} total clients", recipients.len()),
+    }
+
+    // Close clients whose channel is gone (their task removes them)
+    let disconnected = clients_guard.queue_to(&recipients, &message, coalesce);
+    for addr in &disconnected {
+        tracing::info!("Closing disconnected client: {}", addr);
+        clients_guard.disconnect(addr, "send failed");
+    }
+    recipients.len() - disconnected.len()
+}
+
+/// Handle a text frame sent by a client
+async fn handle_text_message(context: &ServerContext, addr: SocketAddr, mut text: String) {
+    let received_at = clock::now_ms();
+    let clients = &context.clients;
+    let mut message = match serde_json::from_str::<WsMessage>(&text) {
+        Ok(message) => message,
+        Err(e) => {
+            tracing::warn!("Rejected invalid message from {}: {}", addr, e);
+            reply_error(context, addr, ErrorCode::InvalidMessage, e.to_string()).await;
+            return;
+        }
+    };
+
+    let role = clients.lock().await.role(&addr).unwrap_or(Role::Observer);
+    if !role.may_send(&message) {
+        let reason = format!("A {:?} connection may not send {}", role, message_type(&text));
+        tracing::warn!("Rejected message from {}: {}", addr, reason);
+        reply_error(context, addr, ErrorCode::Forbidden, reason).await;
+        return;
+    }
+
+    if localize_execute_at(context, addr, &mut message).await {
+        match serde_json::to_string(&message) {
+            Ok(json) => text = json,
+            Err(e) => tracing::error!("Failed to serialize message from {}: {}", addr, e),
+        }
+    }
+
+    let room = message.room();
+    match message {
+        WsMessage::Hello(hello) => {
+            let role = match role.after_hello(&hello) {
+                Ok(role) => role,
+                Err(reason) => {
+                    tracing::warn!("Rejected hello from {}: {}", addr, reason);
+                    reply_error(context, addr, ErrorCode::Forbidden, reason).await;
+                    return;
+                }
+            };
+            let display_id = hello.display_id.clone();
+            let (protocol_version, capabilities) = hello.negotiate();
+            let mut clients_guard = clients.lock().await;
+            if let Err(reason) = clients_guard.register(addr, hello.display_id.clone(), hello.device_id.clone()) {
+                drop(clients_guard);
+                tracing::warn!("Rejected hello from {}: {}", addr, reason);
+                reply_error(context, addr, ErrorCode::Forbidden, reason).await;
+                return;
+            }
+            tracing::info!(
+                "Client {} registered as display {:?} (device {:?}, protocol {:?})",
+                addr, hello.display_id, hello.device_id, hello.protocol_version
+            );
+            clients_guard.set_role(&addr, role);
+
+            // Version 1 clients do not know the welcome message. It goes out
+            // before the negotiated encoding applies, so the client can read it.
+            if hello.protocol_version.is_some() {
+                let welcome = WsMessage::Welcome(WelcomeData {
+                    protocol_version,
+                    capabilities: capabilities.clone(),
+                    role,
+                });
+                send_json(&clients_guard, &[addr], &welcome);
+            }
+            clients_guard.set_protocol(&addr, protocol_version, capabilities);
+            // Measure a controller's clock straight away, before its first scheduled update
+            if clients_guard.clock_sync_addrs().contains(&addr) {
+                let request = WsMessage::ClockSync(clock::request(clock::now_ms()));
+                send_json(&clients_guard, &[addr], &request);
+            }
+
+            if display_id.is_some() {
+                if let Some(info) = clients_guard.info(&addr, Instant::now()) {
+                    context.emit(ServerEvent::DisplayConnected(info));
+                }
+            }
+
+            // Catch the display up on anything addressed specifically to it. Together
+            // with the untargeted replay sent on connect, this is everything it
+            // still needs to acknowledge.
+            if let Some(id) = display_id {
+                let mut resent = clients_guard.replayed(&addr);
+                let rooms = clients_guard.rooms_of(&addr);
+                for (seq, message) in context.replay(Some(&id), &rooms) {
+                    clients_guard.send_to(&[addr], &message);
+                    resent.push(seq);
+                }
+                // Only displays whose connection proved who it is are expected to
+                // ack, so typos and unauthenticated hellos are never waited on
+                if clients_guard.is_authenticated(&addr) {
+                    context.delivery.lock().unwrap().resent(&id, &resent, Instant::now());
+                }
+            }
+        }
+        WsMessage::ClockSync(sync) => {
+            let clients_guard = &mut clients.lock().await;
+            if sync.receive.is_none() {
+                // The client is measuring the server's clock
+                let answer = WsMessage::ClockSync(clock::answer(&sync, received_at, clock::now_ms()));
+                send_json(clients_guard, &[addr], &answer);
+            } else if clients_guard.record_clock_answer(&addr, &sync, clock::now_ms()) {
+                tracing::trace!("Clock offset of {}: {:?} ms", addr, clients_guard.clock_offset(&addr));
+            }
+        }
+        WsMessage::Join(room) => {
+            let mut clients_guard = clients.lock().await;
+            if clients_guard.join(&addr, room.clone()) {
+                tracing::info!("Client {} joined room {}/{}", addr, room.church_id, room.event_id);
+                // Bring the client up to the room's current state
+                let rooms = BTreeSet::from([room]);
+                let mut messages = context.replay(None, &rooms);
+                if let Some(id) = clients_guard.display_for_addr(&addr) {
+                    messages.extend(context.replay(Some(id), &rooms));
+                }
+                for (_, message) in messages {
+                    clients_guard.send_to(&[addr], &message);
+                }
+            }
+        }
+        WsMessage::Leave(room) => {
+            if clients.lock().await.leave(&addr, &room) {
+                tracing::info!("Client {} left room {}/{}", addr, room.church_id, room.event_id);
+            }
+        }
+        WsMessage::Ack(ack) => {
+            let clients_guard = clients.lock().await;
+            match clients_guard.display_for_addr(&addr) {
+                Some(id) => {
+                    tracing::trace!("Display {} acked {}", id, ack.seq);
+                    context.delivery.lock().unwrap().ack(id, ack.seq);
+                }
+                None => tracing::debug!("Ignoring ack from unregistered client {}", addr),
+            }
+        }
+        WsMessage::Precache(request) => {
+            context.precache.lock().unwrap().expect(&request);
+            record_journal(context, &WsMessage::Precache(request.clone()), None);
+            route_message(clients, request.target_display_id.as_deref(), room.as_ref(), Message::Text(text)).await;
+        }
+        WsMessage::PrecacheStatus(report) => {
+            record_precache_report(context, addr, &report, false).await;
+            relay_to_controllers(clients, addr, Message::Text(text)).await;
+        }
+        WsMessage::PrecacheAck(report) => {
+            record_precache_report(context, addr, &report, true).await;
+            relay_to_controllers(clients, addr, Message::Text(text)).await;
+        }
+        WsMessage::MediaTransferStart(start) => {
+            let outcome = context.incoming.lock().await.start(start).await;
+            handle_transfer_outcome(context, addr, outcome).await;
+        }
+        WsMessage::MediaTransferStatus(status) => {
+            send_media_chunks(context, addr, &status).await;
+            context.emit(ServerEvent::MediaTransferStatus(status));
+        }
+        message if message.is_state_change() => {
+            // Relay through the sequenced path so displays can ack it
+            if let Err(e) = publish(context, message).await {
+                tracing::error!("Failed to relay message from {}: {}", addr, e);
+            }
+        }
+        message => {
+            record_journal(context, &message, None);
+            // Relay to the targeted display, or to ALL clients (including sender for local setups)
+            route_message(clients, message.target_display_id(), room.as_ref(), Message::Text(text)).await;
+        }
+    }
+}
+
+/// Append a published message to its event's journal
+fn record_journal(context: &ServerContext, message: &WsMessage, seq: Option<u64>) {
+    if let Err(e) = context.journal.lock().unwrap().record(clock::now_ms(), message, seq) {
+        tracing::warn!("Failed to journal message: {}", e);
+    }
+}
+
+/// Re-publish journaled messages on their schedule
+///
+/// Replayed messages go out unsequenced and stay out of the live snapshots,
+/// delivery tracking and journal, so a rehearsal never changes what a
+/// reconnecting display is sent. The replay keeps its own snapshot for
+/// displays it unfreezes.
+async fn replay(context: ServerContext, event_id: String, steps: Vec<ReplayStep>, speed: f64) {
+    let start = tokio::time::Instant::now();
+    let mut state = SnapshotStore::new();
+    let mut published = 0;
+    for (index, step) in steps.into_iter().enumerate() {
+        tokio::time::sleep_until(start + step.at).await;
+        let mut message = step.message;
+        // Keep the original lead time between publishing and executing
+        if let Some(execute_at) = message.execute_at_mut() {
+            let lead = (*execute_at - step.recorded_at) as f64 / speed;
+            *execute_at = clock::now_ms() + lead as i64;
+        }
+        match publish_replayed(&context, &mut state, index as u64 + 1, message).await {
+            Ok(_) => published += 1,
+            Err(e) => tracing::error!("Failed to replay message of event {}: {}", event_id, e),
+        }
+    }
+    tracing::info!("Replay of event {} finished ({} message(s))", event_id, published);
+    context.emit(ServerEvent::JournalReplayFinished(ReplayFinished { event_id, published }));
+}
+
+/// Send one replayed message, recording it in the replay's own `state` under `seq`
+async fn publish_replayed(
+    context: &ServerContext,
+    state: &mut SnapshotStore,
+    seq: u64,
+    message: WsMessage,
+) -> Result<usize, String> {
+    let target = message.target_display_id().map(str::to_string);
+    let room = message.room();
+    let unfreeze = matches!(&message, WsMessage::Freeze(data) if !data.enabled);
+    state.record(seq, &message);
+
+    let coalesce = Coalesce::for_message(&message);
+    let json = serde_json::to_string(&Envelope::new(message, None))
+        .map_err(|e| format!("Failed to serialize message: {}", e))?;
+    let mut clients_guard = context.clients.lock().await;
+    let sent_to = deliver(
+        &mut clients_guard,
+        target.as_deref(),
+        room.as_ref(),
+        Message::Text(json),
+        coalesce.as_ref(),
+    );
+    if unfreeze {
+        catch_up(&mut clients_guard, target.as_deref(), room.as_ref(), |id, rooms| {
+            snapshot_messages(state, id, rooms, false)
+        });
+    }
+    Ok(sent_to)
+}
+
+/// Convert a relayed message's `execute_at` from the sender's clock to the
+/// server's. Returns whether the message changed.
+async fn localize_execute_at(context: &ServerContext, addr: SocketAddr, message: &mut WsMessage) -> bool {
+    let Some(execute_at) = message.execute_at_mut() else {
+        return false;
+    };
+    match context.clients.lock().await.to_server_time(&addr, *execute_at) {
+        Some(server_time) => {
+            *execute_at = server_time;
+            true
+        }
+        None => {
+            tracing::debug!("No clock offset for {} yet; relaying execute_at unchanged", addr);
+            false
+        }
+    }
+}
+
+/// Serialize a message and queue it for the given clients
+fn send_json(clients_guard: &ClientRegistry, addrs: &[SocketAddr], message: &WsMessage) {
+    match serde_json::to_string(message) {
+        Ok(json) => {
+            clients_guard.send_to(addrs, &Message::Text(json));
+        }
+        Err(e) => tracing::error!("Failed to serialize message: {}", e),
+    }
+}
+
+/// Tell a client why its frame was rejected
+async fn reply_error(context: &ServerContext, addr: SocketAddr, code: ErrorCode, message: String) {
+    let error = WsMessage::Error(ErrorData { code, message });
+    match serde_json::to_string(&error) {
+        Ok(json) => {
+            context.clients.lock().await.send_to(&[addr], &Message::Text(json));
+        }
+        Err(e) => tracing::error!("Failed to serialize error reply: {}", e),
+    }
+}
+
+/// The `type` of a raw frame, for log and error messages
+fn message_type(text: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(text)
+        .ok()
+        .and_then(|value| value.get("type")?.as_str().map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Handle a binary frame sent by a client
+async fn handle_binary_message(context: &ServerContext, addr: SocketAddr, data: &[u8]) {
+    // Encoded messages go through the same checks as text ones
+    if data.first() == Some(&FRAME_MESSAGE) {
+        match codec::decode(data) {
+            Ok(text) => handle_text_message(context, addr, text).await,
+            Err(e) => {
+                tracing::warn!("Invalid message frame from {}: {}", addr, e);
+                reply_error(context, addr, ErrorCode::InvalidMessage, e).await;
+            }
+        }
+        return;
+    }
+
+    let role = context.clients.lock().await.role(&addr).unwrap_or(Role::Observer);
+    if !role.may_send_binary() {
+        tracing::warn!("Rejected binary frame from {} ({:?})", addr, role);
+        let reason = format!("A {:?} connection may not send binary frames", role);
+        reply_error(context, addr, ErrorCode::Forbidden, reason).await;
+        return;
+    }
+
+    match data.first() {
+        Some(&FRAME_MEDIA_CHUNK) => match ChunkFrame::decode(data) {
+            Ok(frame) => {
+                let outcome = context.incoming.lock().await.chunk(frame).await;
+                handle_transfer_outcome(context, addr, outcome).await;
+            }
+            Err(e) => tracing::warn!("Invalid chunk frame from {}: {}", addr, e),
+        },
+        kind => tracing::debug!("Ignoring binary frame from {} (kind {:?})", addr, kind),
+    }
+}
+
+/// Report the receiving side's progress to the sender, and hand over completed files
+async fn handle_transfer_outcome(context: &ServerContext, addr: SocketAddr, outcome: ChunkOutcome) {
+    let status = match outcome {
+        ChunkOutcome::Continue => return,
+        ChunkOutcome::Status(status) => status,
+        ChunkOutcome::Received(status, media) => {
+            context.emit(ServerEvent::MediaReceived(media));
+            status
+        }
+    };
+    match serde_json::to_string(&WsMessage::MediaTransferStatus(status)) {
+        Ok(json) => {
+            context.clients.lock().await.send_to(&[addr], &Message::Text(json));
+        }
+        Err(e) => tracing::error!("Failed to serialize transfer status: {}", e),
+    }
+}
+
+/// Send the next chunks of a file in response to the receiver's status
+async fn send_media_chunks(context: &ServerContext, addr: SocketAddr, status: &MediaTransferStatus) {
+    let frames = match context.outgoing.lock().await.on_status(addr, status).await {
+        Ok(frames) => frames,
+        Err(e) => {
+            tracing::error!("Media transfer {} to {} failed: {}", status.transfer_id, addr, e);
+            return;
+        }
+    };
+    if frames.is_empty() {
+        return;
+    }
+    let clients_guard = context.clients.lock().await;
+    for frame in frames {
+        if !clients_guard.send_to(&[addr], &Message::Binary(frame)).is_empty() {
+            break;
+        }
+    }
+}
+
+/// Fold a display's precache report into the readiness matrix
+async fn record_precache_report(context: &ServerContext, addr: SocketAddr, report: &PrecacheReport, is_ack: bool) {
+    // Displays that never registered are tracked by address
+    let display_id = context
+        .clients
+        .lock()
+        .await
+        .display_for_addr(&addr)
+        .map(str::to_string)
+        .unwrap_or_else(|| addr.to_string());
+
+    let ready = context.precache.lock().unwrap().update(&display_id, report, is_ack);
+    if let Some(ready) = ready {
+        tracing::info!("Display {} finished precaching event {}", ready.display_id, ready.event_id);
+        context.emit(ServerEvent::DisplayPrecacheReady(ready));
+    }
+}
+
+/// Authenticate a handshake request against the pairing state
+fn authorize_handshake(
+    context: &ServerContext,
+    addr: SocketAddr,
+    creds: &HandshakeCredentials,
+) -> Result<AuthOutcome, Box<ErrorResponse>> {
+    let is_loopback = addr.ip().to_canonical().is_loopback();
+
+    let mut manager = context.pairing.lock().unwrap();
+    match manager.authenticate(creds, is_loopback) {
+        Ok(outcome) => {
+            if let AuthOutcome::NewlyPaired { .. } = outcome {
+                context.emit(ServerEvent::PairingsChanged(manager.pairings()));
+            }
+            Ok(outcome)
+        }
+        Err(reason) => {
+            tracing::warn!("Rejected WebSocket handshake from {}: {}", addr, reason);
+            let mut response = ErrorResponse::new(Some(reason));
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+            Err(Box::new(response))
+        }
+    }
+}
+
+/// Handle a single WebSocket connection
+async fn handle_connection<S>(
+    stream: S,
+    addr: SocketAddr,
+    context: ServerContext,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let clients = context.clients.clone();
+    let mut auth = None;
+    let mut requested_role = None;
+
+    // Callback to verify the WebSocket handshake
+    // (the ErrorResponse type is dictated by tungstenite)
+    #[allow(clippy::result_large_err)]
+    let callback = |req: &Request, response: Response| {
+        tracing::debug!("WebSocket handshake from {:?}", req);
+        let creds = HandshakeCredentials::from_request(req);
+        match authorize_handshake(&context, addr, &creds) {
+            Ok(outcome) => {
+                auth = Some(outcome);
+                requested_role = creds.role;
+                Ok(response)
+            }
+            Err(rejection) => Err(*rejection),
+        }
+    };
+
+    // Accept the WebSocket connection
+    let ws_stream = accept_hdr_async(stream, callback).await?;
+    let auth = auth.ok_or("Handshake completed without authentication")?;
+    let (ws_sender, mut ws_receiver) = ws_stream.split();
+
+    // Messages for this client wait in a bounded queue until they are written
+    let queue = ClientQueue::new(CLIENT_QUEUE_CAPACITY);
+
+    // A freshly paired controller needs its token before anything else
+    if let AuthOutcome::NewlyPaired { pair_id, token } = &auth {
+        let paired = WsMessage::Paired(PairedData {
+            pair_id: pair_id.clone(),
+            token: token.clone(),
+        });
+        queue.push(Message::Text(serde_json::to_string(&paired)?), None)?;
+    }
+
+    // Bring the client up to date with what every display is currently showing
+    let mut replayed = Vec::new();
+    for (seq, message) in context.replay(None, &BTreeSet::new()) {
+        queue.push(message, None)?;
+        replayed.push(seq);
+    }
+
+    // Add the client to the clients map
+    let shutdown = {
+        let role = Role::initial(&auth, requested_role);
+        if requested_role.is_some_and(|requested| requested > role) {
+            tracing::warn!("{} asked for {:?} but may only be {:?}", addr, requested_role, role);
+        }
+        let mut client = Client::new(queue.clone(), role);
+        client.pair_id = auth.pair_id().map(str::to_string);
+        client.local = auth == AuthOutcome::Local;
+        client.replayed = replayed;
+        let shutdown = client.shutdown.clone();
+        let mut clients_guard = clients.lock().await;
+        clients_guard.insert(addr, client);
+        tracing::info!("Client {} added ({:?}). Total clients: {}", addr, auth, clients_guard.len());
+        shutdown
+    };
+
+    // Spawn a task to forward messages from the queue to the WebSocket
+    let mut forward_task = tokio::spawn(async move {
+        use futures_util::sink::SinkExt;
+        let mut ws_sender = ws_sender;
+        while let Some(msg) = queue.pop().await {
+            if let Err(e) = ws_sender.send(msg).await {
+                tracing::error!("Error forwarding messages to {}: {}", addr, e);
+                queue.close();
+                break;
+            }
+        }
+    });
+
+    // Handle incoming messages from the client until it closes or the server
+    // asks this connection to go away (revoked pairing, heartbeat timeout, ...)
+    let mut reason = "closed by client".to_string();
+    loop {
+        let result = tokio::select! {
+            _ = shutdown.notified() => {
+                reason = String::new();
+                break;
+            }
+            result = ws_receiver.next() => match result {
+                Some(result) => result,
+                None => break,
+            },
+        };
+
+        if !matches!(result, Ok(Message::Pong(_))) {
+            clients.lock().await.touch(&addr, Instant::now());
+        }
+
+        match result {
+            Ok(Message::Ping(_msg)) => {
+                tracing::trace!("Received ping from {}", addr);
+                // Pongs are handled automatically by tungstenite
+            }
+            Ok(Message::Pong(payload)) => {
+                tracing::trace!("Received pong from {}", addr);
+                clients.lock().await.record_pong(&addr, &payload, Instant::now());
+            }
+            Ok(Message::Close(_)) => {
+                tracing::info!("Client {} initiated close", addr);
+                break;
+            }
+            Ok(Message::Text(text)) => {
+                tracing::info!("Received text message from {}: {} bytes", addr, text.len());
+                handle_text_message(&context, addr, text).await;
+            }
+            Ok(Message::Binary(data)) => {
+                tracing::trace!("Received binary data from {}: {} bytes", addr, data.len());
+                handle_binary_message(&context, addr, &data).await;
+            }
+            Ok(_) => {
+                // Handle any other message types (Frame, etc.)
+            }
+            Err(e) => {
+                tracing::error!("Error receiving from {}: {}", addr, e);
+                reason = format!("receive error: {}", e);
+                break;
+            }
+        }
+    }
+
+    // Remove the client from the map
+    let removed = {
+        let mut clients_guard = clients.lock().await;
+        let now = Instant::now();
+        let info = clients_guard.info(&addr, now);
+        let removed = clients_guard.remove(&addr).zip(info);
+        // Start the display's countdown to being forgotten, unless it already reconnected
+        if let Some(id) = removed.as_ref().and_then(|(client, _)| client.display_id.as_deref()) {
+            if clients_guard.addr_for_display(id).is_none() {
+                context.delivery.lock().unwrap().disconnected(id, now);
+            }
+        }
+        tracing::info!("Client {} removed. Total clients: {}", addr, clients_guard.len());
+        removed
+    };
+
+    context.outgoing.lock().await.cancel_for(&addr);
+
+    if let Some((client, info)) = removed {
+        // Server-initiated closes record their reason on the client
+        if reason.is_empty() {
+            reason = client.close_reason.clone().unwrap_or_else(|| "closed by server".to_string());
+            client.tx.close_with(Message::Close(Some(CloseFrame {
+                code: CloseCode::Away,
+                reason: reason.clone().into(),
+            })));
+        }
+        // Whatever is still queued is flushed, then the forward task ends
+        client.tx.finish();
+        if info.display_id.is_some() {
+            context.emit(ServerEvent::DisplayDisconnected(ClientDisconnected { client: info, reason }));
+        }
+    }
+
+    // Let the forward task flush what is queued (e.g. the close frame), then stop it
+    if tokio::time::timeout(Duration::from_secs(1), &mut forward_task).await.is_err() {
+        forward_task.abort();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wait until a client has registered the given display_id
+    async fn wait_for_display(server: &WebSocketServer, display_id: &str) {
+        for _ in 0..50 {
+            if server.clients.lock().await.addr_for_display(display_id).is_some() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Display {} never registered", display_id);
+    }
+
+    /// Text frames a test client receives until it goes quiet
+    async fn drain_text<S>(ws: &mut tokio_tungstenite::WebSocketStream<S>) -> Vec<String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut received = Vec::new();
+        while let Ok(Some(Ok(frame))) = tokio::time::timeout(Duration::from_millis(200), ws.next()).await {
+            if let Message::Text(text) = frame {
+                received.push(text);
+            }
+        }
+        received
+    }
+
+    /// Read the next frame from a test client, expecting an `error` reply
+    async fn next_error<S>(ws: &mut tokio_tungstenite::WebSocketStream<S>) -> ErrorData
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let reply = tokio::time::timeout(Duration::from_secs(1), ws.next()).await.unwrap().unwrap().unwrap();
+        match serde_json::from_str(reply.to_text().unwrap()).unwrap() {
+            WsMessage::Error(error) => error,
+            other => panic!("Expected an error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_websocket_server_creation() {
+        let server = WebSocketServer::new();
+        assert_eq!(server.port(), 0);
+        assert!(server.clients.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_websocket_server_default() {
+        let server = WebSocketServer::default();
+        assert_eq!(server.port(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_with_no_clients() {
+        let server = WebSocketServer::new();
+        let message = WsMessage::Ping;
+
+        // Broadcasting with no clients should not fail
+        let result = server.broadcast(message).await;
+        assert!(result.is_ok());
     }
 
     #[tokio::test]
@@ -290,4 +1648,895 @@ mod tests {
         assert!(port > 0);
         assert_eq!(server.port(), port);
     }
+
+    #[tokio::test]
+    async fn test_targeted_message_only_reaches_registered_display() {
+        use crate::websocket::types::{HelloData, SlideData};
+        use futures_util::sink::SinkExt;
+        use std::time::Duration;
+
+        let mut server = WebSocketServer::new();
+        let port = server.start(0).await.unwrap();
+        let url = format!("ws://127.0.0.1:{}", port);
+
+        let (mut display_a, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (mut display_b, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        for (ws, id) in [(&mut display_a, "display-a"), (&mut display_b, "display-b")] {
+            let hello = WsMessage::Hello(HelloData {
+                display_id: Some(id.to_string()),
+                ..Default::default()
+            });
+            ws.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+        }
+
+        // Wait for both registrations to land
+        for _ in 0..50 {
+            let registered = {
+                let clients = server.clients.lock().await;
+                clients.addr_for_display("display-a").is_some()
+                    && clients.addr_for_display("display-b").is_some()
+            };
+            if registered {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        server.broadcast(WsMessage::Slide(SlideData {
+            target_display_id: Some("display-b".to_string()),
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            slide_index: 2,
+            timestamp: 1234567890,
+            execute_at: None,
+        })).await.unwrap();
+
+        let received = tokio::time::timeout(Duration::from_secs(1), display_b.next())
+            .await
+            .expect("display-b should receive its message")
+            .unwrap()
+            .unwrap();
+        assert!(received.to_text().unwrap().contains(r#""slide_index":2"#));
+
+        let nothing = tokio::time::timeout(Duration::from_millis(200), display_a.next()).await;
+        assert!(nothing.is_err(), "display-a must not receive display-b's message");
+    }
+
+    #[tokio::test]
+    async fn test_pin_pairing_over_handshake() {
+        let mut server = WebSocketServer::new();
+        let mut events = server.subscribe();
+        let port = server.start(0).await.unwrap();
+        let code = server.begin_pairing(crate::websocket::pairing::DEFAULT_PIN_TTL);
+
+        let url = format!("ws://127.0.0.1:{}/?pin={}&name=Booth", port, code.pin);
+        let (mut controller, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        let first = tokio::time::timeout(Duration::from_secs(1), controller.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let token = match serde_json::from_str::<WsMessage>(first.to_text().unwrap()).unwrap() {
+            WsMessage::Paired(data) => data.token,
+            other => panic!("Expected Paired message, got {:?}", other),
+        };
+        assert!(!token.is_empty());
+
+        match events.recv().await.unwrap() {
+            ServerEvent::PairingsChanged(pairings) => assert_eq!(pairings.len(), 1),
+            other => panic!("Expected PairingsChanged, got {:?}", other),
+        }
+        assert_eq!(server.pairings()[0].client_name, "Booth");
+
+        // The PIN is single use
+        let reuse = tokio_tungstenite::connect_async(&url).await;
+        assert!(reuse.is_err());
+
+        let pair_id = server.pairings()[0].pair_id.clone();
+        server.revoke_pairing(&pair_id).await.unwrap();
+        assert!(server.pairings().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_frontend_controller_flow() {
+        use futures_util::sink::SinkExt;
+
+        // What WebSocketContext sends: bare JSON, no hello and no envelope
+        let slide = |index: usize| {
+            format!(
+                r#"{{"type":"slide","data":{{"church_id":"church-123","event_id":"event-456","song_id":"song-789","slide_index":{},"timestamp":1234567890}}}}"#,
+                index
+            )
+        };
+        let next_slide_index = |frame: Message| match serde_json::from_str::<Envelope>(frame.to_text().unwrap()).unwrap().message {
+            WsMessage::Slide(data) => data.slide_index,
+            other => panic!("Expected a slide, got {:?}", other),
+        };
+
+        let mut server = WebSocketServer::new();
+        let port = server.start(0).await.unwrap();
+        // The display's own webview connects without a token or hello
+        let (mut display, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}", port)).await.unwrap();
+
+        // A controller on the same machine publishes straight away
+        let (mut local, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}", port)).await.unwrap();
+        local.send(Message::Text(slide(1))).await.unwrap();
+        let frame = tokio::time::timeout(Duration::from_secs(1), display.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!(next_slide_index(frame), 1);
+
+        // A remote controller pairs with the PIN, keeps the token it is sent
+        // and reconnects with it
+        let code = server.begin_pairing(crate::websocket::pairing::DEFAULT_PIN_TTL);
+        let url = format!("ws://127.0.0.1:{}/?pin={}&name=Booth", port, code.pin);
+        let (mut pairing, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let first = tokio::time::timeout(Duration::from_secs(1), pairing.next()).await.unwrap().unwrap().unwrap();
+        let token = match serde_json::from_str::<WsMessage>(first.to_text().unwrap()).unwrap() {
+            WsMessage::Paired(data) => data.token,
+            other => panic!("Expected Paired message, got {:?}", other),
+        };
+        pairing.close(None).await.unwrap();
+
+        let url = format!("ws://127.0.0.1:{}/?token={}", port, token);
+        let (mut controller, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        controller.send(Message::Text(slide(2))).await.unwrap();
+        let frame = tokio::time::timeout(Duration::from_secs(1), display.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!(next_slide_index(frame), 2);
+    }
+
+    #[tokio::test]
+    async fn test_tls_with_plain_loopback_fallback() {
+        use crate::websocket::tls::pinned_connector;
+        use tokio_rustls::rustls::pki_types::ServerName;
+
+        let identity = TlsIdentity::generate().unwrap();
+        let mut server = WebSocketServer::new();
+        server.set_tls(Some(identity.clone())).unwrap();
+        assert_eq!(server.tls_fingerprint().as_deref(), Some(identity.fingerprint()));
+        assert!(server.listening_fingerprint().is_none());
+        let port = server.start(0).await.unwrap();
+        assert_eq!(server.listening_fingerprint().as_deref(), Some(identity.fingerprint()));
+
+        // The certificate cannot change under a running listener
+        assert!(server.set_tls(None).is_err());
+        assert!(server.set_tls(Some(TlsIdentity::generate().unwrap())).is_err());
+        assert!(server.set_tls(Some(identity.clone())).is_ok());
+
+        // A controller pinning the certificate gets wss://
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let name = ServerName::try_from("mobile-worship-display.local").unwrap();
+        let tls = pinned_connector(identity.fingerprint())
+            .unwrap()
+            .connect(name, stream)
+            .await
+            .unwrap();
+        let url = format!("wss://127.0.0.1:{}", port);
+        assert!(tokio_tungstenite::client_async(&url, tls).await.is_ok());
+
+        // The local webview can still use plain ws:// over loopback
+        let url = format!("ws://127.0.0.1:{}", port);
+        assert!(tokio_tungstenite::connect_async(&url).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_late_joiner_receives_snapshot() {
+        use crate::websocket::types::{HelloData, LyricsData, SlideData};
+        use futures_util::sink::SinkExt;
+
+        let mut server = WebSocketServer::new();
+        let port = server.start(0).await.unwrap();
+
+        server.broadcast(WsMessage::Lyrics(LyricsData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            title: "Amazing Grace".to_string(),
+            lyrics: "Verse 1".to_string(),
+            background_url: None,
+            timestamp: 1234567890,
+            execute_at: None,
+        })).await.unwrap();
+        server.broadcast(WsMessage::Slide(SlideData {
+            target_display_id: Some("display-a".to_string()),
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            slide_index: 5,
+            timestamp: 1234567890,
+            execute_at: None,
+        })).await.unwrap();
+
+        let url = format!("ws://127.0.0.1:{}", port);
+        let (mut display, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        let replayed = tokio::time::timeout(Duration::from_secs(1), display.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(replayed.to_text().unwrap().contains("Amazing Grace"));
+
+        let hello = WsMessage::Hello(HelloData {
+            display_id: Some("display-a".to_string()),
+            ..Default::default()
+        });
+        display.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+
+        let targeted = tokio::time::timeout(Duration::from_secs(1), display.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(targeted.to_text().unwrap().contains(r#""slide_index":5"#));
+    }
+
+    #[tokio::test]
+    async fn test_sequenced_delivery_and_acks() {
+        use crate::websocket::types::{AckData, Envelope, HelloData, SlideData};
+        use futures_util::sink::SinkExt;
+
+        let slide = |index: usize| WsMessage::Slide(SlideData {
+            target_display_id: Some("display-a".to_string()),
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            slide_index: index,
+            timestamp: 1234567890,
+            execute_at: None,
+        });
+
+        let mut server = WebSocketServer::new();
+        let mut events = server.subscribe();
+        let port = server.start(0).await.unwrap();
+
+        // Nobody has registered display-a yet
+        let report = server.broadcast(slide(1)).await.unwrap();
+        assert_eq!(report.seq, Some(1));
+        assert_eq!(report.sent_to, 0);
+        assert_eq!(report.missed_displays, vec!["display-a".to_string()]);
+        match events.recv().await.unwrap() {
+            ServerEvent::DisplayMissedUpdate(missed) => assert_eq!(missed.seq, 1),
+            other => panic!("Expected DisplayMissedUpdate, got {:?}", other),
+        }
+
+        // On registering, the display is sent the update it missed
+        let url = format!("ws://127.0.0.1:{}", port);
+        let (mut display, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let hello = WsMessage::Hello(HelloData {
+            display_id: Some("display-a".to_string()),
+            ..Default::default()
+        });
+        display.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+
+        let resent = tokio::time::timeout(Duration::from_secs(1), display.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let envelope: Envelope = serde_json::from_str(resent.to_text().unwrap()).unwrap();
+        assert_eq!(envelope.seq, Some(1));
+
+        let ack = WsMessage::Ack(AckData { seq: 1 });
+        display.send(Message::Text(serde_json::to_string(&ack).unwrap())).await.unwrap();
+
+        let report = server.broadcast(slide(2)).await.unwrap();
+        assert_eq!(report.seq, Some(2));
+        assert_eq!(report.sent_to, 1);
+        assert!(report.missed_displays.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_http_serves_media_ranges_and_status() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        struct TestLibrary(PathBuf);
+        impl MediaLibrary for TestLibrary {
+            fn path(&self, media_id: &str) -> Option<PathBuf> {
+                (media_id == "video-1").then(|| self.0.clone())
+            }
+            fn stats(&self) -> Option<CacheStats> {
+                Some(CacheStats { entry_count: 1, total_size: 10, max_size: 100 })
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("mw-http-{}.mp4", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"0123456789").unwrap();
+        let mut server = WebSocketServer::new();
+        server.set_media_library(Arc::new(TestLibrary(path.clone())));
+        let port = server.start(0).await.unwrap();
+
+        // Two requests on one kept-alive connection
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let requests = "GET /media/video-1 HTTP/1.1\r\nHost: test\r\nRange: bytes=2-5\r\n\r\n\
+                        GET /status HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n";
+        stream.write_all(requests.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(2), stream.read_to_string(&mut response)).await.unwrap().unwrap();
+
+        let (media, status) = response.split_at(response.find("HTTP/1.1 200").unwrap());
+        assert!(media.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(media.contains("Content-Range: bytes 2-5/10\r\n"));
+        assert!(media.contains("Content-Type: video/mp4\r\n"));
+        assert!(media.ends_with("\r\n\r\n2345"));
+        let body: serde_json::Value = serde_json::from_str(status.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["protocol_version"], PROTOCOL_VERSION);
+        assert_eq!(body["cache"]["entry_count"], 1);
+
+        // Unknown media, and WebSocket upgrades still work on the same port
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream.write_all(b"HEAD /media/missing HTTP/1.0\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}", port)).await.unwrap();
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_broadcasts_stay_within_joined_room() {
+        use crate::websocket::types::{HelloData, SlideData};
+        use futures_util::sink::SinkExt;
+
+        let room = |event_id: &str| Room {
+            church_id: "church-123".to_string(),
+            event_id: event_id.to_string(),
+        };
+        let slide = |event_id: &str, index: usize| WsMessage::Slide(SlideData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: event_id.to_string(),
+            song_id: "song-789".to_string(),
+            slide_index: index,
+            timestamp: 1234567890,
+            execute_at: None,
+        });
+
+        let mut server = WebSocketServer::new();
+        let port = server.start(0).await.unwrap();
+        let url = format!("ws://127.0.0.1:{}", port);
+        let mut displays = Vec::new();
+        for (display_id, event_id) in [("sanctuary", "main"), ("youth-room", "youth")] {
+            let (mut display, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+            let hello = WsMessage::Hello(HelloData {
+                display_id: Some(display_id.to_string()),
+                ..Default::default()
+            });
+            display.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+            let join = WsMessage::Join(room(event_id));
+            display.send(Message::Text(serde_json::to_string(&join).unwrap())).await.unwrap();
+            displays.push(display);
+        }
+        for _ in 0..50 {
+            if server.rooms().await.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let rooms = server.rooms().await;
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(rooms[0].room, room("main"));
+        assert_eq!(rooms[0].members[0].display_id.as_deref(), Some("sanctuary"));
+
+        let report = server.broadcast(slide("youth", 2)).await.unwrap();
+        assert_eq!(report.sent_to, 1);
+        assert!(report.missed_displays.is_empty());
+        let report = server.broadcast(slide("main", 1)).await.unwrap();
+        assert_eq!(report.sent_to, 1);
+
+        // The sanctuary display only ever sees the main room's slide
+        let frame = tokio::time::timeout(Duration::from_secs(1), displays[0].next()).await.unwrap().unwrap().unwrap();
+        assert!(frame.to_text().unwrap().contains(r#""event_id":"main""#));
+        let frame = tokio::time::timeout(Duration::from_secs(1), displays[1].next()).await.unwrap().unwrap().unwrap();
+        assert!(frame.to_text().unwrap().contains(r#""event_id":"youth""#));
+    }
+
+    #[tokio::test]
+    async fn test_unfreeze_resends_current_state() {
+        use crate::websocket::types::{LyricsData, ScreenData};
+
+        let lyrics = |title: &str| WsMessage::Lyrics(LyricsData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            title: title.to_string(),
+            lyrics: "Verse 1".to_string(),
+            background_url: None,
+            timestamp: 1234567890,
+            execute_at: None,
+        });
+        let freeze = |enabled: bool| WsMessage::Freeze(ScreenData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            enabled,
+            timestamp: 1234567890,
+        });
+
+        let mut server = WebSocketServer::new();
+        let port = server.start(0).await.unwrap();
+        let url = format!("ws://127.0.0.1:{}", port);
+        let (mut display, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        server.broadcast(freeze(true)).await.unwrap();
+        server.broadcast(lyrics("While Frozen")).await.unwrap();
+        server.broadcast(freeze(false)).await.unwrap();
+
+        let mut received = Vec::new();
+        while let Ok(Some(Ok(message))) =
+            tokio::time::timeout(Duration::from_millis(200), display.next()).await
+        {
+            received.push(message.into_text().unwrap());
+        }
+
+        assert_eq!(received.len(), 4);
+        assert!(received[2].contains(r#""type":"freeze""#));
+        assert!(received[3].contains("While Frozen"));
+    }
+
+    #[tokio::test]
+    async fn test_precache_ack_marks_display_ready() {
+        use crate::websocket::types::{HelloData, PrecacheData, PrecacheMediaItem};
+        use futures_util::sink::SinkExt;
+
+        let mut server = WebSocketServer::new();
+        let mut events = server.subscribe();
+        let port = server.start(0).await.unwrap();
+
+        let url = format!("ws://127.0.0.1:{}", port);
+        let (mut display, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let hello = WsMessage::Hello(HelloData {
+            display_id: Some("display-a".to_string()),
+            ..Default::default()
+        });
+        display.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+        wait_for_display(&server, "display-a").await;
+        let (mut other_display, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let hello = WsMessage::Hello(HelloData {
+            display_id: Some("display-b".to_string()),
+            ..Default::default()
+        });
+        other_display.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+        wait_for_display(&server, "display-b").await;
+        let (mut controller, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        server.broadcast(WsMessage::Precache(PrecacheData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            media: vec![PrecacheMediaItem {
+                media_id: "media-1".to_string(),
+                url: "https://example.com/a.jpg".to_string(),
+                media_type: "image".to_string(),
+                expires_at: 1234567890,
+            }],
+            songs: Vec::new(),
+        })).await.unwrap();
+
+        let matrix = server.precache_readiness("event-456").await;
+        assert_eq!(matrix.displays.len(), 2);
+        assert!(!matrix.all_ready);
+
+        let ack = r#"{"type":"precache_ack","data":{"eventId":"event-456","ready":true,"statuses":[{"mediaId":"media-1","status":"ready"}]}}"#;
+        display.send(Message::Text(ack.to_string())).await.unwrap();
+
+        let ready = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                if let ServerEvent::DisplayPrecacheReady(ready) = events.recv().await.unwrap() {
+                    return ready;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(ready.display_id, "display-a");
+        let matrix = server.precache_readiness("event-456").await;
+        assert!(matrix.displays.iter().any(|display| display.display_id == "display-a" && display.ready));
+        assert!(!matrix.all_ready);
+
+        // The report reaches the controller, but not the other display
+        let received = drain_text(&mut controller).await;
+        assert!(received.iter().any(|text| text.contains(r#""type":"precache_ack""#)));
+        let received = drain_text(&mut other_display).await;
+        assert!(received.iter().any(|text| text.contains(r#""type":"precache""#)));
+        assert!(!received.iter().any(|text| text.contains(r#""type":"precache_ack""#)));
+        assert!(!drain_text(&mut display).await.iter().any(|text| text.contains("precache_ack")));
+    }
+
+    #[tokio::test]
+    async fn test_display_roster_events() {
+        use crate::websocket::types::HelloData;
+        use futures_util::sink::SinkExt;
+
+        let mut server = WebSocketServer::new();
+        let mut events = server.subscribe();
+        let port = server.start(0).await.unwrap();
+
+        let url = format!("ws://127.0.0.1:{}", port);
+        let (mut display, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let hello = WsMessage::Hello(HelloData {
+            display_id: Some("display-a".to_string()),
+            ..Default::default()
+        });
+        display.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+
+        let timeout = Duration::from_secs(1);
+        match tokio::time::timeout(timeout, events.recv()).await.unwrap().unwrap() {
+            ServerEvent::DisplayConnected(info) => assert_eq!(info.display_id.as_deref(), Some("display-a")),
+            other => panic!("Expected DisplayConnected, got {:?}", other),
+        }
+
+        let roster = server.connected_clients().await;
+        assert_eq!(roster.len(), 1);
+        assert!(!roster[0].paired);
+
+        display.close(None).await.unwrap();
+        match tokio::time::timeout(timeout, events.recv()).await.unwrap().unwrap() {
+            ServerEvent::DisplayDisconnected(gone) => {
+                assert_eq!(gone.client.display_id.as_deref(), Some("display-a"));
+                assert_eq!(gone.reason, "closed by client");
+            }
+            other => panic!("Expected DisplayDisconnected, got {:?}", other),
+        }
+        assert!(server.connected_clients().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_media_pushed_by_controller_is_received() {
+        use crate::websocket::transfer::OutgoingTransfers;
+        use crate::websocket::types::TransferState;
+        use futures_util::sink::SinkExt;
+
+        let dir = std::env::temp_dir().join(format!("mw-server-transfer-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("background.png");
+        let data: Vec<u8> = (0..200_000).map(|i| (i % 7) as u8).collect();
+        std::fs::write(&source, &data).unwrap();
+
+        let mut server = WebSocketServer::new();
+        server.set_transfer_dir(dir.join("incoming")).await;
+        let mut events = server.subscribe();
+        let port = server.start(0).await.unwrap();
+
+        let url = format!("ws://127.0.0.1:{}", port);
+        let (mut controller, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let server_addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+
+        let mut outgoing = OutgoingTransfers::new();
+        let start = OutgoingTransfers::prepare("media-1", "2024-01-01T00:00:00Z", &source).await.unwrap();
+        outgoing.begin(server_addr, &source, &start).unwrap();
+        controller
+            .send(Message::Text(serde_json::to_string(&WsMessage::MediaTransferStart(start)).unwrap()))
+            .await
+            .unwrap();
+
+        // Answer every status with the chunks it asks for, until the display is done
+        let timeout = Duration::from_secs(2);
+        loop {
+            let message = tokio::time::timeout(timeout, controller.next()).await.unwrap().unwrap().unwrap();
+            let Ok(WsMessage::MediaTransferStatus(status)) = serde_json::from_str(message.to_text().unwrap()) else {
+                continue;
+            };
+            if status.state == TransferState::Complete {
+                break;
+            }
+            assert_ne!(status.state, TransferState::Failed, "{:?}", status.error);
+            for frame in outgoing.on_status(server_addr, &status).await.unwrap() {
+                controller.send(Message::Binary(frame)).await.unwrap();
+            }
+        }
+
+        let received = tokio::time::timeout(timeout, async {
+            loop {
+                if let ServerEvent::MediaReceived(media) = events.recv().await.unwrap() {
+                    return media;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(received.media_id, "media-1");
+        assert_eq!(received.extension, "png");
+        assert_eq!(std::fs::read(&received.path).unwrap(), data);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_stop_sends_close_frame_and_restart_reuses_port() {
+        let mut server = WebSocketServer::new();
+        server.set_config(ServerConfig {
+            bind_address: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            ..ServerConfig::default()
+        });
+        let port = server.start(0).await.unwrap();
+        let url = format!("ws://127.0.0.1:{}", port);
+        let (mut client, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        server.stop().await;
+        assert_eq!(server.port(), 0);
+
+        let closed = tokio::time::timeout(Duration::from_secs(1), client.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        match closed {
+            Message::Close(Some(frame)) => {
+                assert_eq!(frame.code, CloseCode::Away);
+                assert_eq!(frame.reason, "server stopped");
+            }
+            other => panic!("Expected a close frame, got {:?}", other),
+        }
+        assert!(tokio_tungstenite::connect_async(&url).await.is_err());
+
+        assert_eq!(server.restart().await.unwrap(), port);
+        assert!(tokio_tungstenite::connect_async(&url).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_display_cannot_publish_and_invalid_frames_are_rejected() {
+        use crate::websocket::types::{HelloData, SlideData};
+        use futures_util::sink::SinkExt;
+
+        let mut server = WebSocketServer::new();
+        let port = server.start(0).await.unwrap();
+        let url = format!("ws://127.0.0.1:{}", port);
+
+        let (mut display, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (mut observer, _) = tokio_tungstenite::connect_async(format!("{}/?role=observer", url)).await.unwrap();
+        let hello = WsMessage::Hello(HelloData {
+            display_id: Some("display-a".to_string()),
+            ..Default::default()
+        });
+        display.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+        wait_for_display(&server, "display-a").await;
+
+        let slide = WsMessage::Slide(SlideData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            slide_index: 2,
+            timestamp: 1234567890,
+            execute_at: None,
+        });
+        display.send(Message::Text(serde_json::to_string(&slide).unwrap())).await.unwrap();
+        assert_eq!(next_error(&mut display).await.code, ErrorCode::Forbidden);
+
+        display.send(Message::Text(r#"{"type":"takeover"}"#.to_string())).await.unwrap();
+        assert_eq!(next_error(&mut display).await.code, ErrorCode::InvalidMessage);
+
+        // Observers may not raise themselves to display with a hello
+        observer.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+        assert_eq!(next_error(&mut observer).await.code, ErrorCode::Forbidden);
+
+        // Nothing was relayed
+        let nothing = tokio::time::timeout(Duration::from_millis(200), observer.next()).await;
+        assert!(nothing.is_err(), "rejected messages must not be relayed");
+        assert!(server.snapshots.lock().unwrap().replay(None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_versioned_hello_is_welcomed_with_negotiated_capabilities() {
+        use crate::websocket::types::{HelloData, PROTOCOL_VERSION};
+        use futures_util::sink::SinkExt;
+
+        let mut server = WebSocketServer::new();
+        let port = server.start(0).await.unwrap();
+        let url = format!("ws://127.0.0.1:{}", port);
+        let (mut display, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        let hello = WsMessage::Hello(HelloData {
+            display_id: Some("display-a".to_string()),
+            protocol_version: Some(PROTOCOL_VERSION),
+            capabilities: vec![Capability::Transitions, Capability::Precache],
+            ..Default::default()
+        });
+        display.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+
+        let reply = tokio::time::timeout(Duration::from_secs(1), display.next()).await.unwrap().unwrap().unwrap();
+        let WsMessage::Welcome(welcome) = serde_json::from_str(reply.to_text().unwrap()).unwrap() else {
+            panic!("Expected a welcome, got {}", reply);
+        };
+        assert_eq!(welcome.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(welcome.capabilities, vec![Capability::Transitions, Capability::Precache]);
+        assert_eq!(welcome.role, Role::Display);
+
+        // The display did not offer binary transfers, so media is not pushed to it
+        wait_for_display(&server, "display-a").await;
+        let path = std::env::temp_dir().join("mw-test-capabilities.jpg");
+        std::fs::write(&path, b"image").unwrap();
+        let result = server.push_media("display-a", "media-1", "2024-01-01", &path).await;
+        assert!(result.unwrap_err().contains("binary"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_negotiated_encoding_applies_after_welcome() {
+        use crate::websocket::codec::{Encoding, FLAG_DEFLATE, FLAG_MSGPACK};
+        use crate::websocket::types::{HelloData, LyricsData, ScreenData, PROTOCOL_VERSION};
+        use futures_util::sink::SinkExt;
+
+        let mut server = WebSocketServer::new();
+        let port = server.start(0).await.unwrap();
+        let (mut display, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}", port)).await.unwrap();
+
+        let hello = WsMessage::Hello(HelloData {
+            display_id: Some("display-a".to_string()),
+            protocol_version: Some(PROTOCOL_VERSION),
+            capabilities: vec![Capability::MsgPack, Capability::Deflate],
+            ..Default::default()
+        });
+        display.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+
+        // The welcome is still JSON text
+        let reply = tokio::time::timeout(Duration::from_secs(1), display.next()).await.unwrap().unwrap().unwrap();
+        assert!(matches!(serde_json::from_str(reply.to_text().unwrap()).unwrap(), WsMessage::Welcome(_)));
+        wait_for_display(&server, "display-a").await;
+
+        server.broadcast(WsMessage::Lyrics(LyricsData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            title: "Amazing Grace".to_string(),
+            lyrics: "Amazing grace, how sweet the sound\n".repeat(20),
+            background_url: None,
+            timestamp: 1234567890,
+            execute_at: None,
+        })).await.unwrap();
+        let Message::Binary(frame) = display.next().await.unwrap().unwrap() else {
+            panic!("Expected a binary frame");
+        };
+        assert_eq!(frame[1], FLAG_MSGPACK | FLAG_DEFLATE);
+        let envelope: Envelope = serde_json::from_str(&codec::decode(&frame).unwrap()).unwrap();
+        assert!(matches!(envelope.message, WsMessage::Lyrics(data) if data.title == "Amazing Grace"));
+
+        // Encoded frames from the client go through the same role checks
+        let black = WsMessage::Black(ScreenData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            enabled: true,
+            timestamp: 1234567890,
+        });
+        let encoding = Encoding { msgpack: true, deflate: false };
+        display.send(encoding.encode_text(&serde_json::to_string(&black).unwrap()).unwrap()).await.unwrap();
+        let Message::Binary(frame) = display.next().await.unwrap().unwrap() else {
+            panic!("Expected a binary frame");
+        };
+        let WsMessage::Error(error) = serde_json::from_str(&codec::decode(&frame).unwrap()).unwrap() else {
+            panic!("Expected an error");
+        };
+        assert_eq!(error.code, ErrorCode::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_execute_at_is_converted_to_server_clock() {
+        use crate::websocket::types::{HelloData, SlideData, PROTOCOL_VERSION};
+        use futures_util::sink::SinkExt;
+
+        let mut server = WebSocketServer::new();
+        let port = server.start(0).await.unwrap();
+        let url = format!("ws://127.0.0.1:{}", port);
+        let (mut display, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (mut controller, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        let hello = WsMessage::Hello(HelloData {
+            display_id: Some("display-a".to_string()),
+            ..Default::default()
+        });
+        display.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+        wait_for_display(&server, "display-a").await;
+
+        let hello = WsMessage::Hello(HelloData {
+            protocol_version: Some(PROTOCOL_VERSION),
+            capabilities: vec![Capability::ClockSync],
+            ..Default::default()
+        });
+        controller.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+
+        // Answer the server's clock request from a clock running 5s ahead
+        const AHEAD_MS: i64 = 5_000;
+        loop {
+            let frame = tokio::time::timeout(Duration::from_secs(1), controller.next()).await.unwrap().unwrap().unwrap();
+            if let Ok(WsMessage::ClockSync(request)) = serde_json::from_str(frame.to_text().unwrap()) {
+                let now = clock::now_ms() + AHEAD_MS;
+                let answer = WsMessage::ClockSync(clock::answer(&request, now, now));
+                controller.send(Message::Text(serde_json::to_string(&answer).unwrap())).await.unwrap();
+                break;
+            }
+        }
+        let mut offset = None;
+        for _ in 0..50 {
+            offset = server.connected_clients().await.iter().find_map(|info| info.clock_offset_ms);
+            if offset.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let offset = offset.expect("controller clock was never measured");
+        assert!((offset - AHEAD_MS).abs() < 100, "offset {}", offset);
+
+        let execute_at = clock::now_ms() + AHEAD_MS + 2_000;
+        let slide = WsMessage::Slide(SlideData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            slide_index: 2,
+            timestamp: 1234567890,
+            execute_at: Some(execute_at),
+        });
+        controller.send(Message::Text(serde_json::to_string(&slide).unwrap())).await.unwrap();
+
+        let frame = tokio::time::timeout(Duration::from_secs(1), display.next()).await.unwrap().unwrap().unwrap();
+        let envelope: Envelope = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+        let WsMessage::Slide(received) = envelope.message else {
+            panic!("Expected the slide, got {}", frame);
+        };
+        assert_eq!(received.execute_at, Some(execute_at - offset));
+    }
+
+    #[tokio::test]
+    async fn test_published_messages_are_journaled_and_replayed() {
+        use crate::websocket::types::SlideData;
+
+        let dir = std::env::temp_dir().join(format!("mw-journal-{}", uuid::Uuid::new_v4()));
+        let mut server = WebSocketServer::new();
+        let mut events = server.subscribe();
+        server.set_journal_dir(dir.clone());
+        let port = server.start(0).await.unwrap();
+
+        for index in [1, 2] {
+            server.broadcast(WsMessage::Slide(SlideData {
+                target_display_id: None,
+                church_id: "church-123".to_string(),
+                event_id: "event-456".to_string(),
+                song_id: "song-789".to_string(),
+                slide_index: index,
+                timestamp: 1234567890,
+                execute_at: None,
+            })).await.unwrap();
+        }
+        assert_eq!(server.journals().unwrap()[0].event_id, "event-456");
+
+        let (mut display, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}", port)).await.unwrap();
+        // The snapshot replay on connect
+        display.next().await.unwrap().unwrap();
+
+        assert_eq!(server.replay_journal("event-456", 100.0, None).unwrap(), 2);
+        // Back-to-back slides may be coalesced; the last one always arrives
+        loop {
+            let frame = tokio::time::timeout(Duration::from_secs(1), display.next()).await.unwrap().unwrap().unwrap();
+            let envelope: Envelope = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+            // Replayed messages stay out of the live sequence
+            assert_eq!(envelope.seq, None);
+            if matches!(envelope.message, WsMessage::Slide(data) if data.slide_index == 2) {
+                break;
+            }
+        }
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(1), events.recv()).await.unwrap().unwrap();
+            if let ServerEvent::JournalReplayFinished(finished) = event {
+                assert_eq!(finished.published, 2);
+                break;
+            }
+        }
+
+        // Replays are not journaled again, and leave the live snapshot alone
+        let path = dir.join("event-456.jsonl");
+        assert_eq!(journal::read(&path).unwrap().len(), 2);
+        let current = server.status().await.current;
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].seq, Some(2));
+        assert!(server.replay_journal("no-such-event", 1.0, None).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
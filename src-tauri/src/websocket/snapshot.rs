@@ -0,0 +1,262 @@
+//! Last-known display state, replayed to clients that (re)connect
+//!
+//! The server remembers the most recent `Lyrics` and `Slide` message for each
+//! event and target display, plus any black/clear/logo/freeze that is switched
+//! on. A display that reboots mid-service gets the current song and slide as
+//! soon as it connects, instead of a blank screen until the operator advances.
+//!
+//! Messages keep their original sequence number, so a display that already
+//! applied one can recognise the replay as a duplicate, and are replayed in
+//! that order. While a freeze is on, the replay carries the frame that was on
+//! screen when it started rather than content sent since, which a frozen
+//! display never showed.
+
+use crate::websocket::types::{Envelope, WsMessage};
+use std::collections::HashMap;
+
+/// Snapshot key: event plus target display (None for untargeted messages)
+type SnapshotKey = (String, Option<String>);
+
+/// Current display state for one event/target
+#[derive(Debug, Default)]
+struct Snapshot {
+    lyrics: Option<Envelope>,
+    slide: Option<Envelope>,
+    clear: Option<Envelope>,
+    logo: Option<Envelope>,
+    black: Option<Envelope>,
+    freeze: Option<Envelope>,
+    /// Lyrics and slide on screen when the active freeze started
+    frozen_frame: Vec<Envelope>,
+    /// Sequence number of the last change, used to replay oldest first
+    updated: u64,
+}
+
+impl Snapshot {
+    /// What a display should have applied, in sequence order
+    fn messages(&self) -> Vec<Envelope> {
+        let mut messages: Vec<Envelope> = if self.freeze.is_some() {
+            self.frozen_frame.clone()
+        } else {
+            self.lyrics.iter().chain(&self.slide).cloned().collect()
+        };
+        messages.extend([&self.clear, &self.logo, &self.black, &self.freeze].into_iter().flatten().cloned());
+        messages.sort_by_key(|envelope| envelope.seq);
+        messages
+    }
+
+    /// Remember a screen command while it is on; switching it off forgets it
+    fn set_overlay(slot: &mut Option<Envelope>, enabled: bool, envelope: Envelope) {
+        *slot = enabled.then_some(envelope);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SnapshotStore {
+    snapshots: HashMap<SnapshotKey, Snapshot>,
+}
+
+impl SnapshotStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember a message (sent with sequence number `seq`) if it changes what a display shows
+    pub fn record(&mut self, seq: u64, message: &WsMessage) {
+        let envelope = Envelope::new(message.clone(), Some(seq));
+        let snapshot = match message {
+            WsMessage::Lyrics(data) => {
+                let snapshot = self.entry(&data.event_id, data.target_display_id.as_deref());
+                // A slide index from a different song is meaningless with the new lyrics
+                let stale_slide = snapshot.slide.as_ref().is_some_and(|slide| {
+                    matches!(&slide.message, WsMessage::Slide(slide) if slide.song_id != data.song_id)
+                });
+                if stale_slide {
+                    snapshot.slide = None;
+                }
+                snapshot.lyrics = Some(envelope);
+                snapshot
+            }
+            WsMessage::Slide(data) => {
+                let snapshot = self.entry(&data.event_id, data.target_display_id.as_deref());
+                snapshot.slide = Some(envelope);
+                snapshot
+            }
+            WsMessage::Black(data) => {
+                let snapshot = self.entry(&data.event_id, data.target_display_id.as_deref());
+                Snapshot::set_overlay(&mut snapshot.black, data.enabled, envelope);
+                snapshot
+            }
+            WsMessage::Clear(data) => {
+                let snapshot = self.entry(&data.event_id, data.target_display_id.as_deref());
+                Snapshot::set_overlay(&mut snapshot.clear, data.enabled, envelope);
+                snapshot
+            }
+            WsMessage::Logo(data) => {
+                let snapshot = self.entry(&data.event_id, data.target_display_id.as_deref());
+                Snapshot::set_overlay(&mut snapshot.logo, data.enabled, envelope);
+                snapshot
+            }
+            WsMessage::Freeze(data) => {
+                let snapshot = self.entry(&data.event_id, data.target_display_id.as_deref());
+                // A repeated freeze keeps the frame from the first one
+                if data.enabled && snapshot.freeze.is_none() {
+                    snapshot.frozen_frame = snapshot.lyrics.iter().chain(&snapshot.slide).cloned().collect();
+                } else if !data.enabled {
+                    snapshot.frozen_frame.clear();
+                }
+                Snapshot::set_overlay(&mut snapshot.freeze, data.enabled, envelope);
+                snapshot
+            }
+            _ => return,
+        };
+        snapshot.updated = seq;
+    }
+
+    fn entry(&mut self, event_id: &str, target_display_id: Option<&str>) -> &mut Snapshot {
+        self.snapshots
+            .entry((event_id.to_string(), target_display_id.map(str::to_string)))
+            .or_default()
+    }
+
+    /// Messages to replay for a target (None = the untargeted state every client sees)
+    ///
+    /// Events are replayed oldest first so the most recently updated one ends up
+    /// on screen; within an event, messages follow their sequence numbers (see
+    /// `Snapshot::messages`).
+    pub fn replay(&self, target_display_id: Option<&str>) -> Vec<Envelope> {
+        let mut snapshots: Vec<&Snapshot> = self
+            .snapshots
+            .iter()
+            .filter(|((_, target), _)| target.as_deref() == target_display_id)
+            .map(|(_, snapshot)| snapshot)
+            .collect();
+        snapshots.sort_by_key(|snapshot| snapshot.updated);
+
+        snapshots.into_iter().flat_map(Snapshot::messages).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::types::{LyricsData, ScreenData, SlideData};
+
+    fn lyrics(event_id: &str, song_id: &str, target: Option<&str>) -> WsMessage {
+        WsMessage::Lyrics(LyricsData {
+            target_display_id: target.map(str::to_string),
+            church_id: "church-123".to_string(),
+            event_id: event_id.to_string(),
+            song_id: song_id.to_string(),
+            title: song_id.to_string(),
+            lyrics: "Verse 1".to_string(),
+            background_url: None,
+            timestamp: 1234567890,
+            execute_at: None,
+        })
+    }
+
+    fn slide(event_id: &str, song_id: &str, index: usize, target: Option<&str>) -> WsMessage {
+        WsMessage::Slide(SlideData {
+            target_display_id: target.map(str::to_string),
+            church_id: "church-123".to_string(),
+            event_id: event_id.to_string(),
+            song_id: song_id.to_string(),
+            slide_index: index,
+            timestamp: 1234567890,
+            execute_at: None,
+        })
+    }
+
+    #[test]
+    fn test_keeps_latest_lyrics_and_slide() {
+        let mut store = SnapshotStore::new();
+        store.record(1, &lyrics("event-1", "song-a", None));
+        store.record(2, &slide("event-1", "song-a", 1, None));
+        store.record(3, &slide("event-1", "song-a", 3, None));
+        store.record(4, &WsMessage::Ping);
+
+        let replay = store.replay(None);
+        assert_eq!(replay.len(), 2);
+        assert!(matches!(&replay[0].message, WsMessage::Lyrics(data) if data.song_id == "song-a"));
+        assert!(matches!(&replay[1].message, WsMessage::Slide(data) if data.slide_index == 3));
+        assert_eq!(replay[1].seq, Some(3));
+    }
+
+    #[test]
+    fn test_new_song_drops_stale_slide() {
+        let mut store = SnapshotStore::new();
+        store.record(1, &lyrics("event-1", "song-a", None));
+        store.record(2, &slide("event-1", "song-a", 4, None));
+        store.record(3, &lyrics("event-1", "song-b", None));
+
+        let replay = store.replay(None);
+        assert_eq!(replay.len(), 1);
+        assert!(matches!(&replay[0].message, WsMessage::Lyrics(data) if data.song_id == "song-b"));
+    }
+
+    #[test]
+    fn test_targets_and_events_are_separate() {
+        let mut store = SnapshotStore::new();
+        store.record(1, &lyrics("event-1", "song-a", Some("display-a")));
+        store.record(2, &lyrics("event-2", "song-b", None));
+        store.record(3, &lyrics("event-1", "song-c", None));
+
+        assert_eq!(store.replay(Some("display-a")).len(), 1);
+        assert!(store.replay(Some("display-b")).is_empty());
+
+        // Most recently updated event is replayed last
+        let untargeted = store.replay(None);
+        assert!(matches!(&untargeted[1].message, WsMessage::Lyrics(data) if data.event_id == "event-1"));
+    }
+
+    fn screen(enabled: bool) -> ScreenData {
+        ScreenData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-1".to_string(),
+            enabled,
+            timestamp: 1234567890,
+        }
+    }
+
+    #[test]
+    fn test_screen_commands_replay_in_seq_order() {
+        let mut store = SnapshotStore::new();
+        store.record(1, &lyrics("event-1", "song-a", None));
+        store.record(2, &WsMessage::Black(screen(true)));
+        store.record(3, &WsMessage::Clear(screen(true)));
+
+        let replay = store.replay(None);
+        assert!(matches!(replay[0].message, WsMessage::Lyrics(_)));
+        assert!(matches!(replay[1].message, WsMessage::Black(_)));
+        assert!(matches!(replay[2].message, WsMessage::Clear(_)));
+
+        // Switching a state off means there is nothing to replay for it
+        store.record(4, &WsMessage::Black(screen(false)));
+        store.record(5, &WsMessage::Clear(screen(false)));
+        assert_eq!(store.replay(None).len(), 1);
+    }
+
+    #[test]
+    fn test_freeze_keeps_frozen_frame() {
+        let mut store = SnapshotStore::new();
+        store.record(9, &slide("event-1", "song-a", 2, None));
+        store.record(10, &WsMessage::Freeze(screen(true)));
+        store.record(11, &slide("event-1", "song-a", 3, None));
+        store.record(12, &WsMessage::Freeze(screen(true)));
+
+        // A reconnecting display shows the frame that was frozen, not slide 11
+        let replay = store.replay(None);
+        assert_eq!(replay.len(), 2);
+        assert!(matches!(&replay[0].message, WsMessage::Slide(data) if data.slide_index == 2));
+        assert!(matches!(replay[1].message, WsMessage::Freeze(_)));
+        assert_eq!(replay[1].seq, Some(12));
+
+        // Unfreezing brings the latest content back
+        store.record(13, &WsMessage::Freeze(screen(false)));
+        let replay = store.replay(None);
+        assert_eq!(replay.len(), 1);
+        assert!(matches!(&replay[0].message, WsMessage::Slide(data) if data.slide_index == 3));
+    }
+}
@@ -0,0 +1,270 @@
+//! TLS support for the WebSocket server (wss://)
+//!
+//! Each device generates a self-signed certificate once and keeps it in its app
+//! data directory. There is no CA to vouch for it; instead the SHA-256
+//! fingerprint of the certificate is published in the mDNS TXT records so
+//! controllers can pin it (see `pinned_connector`).
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{self, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime,
+};
+use tokio_rustls::rustls::{self, ClientConfig, DigitallySignedStruct, ServerConfig, SignatureScheme};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+const CERT_FILE_NAME: &str = "websocket-cert.der";
+const KEY_FILE_NAME: &str = "websocket-key.der";
+
+/// Names the self-signed certificate is issued for (informational only,
+/// controllers verify the fingerprint rather than the name)
+const CERT_SUBJECT_NAMES: &[&str] = &["mobile-worship-display.local", "localhost"];
+
+/// First byte of a TLS handshake record, used to tell TLS from plain HTTP
+pub const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+/// A device's certificate and private key
+#[derive(Clone)]
+pub struct TlsIdentity {
+    cert_der: Vec<u8>,
+    key_der: Vec<u8>,
+    fingerprint: String,
+}
+
+impl TlsIdentity {
+    /// Generate a fresh self-signed certificate
+    pub fn generate() -> Result<Self, String> {
+        let names: Vec<String> = CERT_SUBJECT_NAMES.iter().map(|n| n.to_string()).collect();
+        let certified = rcgen::generate_simple_self_signed(names)
+            .map_err(|e| format!("Failed to generate certificate: {}", e))?;
+
+        Ok(Self::from_der(
+            certified.cert.der().to_vec(),
+            certified.key_pair.serialize_der(),
+        ))
+    }
+
+    fn from_der(cert_der: Vec<u8>, key_der: Vec<u8>) -> Self {
+        let fingerprint = fingerprint_of(&cert_der);
+        Self { cert_der, key_der, fingerprint }
+    }
+
+    /// Load the certificate persisted in `dir`, generating (and saving) one if missing
+    pub fn load_or_generate(dir: &Path) -> Result<Self, String> {
+        let cert_path = dir.join(CERT_FILE_NAME);
+        let key_path = dir.join(KEY_FILE_NAME);
+
+        if let (Ok(cert_der), Ok(key_der)) = (fs::read(&cert_path), fs::read(&key_path)) {
+            let identity = Self::from_der(cert_der, key_der);
+            // Make sure the stored pair is still usable before trusting it
+            if identity.acceptor().is_ok() {
+                tracing::info!("Loaded TLS certificate {}", identity.fingerprint);
+                return Ok(identity);
+            }
+            tracing::warn!("Stored TLS certificate is invalid, generating a new one");
+        }
+
+        let identity = Self::generate()?;
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create TLS dir: {}", e))?;
+        fs::write(&cert_path, &identity.cert_der)
+            .map_err(|e| format!("Failed to write certificate: {}", e))?;
+        fs::write(&key_path, &identity.key_der)
+            .map_err(|e| format!("Failed to write private key: {}", e))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&key_path, fs::Permissions::from_mode(0o600));
+        }
+
+        tracing::info!("Generated TLS certificate {}", identity.fingerprint);
+        Ok(identity)
+    }
+
+    /// SHA-256 fingerprint of the certificate (`AB:CD:...`)
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    /// Build a TLS acceptor for the server
+    pub fn acceptor(&self) -> Result<TlsAcceptor, String> {
+        let cert = CertificateDer::from(self.cert_der.clone());
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.key_der.clone()));
+
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Failed to configure TLS: {}", e))?
+            .with_no_client_auth()
+            .with_single_cert(vec![cert], key)
+            .map_err(|e| format!("Invalid TLS certificate: {}", e))?;
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// SHA-256 fingerprint of a DER certificate, as colon-separated upper-case hex
+pub fn fingerprint_of(cert_der: &[u8]) -> String {
+    Sha256::digest(cert_der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Normalise a fingerprint for comparison (accepts any case, with or without colons)
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Trusts exactly one certificate, identified by fingerprint, the way a
+/// controller pins a display it discovered
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedCertVerifier {
+    fn new(fingerprint: &str) -> Self {
+        Self {
+            fingerprint: normalize_fingerprint(fingerprint),
+            provider: provider(),
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if normalize_fingerprint(&fingerprint_of(end_entity)) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General("Certificate fingerprint mismatch".to_string()))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// TLS connector for a client that pins the server's certificate fingerprint
+pub fn pinned_connector(fingerprint: &str) -> Result<TlsConnector, String> {
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to configure TLS: {}", e))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier::new(fingerprint)))
+        .with_no_client_auth();
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_fingerprint_format() {
+        let fp = fingerprint_of(b"not really a certificate");
+        assert_eq!(fp.len(), 32 * 3 - 1);
+        assert!(fp.split(':').all(|b| b.len() == 2));
+        assert!(fp.chars().all(|c| c == ':' || c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn test_load_or_generate_persists() {
+        let dir = std::env::temp_dir().join(format!("mw-tls-test-{}", uuid::Uuid::new_v4()));
+
+        let first = TlsIdentity::load_or_generate(&dir).unwrap();
+        let second = TlsIdentity::load_or_generate(&dir).unwrap();
+        assert_eq!(first.fingerprint(), second.fingerprint());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    async fn handshake(identity: &TlsIdentity, pinned: &str) -> Result<(), String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = identity.acceptor().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(mut tls) = acceptor.accept(stream).await {
+                let _ = tls.write_all(b"ok").await;
+                let _ = tls.shutdown().await;
+            }
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let name = ServerName::try_from("mobile-worship-display.local").unwrap();
+        let mut tls = pinned_connector(pinned)?
+            .connect(name, stream)
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut buf = [0u8; 2];
+        tls.read_exact(&mut buf).await.map_err(|e| e.to_string())?;
+        assert_eq!(&buf, b"ok");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pinned_connection() {
+        let identity = TlsIdentity::generate().unwrap();
+        let other = TlsIdentity::generate().unwrap();
+
+        assert!(handshake(&identity, identity.fingerprint()).await.is_ok());
+        assert!(handshake(&identity, other.fingerprint()).await.is_err());
+    }
+}
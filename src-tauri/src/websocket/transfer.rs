@@ -0,0 +1,551 @@
+//! Chunked media transfer over an open WebSocket
+//!
+//! The sender announces a file with `media_transfer_start` (cache key, size
+//! and SHA-256), then streams it as binary frames:
+//!
+//! ```text
+//! [kind: u8 = 0x01][transfer id: 16 bytes][offset: u64 big-endian][payload]
+//! ```
+//!
+//! The receiver appends chunks to a `.part` file in its staging directory and
+//! answers with `media_transfer_status`:
+//! - `ready` after the announcement (or when a chunk arrives out of order):
+//!   send from `received` onwards
+//! - `receiving` every `STATUS_INTERVAL` bytes, which also opens the sender's window
+//! - `complete` or `failed` once the whole file has been checked against its SHA-256
+//!
+//! The sender keeps at most `SEND_WINDOW` bytes unconfirmed, so a large video
+//! does not end up queued in memory in front of slide updates.
+//!
+//! Transfer IDs are derived from the media ID, version and hash. When a
+//! connection drops mid-transfer, announcing the same file again resumes from
+//! the `.part` file left behind.
+
+use crate::websocket::types::{MediaTransferStart, MediaTransferStatus, TransferState};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+/// First byte of a binary frame carrying a media chunk
+pub const FRAME_MEDIA_CHUNK: u8 = 0x01;
+
+/// kind + transfer id + offset
+const HEADER_LEN: usize = 1 + 16 + 8;
+
+/// Payload size of each chunk frame
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The receiver reports progress every this many bytes
+const STATUS_INTERVAL: u64 = 1024 * 1024;
+
+/// Bytes the sender may have in flight beyond the last reported progress
+const SEND_WINDOW: u64 = 4 * STATUS_INTERVAL;
+
+/// Largest file accepted (the whole media cache is capped at 500 MB)
+pub const MAX_TRANSFER_SIZE: u64 = 500 * 1024 * 1024;
+
+/// Transfer ID for a version of a media file
+pub fn transfer_id(media_id: &str, updated_at: &str, sha256: &str) -> Uuid {
+    let name = format!("{}\n{}\n{}", media_id, updated_at, sha256.to_ascii_lowercase());
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes())
+}
+
+/// A binary frame carrying part of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkFrame<'a> {
+    pub transfer_id: Uuid,
+    /// Position of the payload within the file
+    pub offset: u64,
+    pub payload: &'a [u8],
+}
+
+impl<'a> ChunkFrame<'a> {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        frame.push(FRAME_MEDIA_CHUNK);
+        frame.extend_from_slice(self.transfer_id.as_bytes());
+        frame.extend_from_slice(&self.offset.to_be_bytes());
+        frame.extend_from_slice(self.payload);
+        frame
+    }
+
+    pub fn decode(frame: &'a [u8]) -> Result<Self, String> {
+        if frame.len() < HEADER_LEN {
+            return Err(format!("Chunk frame too short ({} bytes)", frame.len()));
+        }
+        if frame[0] != FRAME_MEDIA_CHUNK {
+            return Err(format!("Not a chunk frame (kind {:#04x})", frame[0]));
+        }
+        let transfer_id = Uuid::from_slice(&frame[1..17]).map_err(|e| e.to_string())?;
+        let mut offset = [0u8; 8];
+        offset.copy_from_slice(&frame[17..HEADER_LEN]);
+        Ok(Self {
+            transfer_id,
+            offset: u64::from_be_bytes(offset),
+            payload: &frame[HEADER_LEN..],
+        })
+    }
+}
+
+/// A file that arrived in full and matched its SHA-256
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceivedMedia {
+    pub media_id: String,
+    pub updated_at: String,
+    pub extension: String,
+    /// Where the file was staged; the media cache moves it into place
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// What the receiver should do after handling a control message or chunk
+#[derive(Debug)]
+pub enum ChunkOutcome {
+    /// Nothing to tell the sender
+    Continue,
+    /// Send this status back to the sender
+    Status(MediaTransferStatus),
+    /// The file is complete (the status says so too)
+    Received(MediaTransferStatus, ReceivedMedia),
+}
+
+/// Hex-encoded SHA-256 of a file
+pub async fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Keep extensions usable as part of a file name
+fn sanitize_extension(extension: &str) -> String {
+    let ext: String = extension
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .take(8)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if ext.is_empty() { "bin".to_string() } else { ext }
+}
+
+fn status(start: &MediaTransferStart, state: TransferState, received: u64) -> MediaTransferStatus {
+    MediaTransferStatus {
+        transfer_id: start.transfer_id.clone(),
+        media_id: start.media_id.clone(),
+        state,
+        received,
+        error: None,
+    }
+}
+
+fn failed(start: &MediaTransferStart, received: u64, error: String) -> ChunkOutcome {
+    tracing::warn!("Media transfer {} ({}) failed: {}", start.transfer_id, start.media_id, error);
+    ChunkOutcome::Status(MediaTransferStatus {
+        error: Some(error),
+        ..status(start, TransferState::Failed, received)
+    })
+}
+
+struct Incoming {
+    start: MediaTransferStart,
+    part_path: PathBuf,
+    file: File,
+    received: u64,
+    /// `received` when progress was last reported
+    reported: u64,
+    /// A gap was already reported and the sender has not filled it yet
+    gap_reported: bool,
+}
+
+/// Receiving side: files being written into the staging directory
+#[derive(Default)]
+pub struct IncomingTransfers {
+    dir: Option<PathBuf>,
+    transfers: HashMap<Uuid, Incoming>,
+}
+
+impl IncomingTransfers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Directory for partial files. Transfers are refused until one is set.
+    pub fn set_dir(&mut self, dir: PathBuf) {
+        self.dir = Some(dir);
+    }
+
+    /// Handle a `media_transfer_start`: reply where the sender should start from
+    pub async fn start(&mut self, mut start: MediaTransferStart) -> ChunkOutcome {
+        let Some(dir) = self.dir.clone() else {
+            return failed(&start, 0, "This device does not accept media transfers".to_string());
+        };
+        let id = match Uuid::parse_str(&start.transfer_id) {
+            Ok(id) if id == transfer_id(&start.media_id, &start.updated_at, &start.sha256) => id,
+            _ => return failed(&start, 0, "Transfer ID does not match the file".to_string()),
+        };
+        if start.size > MAX_TRANSFER_SIZE {
+            return failed(&start, 0, format!("File too large ({} bytes)", start.size));
+        }
+        start.extension = sanitize_extension(&start.extension);
+        start.sha256 = start.sha256.to_ascii_lowercase();
+
+        if let Err(e) = fs::create_dir_all(&dir).await {
+            return failed(&start, 0, format!("Failed to create transfer dir: {}", e));
+        }
+        let part_path = dir.join(format!("{}.part", id));
+
+        // A restarted transfer picks up wherever the previous attempt stopped
+        self.transfers.remove(&id);
+        let mut received = fs::metadata(&part_path).await.map(|m| m.len()).unwrap_or(0);
+        if received > start.size {
+            let _ = fs::remove_file(&part_path).await;
+            received = 0;
+        }
+        let file = match OpenOptions::new().create(true).append(true).open(&part_path).await {
+            Ok(file) => file,
+            Err(e) => return failed(&start, 0, format!("Failed to open {}: {}", part_path.display(), e)),
+        };
+
+        if received > 0 {
+            tracing::info!("Resuming media transfer {} at {}/{} bytes", id, received, start.size);
+        } else {
+            tracing::info!("Receiving media {} ({} bytes) as transfer {}", start.media_id, start.size, id);
+        }
+
+        self.transfers.insert(id, Incoming {
+            start,
+            part_path,
+            file,
+            received,
+            reported: received,
+            gap_reported: false,
+        });
+        if received == self.transfers[&id].start.size {
+            return self.finish(id).await;
+        }
+        ChunkOutcome::Status(status(&self.transfers[&id].start, TransferState::Ready, received))
+    }
+
+    /// Handle a chunk frame
+    pub async fn chunk(&mut self, frame: ChunkFrame<'_>) -> ChunkOutcome {
+        let Some(transfer) = self.transfers.get_mut(&frame.transfer_id) else {
+            tracing::debug!("Ignoring chunk for unknown transfer {}", frame.transfer_id);
+            return ChunkOutcome::Continue;
+        };
+
+        if frame.offset < transfer.received {
+            // Already have it (resent after a `ready`)
+            return ChunkOutcome::Continue;
+        }
+        if frame.offset > transfer.received {
+            if transfer.gap_reported {
+                return ChunkOutcome::Continue;
+            }
+            transfer.gap_reported = true;
+            return ChunkOutcome::Status(status(&transfer.start, TransferState::Ready, transfer.received));
+        }
+
+        let end = transfer.received + frame.payload.len() as u64;
+        if end > transfer.start.size {
+            let outcome = failed(&transfer.start, transfer.received, "Received more data than announced".to_string());
+            self.discard(frame.transfer_id).await;
+            return outcome;
+        }
+        if let Err(e) = transfer.file.write_all(frame.payload).await {
+            let outcome = failed(&transfer.start, transfer.received, format!("Failed to write chunk: {}", e));
+            self.discard(frame.transfer_id).await;
+            return outcome;
+        }
+        transfer.received = end;
+        transfer.gap_reported = false;
+
+        if transfer.received == transfer.start.size {
+            return self.finish(frame.transfer_id).await;
+        }
+        if transfer.received - transfer.reported >= STATUS_INTERVAL {
+            transfer.reported = transfer.received;
+            return ChunkOutcome::Status(status(&transfer.start, TransferState::Receiving, transfer.received));
+        }
+        ChunkOutcome::Continue
+    }
+
+    /// Check the hash of a fully received file
+    async fn finish(&mut self, id: Uuid) -> ChunkOutcome {
+        let Some(mut transfer) = self.transfers.remove(&id) else {
+            return ChunkOutcome::Continue;
+        };
+        let _ = transfer.file.flush().await;
+        drop(transfer.file);
+
+        let size = transfer.received;
+        match hash_file(&transfer.part_path).await {
+            Ok(hash) if hash == transfer.start.sha256 => {
+                tracing::info!("Media transfer {} complete ({} bytes)", id, size);
+                let media = ReceivedMedia {
+                    media_id: transfer.start.media_id.clone(),
+                    updated_at: transfer.start.updated_at.clone(),
+                    extension: transfer.start.extension.clone(),
+                    path: transfer.part_path,
+                    size,
+                };
+                ChunkOutcome::Received(status(&transfer.start, TransferState::Complete, size), media)
+            }
+            Ok(_) => {
+                let _ = fs::remove_file(&transfer.part_path).await;
+                failed(&transfer.start, 0, "SHA-256 mismatch".to_string())
+            }
+            Err(e) => failed(&transfer.start, size, format!("Failed to hash file: {}", e)),
+        }
+    }
+
+    /// Forget a transfer and delete its partial file
+    async fn discard(&mut self, id: Uuid) {
+        if let Some(transfer) = self.transfers.remove(&id) {
+            drop(transfer.file);
+            let _ = fs::remove_file(&transfer.part_path).await;
+        }
+    }
+}
+
+struct Outgoing {
+    /// Connection the file is being sent over
+    addr: SocketAddr,
+    path: PathBuf,
+    size: u64,
+    /// Offset of the next chunk to send
+    sent: u64,
+}
+
+/// Sending side: files being streamed to a peer
+#[derive(Default)]
+pub struct OutgoingTransfers {
+    transfers: HashMap<Uuid, Outgoing>,
+}
+
+impl OutgoingTransfers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read and hash a file to announce it. Takes no lock on the transfers,
+    /// since hashing a large file takes a while.
+    pub async fn prepare(media_id: &str, updated_at: &str, path: &Path) -> Result<MediaTransferStart, String> {
+        let size = fs::metadata(path)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+            .len();
+        if size > MAX_TRANSFER_SIZE {
+            return Err(format!("File too large ({} bytes)", size));
+        }
+        let sha256 = hash_file(path)
+            .await
+            .map_err(|e| format!("Failed to hash {}: {}", path.display(), e))?;
+        let id = transfer_id(media_id, updated_at, &sha256);
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("bin");
+
+        Ok(MediaTransferStart {
+            transfer_id: id.to_string(),
+            media_id: media_id.to_string(),
+            updated_at: updated_at.to_string(),
+            extension: sanitize_extension(extension),
+            size,
+            sha256,
+        })
+    }
+
+    /// Send a prepared file over the connection `addr`; chunks follow once
+    /// the peer replies to the announcement with `ready`
+    pub fn begin(&mut self, addr: SocketAddr, path: &Path, start: &MediaTransferStart) -> Result<(), String> {
+        let id = Uuid::parse_str(&start.transfer_id).map_err(|e| e.to_string())?;
+        self.transfers.insert(id, Outgoing { addr, path: path.to_path_buf(), size: start.size, sent: 0 });
+        Ok(())
+    }
+
+    /// Handle a status from the peer on `addr`, returning the chunk frames to send next
+    pub async fn on_status(&mut self, addr: SocketAddr, status: &MediaTransferStatus) -> Result<Vec<Vec<u8>>, String> {
+        let id = Uuid::parse_str(&status.transfer_id).map_err(|e| e.to_string())?;
+        let Some(transfer) = self.transfers.get_mut(&id).filter(|t| t.addr == addr) else {
+            return Ok(Vec::new());
+        };
+
+        match status.state {
+            TransferState::Complete | TransferState::Failed => {
+                self.transfers.remove(&id);
+                return Ok(Vec::new());
+            }
+            TransferState::Ready => transfer.sent = status.received.min(transfer.size),
+            TransferState::Receiving => {}
+        }
+
+        let limit = (status.received + SEND_WINDOW).min(transfer.size);
+        if transfer.sent >= limit {
+            return Ok(Vec::new());
+        }
+
+        let mut file = File::open(&transfer.path)
+            .await
+            .map_err(|e| format!("Failed to open {}: {}", transfer.path.display(), e))?;
+        file.seek(std::io::SeekFrom::Start(transfer.sent))
+            .await
+            .map_err(|e| format!("Failed to seek {}: {}", transfer.path.display(), e))?;
+
+        let mut frames = Vec::new();
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        while transfer.sent < limit {
+            let want = (limit - transfer.sent).min(CHUNK_SIZE as u64) as usize;
+            file.read_exact(&mut buf[..want])
+                .await
+                .map_err(|e| format!("Failed to read {}: {}", transfer.path.display(), e))?;
+            frames.push(ChunkFrame { transfer_id: id, offset: transfer.sent, payload: &buf[..want] }.encode());
+            transfer.sent += want as u64;
+        }
+        Ok(frames)
+    }
+
+    /// Drop the transfers running over a connection that closed
+    pub fn cancel_for(&mut self, addr: &SocketAddr) {
+        self.transfers.retain(|_, transfer| transfer.addr != *addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("mw-transfer-test-{}", Uuid::new_v4()))
+    }
+
+    /// Deliver frames to the receiver, returning the last status it replied with
+    async fn deliver(incoming: &mut IncomingTransfers, frames: &[Vec<u8>]) -> Option<ChunkOutcome> {
+        let mut last = None;
+        for frame in frames {
+            match incoming.chunk(ChunkFrame::decode(frame).unwrap()).await {
+                ChunkOutcome::Continue => {}
+                outcome => last = Some(outcome),
+            }
+        }
+        last
+    }
+
+    #[test]
+    fn test_chunk_frame_round_trip() {
+        let id = transfer_id("media-1", "2024-01-01T00:00:00Z", "ABCD");
+        assert_eq!(id, transfer_id("media-1", "2024-01-01T00:00:00Z", "abcd"));
+
+        let frame = ChunkFrame { transfer_id: id, offset: 1 << 40, payload: b"hello" };
+        let encoded = frame.encode();
+        assert_eq!(encoded.len(), HEADER_LEN + 5);
+        assert_eq!(ChunkFrame::decode(&encoded).unwrap(), frame);
+
+        assert!(ChunkFrame::decode(&encoded[..10]).is_err());
+        let mut wrong_kind = encoded.clone();
+        wrong_kind[0] = 0x02;
+        assert!(ChunkFrame::decode(&wrong_kind).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_transfer_resumes_and_verifies() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).await.unwrap();
+        let source = dir.join("source.jpg");
+        let data: Vec<u8> = (0..(CHUNK_SIZE * 3 + 100)).map(|i| (i % 251) as u8).collect();
+        fs::write(&source, &data).await.unwrap();
+        let addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+
+        let mut outgoing = OutgoingTransfers::new();
+        let start = OutgoingTransfers::prepare("media-1", "2024-01-01T00:00:00Z", &source).await.unwrap();
+        outgoing.begin(addr, &source, &start).unwrap();
+        assert_eq!(start.extension, "jpg");
+
+        let mut incoming = IncomingTransfers::new();
+        incoming.set_dir(dir.join("incoming"));
+        let ChunkOutcome::Status(ready) = incoming.start(start.clone()).await else {
+            panic!("Expected a ready status");
+        };
+        assert_eq!((ready.state, ready.received), (TransferState::Ready, 0));
+
+        // Only the first two chunks make it before the connection drops
+        let frames = outgoing.on_status(addr, &ready).await.unwrap();
+        assert_eq!(frames.len(), 4);
+        assert!(deliver(&mut incoming, &frames[..2]).await.is_none());
+
+        // A fresh receiver (e.g. after a restart) resumes from the partial file
+        let mut incoming = IncomingTransfers::new();
+        incoming.set_dir(dir.join("incoming"));
+        let ChunkOutcome::Status(ready) = incoming.start(start).await else {
+            panic!("Expected a ready status");
+        };
+        assert_eq!(ready.received, (CHUNK_SIZE * 2) as u64);
+
+        let frames = outgoing.on_status(addr, &ready).await.unwrap();
+        assert_eq!(frames.len(), 2);
+        match deliver(&mut incoming, &frames).await {
+            Some(ChunkOutcome::Received(status, media)) => {
+                assert_eq!(status.state, TransferState::Complete);
+                assert_eq!(media.size, data.len() as u64);
+                assert_eq!(fs::read(&media.path).await.unwrap(), data);
+            }
+            other => panic!("Expected the file to be received, got {:?}", other),
+        }
+
+        let _ = fs::remove_dir_all(&dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_hash_mismatch_and_gaps() {
+        let dir = temp_dir();
+        let mut incoming = IncomingTransfers::new();
+
+        let sha256 = "00".repeat(32);
+        let start = MediaTransferStart {
+            transfer_id: transfer_id("media-1", "v1", &sha256).to_string(),
+            media_id: "media-1".to_string(),
+            updated_at: "v1".to_string(),
+            extension: "../png".to_string(),
+            size: 10,
+            sha256,
+        };
+
+        // No staging directory configured
+        assert!(matches!(
+            incoming.start(start.clone()).await,
+            ChunkOutcome::Status(MediaTransferStatus { state: TransferState::Failed, .. })
+        ));
+
+        incoming.set_dir(dir.clone());
+        let id = Uuid::parse_str(&start.transfer_id).unwrap();
+        assert!(matches!(incoming.start(start).await, ChunkOutcome::Status(_)));
+
+        // A chunk past the end of what was received asks the sender to go back, once
+        let ahead = ChunkFrame { transfer_id: id, offset: 5, payload: b"world" }.encode();
+        match deliver(&mut incoming, &[ahead.clone(), ahead]).await {
+            Some(ChunkOutcome::Status(status)) => assert_eq!((status.state, status.received), (TransferState::Ready, 0)),
+            other => panic!("Expected a ready status, got {:?}", other),
+        }
+
+        let whole = ChunkFrame { transfer_id: id, offset: 0, payload: b"helloworld" }.encode();
+        match deliver(&mut incoming, &[whole]).await {
+            Some(ChunkOutcome::Status(status)) => {
+                assert_eq!(status.state, TransferState::Failed);
+                assert_eq!(status.error.as_deref(), Some("SHA-256 mismatch"));
+            }
+            other => panic!("Expected a failed status, got {:?}", other),
+        }
+        assert!(!dir.join(format!("{}.part", id)).exists());
+
+        let _ = fs::remove_dir_all(&dir).await;
+    }
+}
@@ -1,7 +1,31 @@
+use crate::websocket::roles::Role;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Protocol version spoken by this build
+///
+/// Clients that send no `protocol_version` in their hello speak version 1,
+/// the protocol from before versioning. Bump this whenever a message changes
+/// shape, and regenerate `schema/ws-protocol.schema.json`.
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// Version assumed for clients that do not announce one
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional features this build supports
+pub const SUPPORTED_CAPABILITIES: [Capability; 7] = [
+    Capability::Transitions,
+    Capability::Video,
+    Capability::Precache,
+    Capability::BinaryTransfer,
+    Capability::ClockSync,
+    Capability::MsgPack,
+    Capability::Deflate,
+];
 
 /// WebSocket message types with tag-based deserialization
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", content = "data")]
 pub enum WsMessage {
     #[serde(rename = "lyrics")]
@@ -10,12 +34,324 @@ pub enum WsMessage {
     #[serde(rename = "slide")]
     Slide(SlideData),
 
+    /// Show a background image or video
+    #[serde(rename = "media")]
+    Media(MediaData),
+
+    /// Black out the screen entirely
+    #[serde(rename = "black")]
+    Black(ScreenData),
+
+    /// Hide the text but keep the background
+    #[serde(rename = "clear")]
+    Clear(ScreenData),
+
+    /// Show the church logo instead of the current content
+    #[serde(rename = "logo")]
+    Logo(LogoData),
+
+    /// Keep showing the current frame and ignore further updates until unfrozen
+    #[serde(rename = "freeze")]
+    Freeze(ScreenData),
+
+    /// Ask displays to download an event's media and songs ahead of time
+    #[serde(rename = "precache")]
+    Precache(PrecacheData),
+
+    /// Progress report from a display while it is precaching
+    #[serde(rename = "precache_status")]
+    PrecacheStatus(PrecacheReport),
+
+    /// Final report from a display once precaching has finished
+    #[serde(rename = "precache_ack")]
+    PrecacheAck(PrecacheReport),
+
     #[serde(rename = "ping")]
     Ping,
+
+    /// Sent by a client right after connecting to register which display it is
+    #[serde(rename = "hello")]
+    Hello(HelloData),
+
+    /// Reply to a versioned hello with what both ends agreed on
+    #[serde(rename = "welcome")]
+    Welcome(WelcomeData),
+
+    /// Sent by the server to a controller that just redeemed the pairing PIN
+    #[serde(rename = "paired")]
+    Paired(PairedData),
+
+    /// Clock offset measurement (see `clock`); either side may ask
+    #[serde(rename = "clock_sync")]
+    ClockSync(ClockSyncData),
+
+    /// Only receive broadcasts for this church and event (see `Room`)
+    #[serde(rename = "join")]
+    Join(Room),
+
+    /// Stop receiving broadcasts for a room joined earlier
+    #[serde(rename = "leave")]
+    Leave(Room),
+
+    /// Sent by a display once it has applied a sequenced message
+    #[serde(rename = "ack")]
+    Ack(AckData),
+
+    /// Announces a media file about to be streamed in binary chunks (see `transfer`)
+    #[serde(rename = "media_transfer_start")]
+    MediaTransferStart(MediaTransferStart),
+
+    /// Receiver's progress on a media transfer
+    #[serde(rename = "media_transfer_status")]
+    MediaTransferStatus(MediaTransferStatus),
+
+    /// Sent by the server when it rejects a client's message
+    #[serde(rename = "error")]
+    Error(ErrorData),
+}
+
+impl WsMessage {
+    /// The display this message is addressed to, if any.
+    /// Messages without a target are delivered to every client.
+    pub fn target_display_id(&self) -> Option<&str> {
+        match self {
+            WsMessage::Lyrics(data) => data.target_display_id.as_deref(),
+            WsMessage::Slide(data) => data.target_display_id.as_deref(),
+            WsMessage::Media(data) => data.target_display_id.as_deref(),
+            WsMessage::Black(data) | WsMessage::Clear(data) | WsMessage::Freeze(data) => {
+                data.target_display_id.as_deref()
+            }
+            WsMessage::Logo(data) => data.target_display_id.as_deref(),
+            WsMessage::Precache(data) => data.target_display_id.as_deref(),
+            WsMessage::PrecacheStatus(_) | WsMessage::PrecacheAck(_) => None,
+            WsMessage::Ping | WsMessage::Hello(_) | WsMessage::Welcome(_) => None,
+            WsMessage::Paired(_) | WsMessage::Ack(_) | WsMessage::ClockSync(_) => None,
+            WsMessage::Join(_) | WsMessage::Leave(_) => None,
+            WsMessage::MediaTransferStart(_) | WsMessage::MediaTransferStatus(_) => None,
+            WsMessage::Error(_) => None,
+        }
+    }
+
+    /// The event this message belongs to, if any
+    pub fn event_id(&self) -> Option<&str> {
+        match self {
+            WsMessage::Lyrics(data) => Some(&data.event_id),
+            WsMessage::Slide(data) => Some(&data.event_id),
+            WsMessage::Media(data) => Some(&data.event_id),
+            WsMessage::Black(data) | WsMessage::Clear(data) | WsMessage::Freeze(data) => Some(&data.event_id),
+            WsMessage::Logo(data) => Some(&data.event_id),
+            WsMessage::Precache(data) => Some(&data.event_id),
+            WsMessage::PrecacheStatus(report) | WsMessage::PrecacheAck(report) => Some(&report.event_id),
+            _ => None,
+        }
+    }
+
+    /// The room this message is broadcast to, for messages scoped to a church and event
+    pub fn room(&self) -> Option<Room> {
+        let (church_id, event_id) = match self {
+            WsMessage::Lyrics(data) => (&data.church_id, &data.event_id),
+            WsMessage::Slide(data) => (&data.church_id, &data.event_id),
+            WsMessage::Media(data) => (&data.church_id, &data.event_id),
+            WsMessage::Black(data) | WsMessage::Clear(data) | WsMessage::Freeze(data) => {
+                (&data.church_id, &data.event_id)
+            }
+            WsMessage::Logo(data) => (&data.church_id, &data.event_id),
+            WsMessage::Precache(data) => (&data.church_id, &data.event_id),
+            _ => return None,
+        };
+        Some(Room {
+            church_id: church_id.clone(),
+            event_id: event_id.clone(),
+        })
+    }
+
+    /// When the message should take effect, for messages that carry `execute_at`
+    pub fn execute_at_mut(&mut self) -> Option<&mut i64> {
+        match self {
+            WsMessage::Lyrics(data) => data.execute_at.as_mut(),
+            WsMessage::Slide(data) => data.execute_at.as_mut(),
+            WsMessage::Media(data) => data.execute_at.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// Whether this message changes what a display shows.
+    /// Only these are sequenced, acknowledged and replayed.
+    pub fn is_state_change(&self) -> bool {
+        matches!(
+            self,
+            WsMessage::Lyrics(_)
+                | WsMessage::Slide(_)
+                | WsMessage::Black(_)
+                | WsMessage::Clear(_)
+                | WsMessage::Logo(_)
+                | WsMessage::Freeze(_)
+        )
+    }
+}
+
+/// JSON Schema of every message as it is sent on the wire, published as
+/// `schema/ws-protocol.schema.json` for display builders
+pub fn protocol_schema() -> schemars::schema::RootSchema {
+    let mut schema = schemars::schema_for!(Envelope);
+    let metadata = schema.schema.metadata();
+    metadata.title = Some("Mobile Worship WebSocket message".to_string());
+    metadata.description = Some(format!("Protocol version {}", PROTOCOL_VERSION));
+    schema
+}
+
+/// A message as it is sent on the wire
+///
+/// State-changing messages sent by the server carry a `seq` next to `type` and
+/// `data` (`{"type":"slide","data":{...},"seq":42}`). Sequence numbers increase
+/// monotonically across the server's lifetime; displays acknowledge them with
+/// an `ack` message.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Envelope {
+    #[serde(flatten)]
+    pub message: WsMessage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+impl Envelope {
+    pub fn new(message: WsMessage, seq: Option<u64>) -> Self {
+        Self { message, seq }
+    }
+}
+
+/// Clock offset request or answer (Unix milliseconds)
+///
+/// A request only carries `origin`; the answer echoes it and adds when the
+/// request was received and the answer sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ClockSyncData {
+    pub origin: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receive: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transmit: Option<i64>,
+}
+
+/// Acknowledgement from a display
+///
+/// Acks are cumulative: acknowledging `seq` confirms every earlier message
+/// addressed to the display as well.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AckData {
+    pub seq: u64,
+}
+
+/// Registration frame sent by a client after the WebSocket handshake
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct HelloData {
+    /// Per-display UUID. Targeted messages are only routed to the connection
+    /// that registered this ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_id: Option<String>,
+    /// Device UUID (several displays may share one device)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    /// Role to take (see `roles`). Defaults to display when a display_id is
+    /// given, otherwise the connection keeps its role.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    /// Highest protocol version the client speaks. Absent for version 1
+    /// clients, which get no `welcome`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u32>,
+    /// Optional features the client supports
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<Capability>,
+}
+
+impl HelloData {
+    /// The protocol version and capabilities both this build and the client support
+    pub fn negotiate(&self) -> (u32, Vec<Capability>) {
+        let version = self.protocol_version.unwrap_or(LEGACY_PROTOCOL_VERSION).min(PROTOCOL_VERSION);
+        let capabilities = SUPPORTED_CAPABILITIES
+            .into_iter()
+            .filter(|capability| self.capabilities.contains(capability))
+            .collect();
+        (version, capabilities)
+    }
+}
+
+/// Optional protocol features, negotiated in the hello exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// Animated transitions between slides
+    Transitions,
+    /// Video backgrounds
+    Video,
+    /// `precache` requests and reports
+    Precache,
+    /// Media pushed as binary chunk frames (see `transfer`)
+    BinaryTransfer,
+    /// Answers `clock_sync` requests, so its `execute_at` times can be converted
+    ClockSync,
+    /// Accepts messages as MessagePack binary frames (see `codec`)
+    #[serde(rename = "msgpack")]
+    MsgPack,
+    /// Accepts deflate-compressed message frames (see `codec`)
+    Deflate,
+    /// A capability from a newer build; ignored
+    #[serde(other)]
+    #[schemars(skip)]
+    Unknown,
+}
+
+/// A church and event whose broadcasts a client receives
+///
+/// Clients that joined rooms only receive broadcasts scoped to one of them
+/// (see `WsMessage::room`); clients that joined none receive every broadcast,
+/// as before rooms existed. Messages addressed to a display reach it
+/// regardless of its rooms.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct Room {
+    pub church_id: String,
+    pub event_id: String,
+}
+
+/// What the server and a client agreed on in the hello exchange
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct WelcomeData {
+    pub protocol_version: u32,
+    /// Capabilities both ends support
+    pub capabilities: Vec<Capability>,
+    /// The role the connection ended up with
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The frame is not a valid message
+    InvalidMessage,
+    /// The connection's role does not allow this message
+    Forbidden,
+}
+
+/// Why the server rejected a message
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorData {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// Credentials issued to a newly paired controller
+///
+/// The controller must store the token and present it (`?token=...`) on every
+/// future connection.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PairedData {
+    pub pair_id: String,
+    pub token: String,
 }
 
 /// Data for lyrics display updates
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct LyricsData {
     /// Target display ID. If None, broadcast to all displays.
     /// If Some, only the display with this ID should process the message.
@@ -29,10 +365,15 @@ pub struct LyricsData {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub background_url: Option<String>,
     pub timestamp: i64,
+    /// When displays should apply the update (Unix milliseconds on the
+    /// sender's clock; converted to the server's clock when relayed).
+    /// If None, displays apply it on arrival.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execute_at: Option<i64>,
 }
 
 /// Data for slide navigation updates
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SlideData {
     /// Target display ID. If None, broadcast to all displays.
     /// If Some, only the display with this ID should process the message.
@@ -43,6 +384,172 @@ pub struct SlideData {
     pub song_id: String,
     pub slide_index: usize,
     pub timestamp: i64,
+    /// When displays should apply the update (Unix milliseconds on the
+    /// sender's clock; converted to the server's clock when relayed).
+    /// If None, displays apply it on arrival.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execute_at: Option<i64>,
+}
+
+/// Data for background media changes
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MediaData {
+    /// Target display ID. If None, broadcast to all displays.
+    /// If Some, only the display with this ID should process the message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_display_id: Option<String>,
+    pub church_id: String,
+    pub event_id: String,
+    pub media_url: String,
+    /// "image" or "video"
+    pub media_type: String,
+    pub timestamp: i64,
+    /// When displays should apply the update (Unix milliseconds on the
+    /// sender's clock; converted to the server's clock when relayed).
+    /// If None, displays apply it on arrival.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execute_at: Option<i64>,
+}
+
+/// Data for screen-level commands (black, clear, freeze)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ScreenData {
+    /// Target display ID. If None, broadcast to all displays.
+    /// If Some, only the display with this ID should process the message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_display_id: Option<String>,
+    pub church_id: String,
+    pub event_id: String,
+    /// true to switch the state on, false to switch it off again
+    pub enabled: bool,
+    pub timestamp: i64,
+}
+
+/// Data for showing or hiding the logo
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LogoData {
+    /// Target display ID. If None, broadcast to all displays.
+    /// If Some, only the display with this ID should process the message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_display_id: Option<String>,
+    pub church_id: String,
+    pub event_id: String,
+    pub enabled: bool,
+    /// Logo image to show. If None, the display uses the church's default logo.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logo_url: Option<String>,
+    pub timestamp: i64,
+}
+
+/// Precache request (mirrors `PrecacheMessage` in `src/types/live.ts`)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PrecacheData {
+    /// Target display ID. If None, every display precaches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_display_id: Option<String>,
+    pub church_id: String,
+    pub event_id: String,
+    pub media: Vec<PrecacheMediaItem>,
+    pub songs: Vec<PrecacheSongItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PrecacheMediaItem {
+    pub media_id: String,
+    /// Signed Supabase URL
+    pub url: String,
+    /// "image" or "video"
+    #[serde(rename = "type")]
+    pub media_type: String,
+    /// Unix timestamp when the URL expires
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PrecacheSongItem {
+    pub song_id: String,
+    pub title: String,
+    pub lyrics: String,
+    /// Background key -> media ID
+    pub backgrounds: HashMap<String, String>,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PrecacheState {
+    Downloading,
+    Ready,
+    Error,
+}
+
+/// Cache status of one media item on a display
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PrecacheStatus {
+    pub media_id: String,
+    pub status: PrecacheState,
+    /// 0-100
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Precache progress or completion report (mirrors `PrecacheAck` in `src/types/live.ts`)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PrecacheReport {
+    pub event_id: String,
+    /// Whether the display considers itself ready (only meaningful in acks)
+    #[serde(default)]
+    pub ready: bool,
+    pub statuses: Vec<PrecacheStatus>,
+}
+
+/// Announcement of a media file sent over the WebSocket
+///
+/// The file follows as binary chunk frames tagged with `transfer_id`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MediaTransferStart {
+    /// UUID derived from media_id, updated_at and sha256 (see `transfer::transfer_id`)
+    pub transfer_id: String,
+    pub media_id: String,
+    /// Version of the media; cached files are keyed by media_id + updated_at
+    pub updated_at: String,
+    /// File extension used for the cached file (e.g. "jpg")
+    pub extension: String,
+    /// Total size in bytes
+    pub size: u64,
+    /// Hex-encoded SHA-256 of the whole file
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+    /// Send (again) starting at `received`
+    Ready,
+    /// Progress report
+    Receiving,
+    /// The file arrived and its SHA-256 matched
+    Complete,
+    /// The transfer was rejected or the file did not match its SHA-256
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MediaTransferStatus {
+    pub transfer_id: String,
+    pub media_id: String,
+    pub state: TransferState,
+    /// Bytes received so far
+    pub received: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 #[cfg(test)]
@@ -60,6 +567,7 @@ mod tests {
             lyrics: "# Verse 1\nAmazing grace...".to_string(),
             background_url: Some("https://example.com/bg.jpg".to_string()),
             timestamp: 1234567890,
+            execute_at: None,
         });
 
         let json = serde_json::to_string(&msg).unwrap();
@@ -93,6 +601,7 @@ mod tests {
             song_id: "song-789".to_string(),
             slide_index: 3,
             timestamp: 1234567890,
+            execute_at: None,
         });
 
         let json = serde_json::to_string(&msg).unwrap();
@@ -113,6 +622,7 @@ mod tests {
             lyrics: "# Verse 1\nAmazing grace...".to_string(),
             background_url: None,
             timestamp: 1234567890,
+            execute_at: None,
         });
 
         let json = serde_json::to_string(&msg).unwrap();
@@ -131,6 +641,7 @@ mod tests {
             lyrics: "Test lyrics".to_string(),
             background_url: None,
             timestamp: 1234567890,
+            execute_at: None,
         };
 
         let json = serde_json::to_string(&data).unwrap();
@@ -139,4 +650,195 @@ mod tests {
         // target_display_id should also be omitted when None
         assert!(!json.contains("target_display_id"));
     }
+
+    #[test]
+    fn test_deserialize_hello_message() {
+        let json = r#"{"type":"hello","data":{"display_id":"display-abc","device_id":"device-1"}}"#;
+
+        match serde_json::from_str::<WsMessage>(json).unwrap() {
+            WsMessage::Hello(data) => {
+                assert_eq!(data.display_id.as_deref(), Some("display-abc"));
+                assert_eq!(data.device_id.as_deref(), Some("device-1"));
+            }
+            _ => panic!("Expected Hello message"),
+        }
+
+        // Both fields are optional
+        let json = r#"{"type":"hello","data":{}}"#;
+        assert!(matches!(serde_json::from_str::<WsMessage>(json).unwrap(), WsMessage::Hello(_)));
+    }
+
+    #[test]
+    fn test_target_display_id() {
+        let msg = WsMessage::Slide(SlideData {
+            target_display_id: Some("display-xyz".to_string()),
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            slide_index: 0,
+            timestamp: 1234567890,
+            execute_at: None,
+        });
+        assert_eq!(msg.target_display_id(), Some("display-xyz"));
+        assert_eq!(WsMessage::Ping.target_display_id(), None);
+    }
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let envelope = Envelope::new(WsMessage::Slide(SlideData {
+            target_display_id: None,
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            song_id: "song-789".to_string(),
+            slide_index: 1,
+            timestamp: 1234567890,
+            execute_at: None,
+        }), Some(42));
+
+        let json = serde_json::to_string(&envelope).unwrap();
+        assert!(json.contains(r#""type":"slide""#));
+        assert!(json.contains(r#""seq":42"#));
+
+        // Displays that ignore `seq` still see a plain WsMessage
+        assert!(matches!(serde_json::from_str::<WsMessage>(&json).unwrap(), WsMessage::Slide(_)));
+
+        let parsed: Envelope = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.seq, Some(42));
+
+        let unsequenced: Envelope = serde_json::from_str(r#"{"type":"ping"}"#).unwrap();
+        assert!(unsequenced.seq.is_none());
+        assert!(matches!(unsequenced.message, WsMessage::Ping));
+    }
+
+    #[test]
+    fn test_deserialize_ack_message() {
+        let msg: WsMessage = serde_json::from_str(r#"{"type":"ack","data":{"seq":7}}"#).unwrap();
+        assert!(matches!(msg, WsMessage::Ack(AckData { seq: 7 })));
+        assert!(!msg.is_state_change());
+    }
+
+    #[test]
+    fn test_screen_commands() {
+        let black = WsMessage::Black(ScreenData {
+            target_display_id: Some("display-abc".to_string()),
+            church_id: "church-123".to_string(),
+            event_id: "event-456".to_string(),
+            enabled: true,
+            timestamp: 1234567890,
+        });
+        let json = serde_json::to_string(&black).unwrap();
+        assert!(json.contains(r#""type":"black""#));
+        assert!(json.contains(r#""enabled":true"#));
+        assert_eq!(black.target_display_id(), Some("display-abc"));
+        assert!(black.is_state_change());
+
+        let json = r#"{"type":"logo","data":{"church_id":"church-123","event_id":"event-456","enabled":true,"timestamp":1234567890}}"#;
+        match serde_json::from_str::<WsMessage>(json).unwrap() {
+            WsMessage::Logo(data) => {
+                assert!(data.enabled);
+                assert!(data.logo_url.is_none());
+                assert!(data.target_display_id.is_none());
+            }
+            other => panic!("Expected Logo message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_precache_matches_frontend_shape() {
+        let json = r#"{"type":"precache","data":{"churchId":"church-123","eventId":"event-456","media":[{"mediaId":"media-1","url":"https://example.com/a.jpg","type":"image","expiresAt":1234567890}],"songs":[{"songId":"song-789","title":"Amazing Grace","lyrics":"Verse 1","backgrounds":{"default":"media-1"},"updatedAt":"2024-01-01T00:00:00Z"}]}}"#;
+        match serde_json::from_str::<WsMessage>(json).unwrap() {
+            WsMessage::Precache(data) => {
+                assert_eq!(data.event_id, "event-456");
+                assert_eq!(data.media[0].media_type, "image");
+                assert_eq!(data.songs[0].backgrounds["default"], "media-1");
+            }
+            other => panic!("Expected Precache message, got {:?}", other),
+        }
+
+        let json = r#"{"type":"precache_ack","data":{"eventId":"event-456","ready":true,"statuses":[{"mediaId":"media-1","status":"ready","progress":100}]}}"#;
+        match serde_json::from_str::<WsMessage>(json).unwrap() {
+            WsMessage::PrecacheAck(report) => {
+                assert!(report.ready);
+                assert_eq!(report.statuses[0].status, PrecacheState::Ready);
+            }
+            other => panic!("Expected PrecacheAck message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_media_transfer_messages() {
+        let json = r#"{"type":"media_transfer_status","data":{"transfer_id":"t-1","media_id":"media-1","state":"ready","received":65536}}"#;
+        match serde_json::from_str::<WsMessage>(json).unwrap() {
+            WsMessage::MediaTransferStatus(status) => {
+                assert_eq!(status.state, TransferState::Ready);
+                assert_eq!(status.received, 65536);
+                assert!(status.error.is_none());
+            }
+            other => panic!("Expected MediaTransferStatus message, got {:?}", other),
+        }
+
+        let start = WsMessage::MediaTransferStart(MediaTransferStart {
+            transfer_id: "t-1".to_string(),
+            media_id: "media-1".to_string(),
+            updated_at: "2024-01-01T00:00:00Z".to_string(),
+            extension: "jpg".to_string(),
+            size: 1024,
+            sha256: "00".repeat(32),
+        });
+        let json = serde_json::to_string(&start).unwrap();
+        assert!(json.contains(r#""type":"media_transfer_start""#));
+        assert!(!start.is_state_change());
+        assert_eq!(start.target_display_id(), None);
+    }
+
+    #[test]
+    fn test_media_and_error_messages() {
+        let json = r#"{"type":"media","data":{"church_id":"church-123","event_id":"event-456","media_url":"https://example.com/bg.mp4","media_type":"video","timestamp":1234567890}}"#;
+        match serde_json::from_str::<WsMessage>(json).unwrap() {
+            WsMessage::Media(data) => assert_eq!(data.media_type, "video"),
+            other => panic!("Expected Media message, got {:?}", other),
+        }
+
+        let error = WsMessage::Error(ErrorData {
+            code: ErrorCode::Forbidden,
+            message: "Displays may not send slide".to_string(),
+        });
+        let json = serde_json::to_string(&error).unwrap();
+        assert!(json.contains(r#""type":"error""#));
+        assert!(json.contains(r#""code":"forbidden""#));
+
+        let json = r#"{"type":"hello","data":{"role":"observer"}}"#;
+        match serde_json::from_str::<WsMessage>(json).unwrap() {
+            WsMessage::Hello(hello) => assert_eq!(hello.role, Some(Role::Observer)),
+            other => panic!("Expected Hello message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_hello_negotiates_version_and_capabilities() {
+        // A newer client: higher version and a capability this build does not know
+        let json = r#"{"type":"hello","data":{"protocol_version":7,"capabilities":["video","holograms","binary_transfer"]}}"#;
+        let WsMessage::Hello(hello) = serde_json::from_str::<WsMessage>(json).unwrap() else {
+            panic!("Expected Hello message");
+        };
+        assert_eq!(hello.negotiate(), (PROTOCOL_VERSION, vec![Capability::Video, Capability::BinaryTransfer]));
+
+        // A client from before versioning
+        assert_eq!(HelloData::default().negotiate(), (LEGACY_PROTOCOL_VERSION, vec![]));
+    }
+
+    #[test]
+    fn test_published_schema_is_up_to_date() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("schema/ws-protocol.schema.json");
+        let schema = serde_json::to_string_pretty(&protocol_schema()).unwrap() + "\n";
+        if std::env::var_os("UPDATE_SCHEMA").is_some() {
+            std::fs::write(&path, &schema).unwrap();
+        }
+        let published = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            published == schema,
+            "{} is out of date; regenerate it with `UPDATE_SCHEMA=1 cargo test`",
+            path.display()
+        );
+    }
 }
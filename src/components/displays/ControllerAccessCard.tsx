@@ -0,0 +1,154 @@
+import { useCallback, useEffect, useState } from 'react'
+import { useTranslation } from 'react-i18next'
+import { isTauri, safeInvoke } from '@/lib/tauri'
+import { Button } from '@/components/ui/button'
+import { Label } from '@/components/ui/label'
+import { Switch } from '@/components/ui/switch'
+import { Card, CardContent, CardDescription, CardHeader, CardTitle } from '@/components/ui/card'
+import { KeyRound, ShieldAlert, Trash2 } from 'lucide-react'
+
+// Matches PairingSummary / PairingCode in src-tauri/src/websocket/pairing.rs
+interface PairingSummary {
+  pair_id: string
+  client_name: string
+  created_at: string
+  last_used_at: string | null
+}
+
+interface PairingCode {
+  pin: string
+  expires_at: string
+}
+
+/**
+ * Who may control the displays hosted on this device.
+ *
+ * Remote controllers must pair with a PIN shown here unless the operator
+ * turns pairing off, which lets anyone on the network publish.
+ */
+export function ControllerAccessCard() {
+  const { t } = useTranslation()
+  const [required, setRequired] = useState(true)
+  const [pairings, setPairings] = useState<PairingSummary[]>([])
+  const [code, setCode] = useState<PairingCode | null>(null)
+
+  const refresh = useCallback(async () => {
+    const [isRequired, list] = await Promise.all([
+      safeInvoke<boolean>('get_pairing_required'),
+      safeInvoke<PairingSummary[]>('list_pairings'),
+    ])
+    if (isRequired !== null) setRequired(isRequired)
+    if (list) setPairings(list)
+  }, [])
+
+  useEffect(() => {
+    if (!isTauri()) return
+
+    refresh()
+    let unlisten: (() => void) | null = null
+    import('@tauri-apps/api/event').then(({ listen }) => {
+      listen<PairingSummary[]>('pairings-changed', (event) => {
+        setPairings(event.payload)
+        // The PIN is single use; a new pairing means it was redeemed
+        setCode(null)
+      }).then((fn) => {
+        unlisten = fn
+      })
+    })
+    return () => {
+      if (unlisten) unlisten()
+    }
+  }, [refresh])
+
+  // Hide the PIN once it expires
+  useEffect(() => {
+    if (!code) return
+    const remaining = new Date(code.expires_at).getTime() - Date.now()
+    const timeout = setTimeout(() => setCode(null), Math.max(remaining, 0))
+    return () => clearTimeout(timeout)
+  }, [code])
+
+  const handleRequiredChange = async (value: boolean) => {
+    setRequired(value)
+    await safeInvoke('set_pairing_required', { required: value })
+    refresh()
+  }
+
+  const handleBeginPairing = async () => {
+    setCode(await safeInvoke<PairingCode>('begin_pairing'))
+  }
+
+  const handleCancelPairing = async () => {
+    await safeInvoke('cancel_pairing')
+    setCode(null)
+  }
+
+  const handleRevoke = async (pairId: string) => {
+    await safeInvoke('revoke_pairing', { pairId })
+    refresh()
+  }
+
+  if (!isTauri()) {
+    return null
+  }
+
+  return (
+    <Card className="mb-6">
+      <CardHeader>
+        <CardTitle className="text-lg flex items-center gap-2">
+          <KeyRound className="h-5 w-5" />
+          {t('displays.controllerAccess.title')}
+        </CardTitle>
+        <CardDescription>{t('displays.controllerAccess.description')}</CardDescription>
+      </CardHeader>
+      <CardContent className="space-y-4">
+        <div className="flex items-center justify-between gap-4">
+          <Label htmlFor="require-pairing">{t('displays.controllerAccess.requirePairing')}</Label>
+          <Switch id="require-pairing" checked={required} onCheckedChange={handleRequiredChange} />
+        </div>
+        {!required && (
+          <p className="flex items-center gap-2 text-sm text-destructive">
+            <ShieldAlert className="h-4 w-4 flex-shrink-0" />
+            {t('displays.controllerAccess.openWarning')}
+          </p>
+        )}
+
+        {code ? (
+          <div className="rounded-lg border p-4 text-center">
+            <p className="text-sm text-muted-foreground">{t('displays.controllerAccess.enterPin')}</p>
+            <p className="my-2 font-mono text-4xl font-bold tracking-widest">{code.pin}</p>
+            <Button variant="outline" size="sm" onClick={handleCancelPairing}>
+              {t('common.cancel')}
+            </Button>
+          </div>
+        ) : (
+          <Button onClick={handleBeginPairing}>{t('displays.controllerAccess.pairController')}</Button>
+        )}
+
+        {pairings.length > 0 && (
+          <div className="space-y-2">
+            <p className="text-sm font-medium">{t('displays.controllerAccess.paired')}</p>
+            {pairings.map((pairing) => (
+              <div key={pairing.pair_id} className="flex items-center justify-between rounded-md border px-3 py-2">
+                <div className="min-w-0">
+                  <div className="truncate">{pairing.client_name}</div>
+                  <div className="text-xs text-muted-foreground">
+                    {new Date(pairing.last_used_at ?? pairing.created_at).toLocaleString()}
+                  </div>
+                </div>
+                <Button
+                  variant="ghost"
+                  size="sm"
+                  onClick={() => handleRevoke(pairing.pair_id)}
+                  title={t('displays.controllerAccess.revoke')}
+                >
+                  <Trash2 className="h-4 w-4" />
+                </Button>
+              </div>
+            ))}
+          </div>
+        )}
+      </CardContent>
+    </Card>
+  )
+}
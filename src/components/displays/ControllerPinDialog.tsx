@@ -0,0 +1,65 @@
+import { useEffect, useState } from 'react'
+import { useTranslation } from 'react-i18next'
+import { useWebSocketConnections } from '@/contexts/WebSocketContext'
+import {
+  Dialog,
+  DialogContent,
+  DialogDescription,
+  DialogFooter,
+  DialogHeader,
+  DialogTitle,
+} from '@/components/ui/dialog'
+import { Button } from '@/components/ui/button'
+import { Input } from '@/components/ui/input'
+import { Label } from '@/components/ui/label'
+
+/**
+ * Asks for the PIN of a display host that turned this controller away,
+ * one host at a time. The PIN is shown under Controller Access on the
+ * Displays page of the device hosting the display.
+ */
+export function ControllerPinDialog() {
+  const { t } = useTranslation()
+  const { pairingRequired, pair, dismissPairing } = useWebSocketConnections()
+  const [pin, setPin] = useState('')
+
+  const [target] = Array.from(pairingRequired.values())
+
+  useEffect(() => {
+    setPin('')
+  }, [target?.key])
+
+  if (!target) {
+    return null
+  }
+
+  return (
+    <Dialog open onOpenChange={(open) => !open && dismissPairing(target.key)}>
+      <DialogContent>
+        <DialogHeader>
+          <DialogTitle>{t('displays.controllerPin.title', { name: target.name })}</DialogTitle>
+          <DialogDescription>{t('displays.controllerPin.description')}</DialogDescription>
+        </DialogHeader>
+        <div className="space-y-2">
+          <Label htmlFor="controller-pin">{t('displays.controllerPin.pinLabel')}</Label>
+          <Input
+            id="controller-pin"
+            inputMode="numeric"
+            maxLength={6}
+            value={pin}
+            onChange={(e) => setPin(e.target.value.replace(/\D/g, ''))}
+            autoFocus
+          />
+        </div>
+        <DialogFooter>
+          <Button variant="outline" onClick={() => dismissPairing(target.key)}>
+            {t('common.cancel')}
+          </Button>
+          <Button disabled={pin.length !== 6} onClick={() => pair(target.key, pin)}>
+            {t('displays.controllerPin.pair')}
+          </Button>
+        </DialogFooter>
+      </DialogContent>
+    </Dialog>
+  )
+}
@@ -89,11 +89,11 @@ export function EventCard({ event, itemCounts }: EventCardProps) {
         if (discoveredDisplay) {
           // Use live mDNS data - this has the current port
           console.log('[EventCard] Using mDNS discovery data for', display.name, 'host:', discoveredDisplay.host, 'port:', discoveredDisplay.port)
-          connect({ host: discoveredDisplay.host, port: discoveredDisplay.port, name: display.name })
+          connect({ host: discoveredDisplay.host, port: discoveredDisplay.port, name: display.name, deviceId: discoveredDisplay.deviceId })
         } else if (display.host && display.port) {
           // Fallback to database data (may have stale port)
           console.log('[EventCard] Using database data for', display.name, 'host:', display.host, 'port:', display.port, '(mDNS not found)')
-          connect({ host: display.host, port: display.port, name: display.name })
+          connect({ host: display.host, port: display.port, name: display.name, deviceId: display.deviceId })
         } else {
           console.warn('[EventCard] No connection info for display:', display.name)
         }
@@ -10,6 +10,47 @@ interface ConnectedDisplay {
   port: number
 }
 
+/** A display host that rejected our connection because we are not paired with it */
+interface PairingTarget {
+  key: string // host:port
+  name: string
+  host: string
+  port: number
+  deviceId?: string
+}
+
+type ConnectTarget = DiscoveredDisplay | { host: string; port: number; name: string; deviceId?: string }
+
+// Pairing tokens by display host (device ID, or host address for manual connections)
+const PAIRING_TOKENS_KEY = 'mw-pairing-tokens'
+
+function loadPairingTokens(): Record<string, string> {
+  try {
+    return JSON.parse(localStorage.getItem(PAIRING_TOKENS_KEY) || '{}')
+  } catch {
+    return {}
+  }
+}
+
+function setPairingToken(hostKey: string, token: string | null) {
+  const tokens = loadPairingTokens()
+  if (token) {
+    tokens[hostKey] = token
+  } else {
+    delete tokens[hostKey]
+  }
+  localStorage.setItem(PAIRING_TOKENS_KEY, JSON.stringify(tokens))
+}
+
+function pairingHostKey(display: ConnectTarget): string {
+  return display.deviceId || display.host
+}
+
+// Name the display host lists this controller under
+function controllerName(): string {
+  return `Mobile Worship (${navigator.platform || 'Controller'})`
+}
+
 interface LyricsMessage {
   church_id: string
   event_id: string
@@ -41,9 +82,12 @@ interface WebSocketContextValue {
   connected: Map<string, ConnectedDisplay>
   isDiscovering: boolean
   precacheAcks: Map<string, PrecacheAck> // key = displayKey
+  pairingRequired: Map<string, PairingTarget> // key = displayKey
   discover: () => Promise<DiscoveredDisplay[]>
-  connect: (display: DiscoveredDisplay | { host: string; port: number; name: string }) => void
+  connect: (display: ConnectTarget) => void
   disconnect: (key: string) => void
+  pair: (key: string, pin: string) => void
+  dismissPairing: (key: string) => void
   broadcastLyrics: (message: LyricsMessage) => void
   broadcastSlide: (message: SlideMessage) => void
   broadcastMedia: (message: MediaMessage) => void
@@ -58,6 +102,7 @@ export function WebSocketProvider({ children }: { children: ReactNode }) {
   const [connected, setConnected] = useState<Map<string, ConnectedDisplay>>(new Map())
   const [isDiscovering, setIsDiscovering] = useState(false)
   const [precacheAcks, setPrecacheAcks] = useState<Map<string, PrecacheAck>>(new Map())
+  const [pairingRequired, setPairingRequired] = useState<Map<string, PairingTarget>>(new Map())
   const wsRef = useRef<Map<string, WebSocket>>(new Map())
   const discoveredRef = useRef<DiscoveredDisplay[]>([])
 
@@ -120,7 +165,7 @@ export function WebSocketProvider({ children }: { children: ReactNode }) {
     }
   }, [])
 
-  const connect = useCallback((display: DiscoveredDisplay | { host: string; port: number; name: string }) => {
+  const connect = useCallback((display: ConnectTarget, pin?: string) => {
     const key = `${display.host}:${display.port}`
 
     if (wsRef.current.has(key)) {
@@ -128,10 +173,22 @@ export function WebSocketProvider({ children }: { children: ReactNode }) {
       return
     }
 
+    // Present our pairing token, or redeem the PIN the display host shows
+    const hostKey = pairingHostKey(display)
+    const token = loadPairingTokens()[hostKey]
+    let query = ''
+    if (pin) {
+      query = `?pin=${encodeURIComponent(pin)}&name=${encodeURIComponent(controllerName())}`
+    } else if (token) {
+      query = `?token=${encodeURIComponent(token)}`
+    }
+
     console.log('[WebSocketContext] Connecting to', key)
-    const ws = new WebSocket(`ws://${display.host}:${display.port}`)
+    const ws = new WebSocket(`ws://${display.host}:${display.port}/${query}`)
+    let opened = false
 
     ws.onopen = () => {
+      opened = true
       console.log('[WebSocketContext] Connected to', display.name)
       setConnected(prev => new Map(prev).set(key, { key, name: display.name, host: display.host, port: display.port }))
     }
@@ -139,7 +196,10 @@ export function WebSocketProvider({ children }: { children: ReactNode }) {
     ws.onmessage = (event) => {
       try {
         const message = JSON.parse(event.data)
-        if (message.type === 'precache_ack') {
+        if (message.type === 'paired') {
+          console.log('[WebSocketContext] Paired with', display.name)
+          setPairingToken(hostKey, message.data.token)
+        } else if (message.type === 'precache_ack') {
           console.log('[WebSocketContext] Received precache_ack from', display.name)
           setPrecacheAcks(prev => new Map(prev).set(key, message.data as PrecacheAck))
         }
@@ -160,11 +220,44 @@ export function WebSocketProvider({ children }: { children: ReactNode }) {
         return next
       })
       wsRef.current.delete(key)
+
+      if (!opened) {
+        // Browsers hide why a handshake failed. If the host answers plain
+        // HTTP it is up, so it turned us away: we need to pair (again).
+        fetch(`http://${display.host}:${display.port}/status`, { mode: 'no-cors' })
+          .then(() => {
+            console.log('[WebSocketContext] Pairing required by', display.name)
+            setPairingToken(hostKey, null)
+            setPairingRequired(prev => new Map(prev).set(key, {
+              key,
+              name: display.name,
+              host: display.host,
+              port: display.port,
+              deviceId: display.deviceId,
+            }))
+          })
+          .catch(() => console.warn('[WebSocketContext] Display unreachable:', display.name))
+      }
     }
 
     wsRef.current.set(key, ws)
   }, [])
 
+  const dismissPairing = useCallback((key: string) => {
+    setPairingRequired(prev => {
+      const next = new Map(prev)
+      next.delete(key)
+      return next
+    })
+  }, [])
+
+  const pair = useCallback((key: string, pin: string) => {
+    const target = pairingRequired.get(key)
+    if (!target) return
+    dismissPairing(key)
+    connect(target, pin)
+  }, [pairingRequired, connect, dismissPairing])
+
   const disconnect = useCallback((key: string) => {
     const ws = wsRef.current.get(key)
     if (ws) {
@@ -263,9 +356,12 @@ export function WebSocketProvider({ children }: { children: ReactNode }) {
     connected,
     isDiscovering,
     precacheAcks,
+    pairingRequired,
     discover,
     connect,
     disconnect,
+    pair,
+    dismissPairing,
     broadcastLyrics,
     broadcastSlide,
     broadcastMedia,
@@ -55,6 +55,7 @@ import {
 } from '@/components/ui/alert-dialog'
 import { Card, CardContent, CardDescription, CardHeader, CardTitle } from '@/components/ui/card'
 import { Badge } from '@/components/ui/badge'
+import { ControllerAccessCard } from '@/components/displays/ControllerAccessCard'
 import {
   Monitor,
   Plus,
@@ -329,6 +330,8 @@ export function DisplaysPage() {
         </Button>
       </div>
 
+      <ControllerAccessCard />
+
       {/* Discovered Displays Section */}
       {discoveredDisplays.length > 0 && (
         <Card className="mb-6">
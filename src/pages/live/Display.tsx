@@ -577,14 +577,21 @@ export function DisplayPage({ eventId }: DisplayPageProps) {
 
         ws.onopen = () => {
           console.log('[Display] Connected to local WebSocket server')
+          // Register, so messages targeted at this display are routed to us
+          ws!.send(JSON.stringify({ type: 'hello', data: { display_id: displayId, device_id: deviceId } }))
         }
 
         ws.onmessage = (event) => {
           console.log('[Display] Received WebSocket message:', event.data.substring(0, 200))
           try {
-            const message: WsMessage = JSON.parse(event.data)
+            const message: WsMessage & { seq?: number } = JSON.parse(event.data)
             console.log('[Display] Parsed message type:', message.type)
 
+            // Acknowledge sequenced updates so the controller sees we are current
+            if (typeof message.seq === 'number') {
+              ws!.send(JSON.stringify({ type: 'ack', data: { seq: message.seq } }))
+            }
+
             if (message.type === 'precache') {
               // Handle precache message - download and cache all media
               // Accept messages for any church since display is just a receiver
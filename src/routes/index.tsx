@@ -19,6 +19,7 @@ import { DisplayPage } from '@/pages/live/Display'
 import { ProtectedRoute } from '@/components/ProtectedRoute'
 import { AppLayout } from '@/components/AppLayout'
 import { AutoStartRedirect } from '@/components/AutoStartRedirect'
+import { ControllerPinDialog } from '@/components/displays/ControllerPinDialog'
 import { WebSocketProvider } from '@/contexts/WebSocketContext'
 
 // Layout wrapper that includes auto-redirect
@@ -142,6 +143,7 @@ export function AppRoutes() {
   return (
     <WebSocketProvider>
       <RouterProvider router={router} />
+      <ControllerPinDialog />
     </WebSocketProvider>
   )
 }